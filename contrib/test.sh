@@ -1,10 +1,15 @@
 #!/bin/sh -ex
 
-FEATURES="simple_http simple_tcp simple_uds proxy"
+FEATURES="simple_http simple_http_server simple_tcp simple_tcp_server simple_uds simple_uds_server proxy async testing log"
 
 cargo --version
 rustc --version
 
+# Later versions of log don't build with the minimum supported Rust version.
+if cargo --version | grep "1\.41"; then
+    cargo update -p log --precise 0.4.18
+fi
+
 # Some tests require certain toolchain types.
 NIGHTLY=false
 if cargo --version | grep nightly; then
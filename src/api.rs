@@ -0,0 +1,207 @@
+//! # Typed client interfaces
+//!
+//! Support for the [`rpc_api!`](crate::rpc_api) macro, which generates a trait with one typed
+//! method per RPC method, implemented for [`Client`].
+//!
+
+use serde;
+use serde_json::value::RawValue;
+
+use crate::client::Client;
+use crate::error::Error;
+
+/// Defines a trait with a typed method for each of the given RPC methods, and implements it for
+/// [`Client`](crate::Client).
+///
+/// Each method takes `&self` followed by the parameters of the call, which are serialized in
+/// order, and returns a `Result` of the given type, or `()` if none is given. The RPC method
+/// called has the name of the trait method.
+///
+/// Parameters serialized as `null`, such as `Option` parameters set to `None`, are omitted at the
+/// end of the parameter list, so that the server uses its defaults for them. Methods marked with
+/// `#[named]` pass their parameters by name instead, and omit all the `null` ones.
+///
+/// # Example
+///
+/// ```
+/// use serde_json::Value;
+///
+/// jsonrpc::rpc_api! {
+///     /// The bits of the Bitcoin Core API we use.
+///     pub trait BitcoinRpc {
+///         /// Returns the height of the most-work fully-validated chain.
+///         fn getblockcount() -> u64;
+///         fn getblock(hash: String, verbosity: Option<u8>) -> Value;
+///         #[named]
+///         fn getblockstats(hash_or_height: u64, stats: Option<Vec<String>>) -> Value;
+///         fn ping();
+///     }
+/// }
+///
+/// fn tip(client: &jsonrpc::Client) -> Result<Value, jsonrpc::Error> {
+///     let height = client.getblockcount()?;
+///     client.getblockstats(height, None)
+/// }
+/// ```
+#[macro_export]
+macro_rules! rpc_api {
+    ($(#[$attr:meta])* $vis:vis trait $trait:ident { $($methods:tt)* }) => {
+        $crate::rpc_api!(@parse [$(#[$attr])* $vis trait $trait] [] $($methods)*);
+    };
+
+    // Munches the methods one at a time, normalizing them into the second list.
+    (@parse $head:tt [$($done:tt)*]
+        $(#[doc = $doc:literal])*
+        #[named]
+        fn $name:ident($($param:ident: $ty:ty),* $(,)?) -> $ret:ty;
+        $($rest:tt)*
+    ) => {
+        $crate::rpc_api!(@parse $head
+            [$($done)* (by_name [$($doc)*] $name [$($param: $ty),*] $ret)]
+            $($rest)*);
+    };
+    (@parse $head:tt [$($done:tt)*]
+        $(#[doc = $doc:literal])*
+        #[named]
+        fn $name:ident($($param:ident: $ty:ty),* $(,)?);
+        $($rest:tt)*
+    ) => {
+        $crate::rpc_api!(@parse $head
+            [$($done)* (by_name [$($doc)*] $name [$($param: $ty),*] ())]
+            $($rest)*);
+    };
+    (@parse $head:tt [$($done:tt)*]
+        $(#[doc = $doc:literal])* fn $name:ident($($param:ident: $ty:ty),* $(,)?) -> $ret:ty;
+        $($rest:tt)*
+    ) => {
+        $crate::rpc_api!(@parse $head
+            [$($done)* (by_position [$($doc)*] $name [$($param: $ty),*] $ret)]
+            $($rest)*);
+    };
+    (@parse $head:tt [$($done:tt)*]
+        $(#[doc = $doc:literal])* fn $name:ident($($param:ident: $ty:ty),* $(,)?);
+        $($rest:tt)*
+    ) => {
+        $crate::rpc_api!(@parse $head
+            [$($done)* (by_position [$($doc)*] $name [$($param: $ty),*] ())]
+            $($rest)*);
+    };
+
+    // All methods are parsed, generates the trait and its implementation.
+    (@parse [$(#[$attr:meta])* $vis:vis trait $trait:ident]
+        [$(($mode:ident [$($doc:literal)*] $name:ident [$($param:ident: $ty:ty),*] $ret:ty))*]
+    ) => {
+        $(#[$attr])*
+        $vis trait $trait {
+            $(
+                $(#[doc = $doc])*
+                fn $name(&self, $($param: $ty),*) -> Result<$ret, $crate::Error>;
+            )*
+        }
+
+        impl $trait for $crate::Client {
+            $(
+                fn $name(&self, $($param: $ty),*) -> Result<$ret, $crate::Error> {
+                    $crate::rpc_api!(@call $mode self, $name, $($param),*)
+                }
+            )*
+        }
+    };
+
+    (@call by_position $client:expr, $name:ident, $($param:ident),*) => {
+        $crate::api::call_by_position(
+            $client,
+            stringify!($name),
+            vec![$($crate::try_arg(&$param)?),*],
+        )
+    };
+    (@call by_name $client:expr, $name:ident, $($param:ident),*) => {
+        $crate::api::call_by_name(
+            $client,
+            stringify!($name),
+            vec![$((stringify!($param), $crate::try_arg(&$param)?)),*],
+        )
+    };
+}
+
+/// Returns whether an argument is `null`, and can be omitted.
+fn is_null(arg: &RawValue) -> bool {
+    arg.get() == "null"
+}
+
+/// Calls `method` with the positional parameters `args`, leaving out trailing `null` ones.
+#[doc(hidden)]
+pub fn call_by_position<R: for<'a> serde::de::Deserialize<'a>>(
+    client: &Client,
+    method: &str,
+    mut args: Vec<Box<RawValue>>,
+) -> Result<R, Error> {
+    while args.last().map_or(false, |arg| is_null(arg)) {
+        args.pop();
+    }
+    client.call(method, &args)
+}
+
+/// Calls `method` with the named parameters `args`, leaving out `null` ones.
+#[doc(hidden)]
+pub fn call_by_name<R: for<'a> serde::de::Deserialize<'a>>(
+    client: &Client,
+    method: &str,
+    mut args: Vec<(&str, Box<RawValue>)>,
+) -> Result<R, Error> {
+    args.retain(|(_, arg)| !is_null(arg));
+    client.call(method, &args)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fmt;
+
+    use serde_json::{json, Value};
+
+    use crate::client::tests::EchoTransport;
+    use crate::client::Transport;
+    use crate::error::result_to_response;
+    use crate::{Client, Request, Response};
+
+    /// Replies to requests with their parameters, and to batches as [`EchoTransport`].
+    struct ParamsEcho;
+
+    impl Transport for ParamsEcho {
+        fn send_request(&self, req: Request) -> Result<Response, crate::Error> {
+            let params = serde_json::to_value(req.params)?;
+            Ok(result_to_response(Ok(params), req.id.unwrap_or_default()))
+        }
+        fn send_batch(&self, reqs: &[Request]) -> Result<Vec<Response>, crate::Error> {
+            EchoTransport.send_batch(reqs)
+        }
+        fn send_notification(&self, req: Request) -> Result<(), crate::Error> {
+            EchoTransport.send_notification(req)
+        }
+        fn fmt_target(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "params-echo")
+        }
+    }
+
+    rpc_api! {
+        /// An API for tests.
+        trait TestApi {
+            fn noparams() -> Value;
+            /// Has optional parameters.
+            fn positional(a: u32, b: Option<&str>, c: Option<bool>,) -> Value;
+            #[named]
+            fn named(a: u32, b: Option<&str>, c: Option<bool>) -> Value;
+        }
+    }
+
+    #[test]
+    fn generated_calls() {
+        let client = Client::with_transport(ParamsEcho);
+
+        assert_eq!(client.noparams().unwrap(), json!([]));
+        assert_eq!(client.positional(1, Some("x"), Some(true)).unwrap(), json!([1, "x", true]));
+        assert_eq!(client.positional(1, None, Some(true)).unwrap(), json!([1, null, true]));
+        assert_eq!(client.positional(1, None, None).unwrap(), json!([1]));
+        assert_eq!(client.named(1, None, Some(false)).unwrap(), json!({"a": 1, "c": false}));
+    }
+}
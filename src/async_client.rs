@@ -0,0 +1,327 @@
+//! # Asynchronous client support
+//!
+//! An asynchronous counterpart to [`crate::client`]. The [`AsyncTransport`] trait returns boxed
+//! futures and does not depend on any particular async runtime.
+//!
+//! The transports shipped with this crate implement [`AsyncTransport`] by running their blocking
+//! socket I/O on a bounded pool of helper threads, so that awaiting a call never blocks the
+//! executor.
+//!
+
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+
+use serde;
+
+use crate::client::{
+    check_batch_responses, check_response, match_batch_responses, ProtocolVersion, ResponseChecks,
+    Strictness,
+};
+use crate::error::Error;
+use crate::id::{IdGenerator, SequentialIds};
+use crate::{Params, Request, Response};
+
+/// An owned, type-erased future, as returned by [`AsyncTransport`] methods.
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// An interface for an asynchronous transport over which to use the JSONRPC protocol.
+pub trait AsyncTransport: Send + Sync + 'static {
+    /// Send an RPC request over the transport.
+    fn send_request<'a>(&'a self, _: Request<'a>) -> BoxFuture<'a, Result<Response, Error>>;
+    /// Send a batch of RPC requests over the transport.
+    ///
+    /// The batch may contain notifications, for which the server will not return a response. If
+    /// the batch only consists of notifications, no response is waited for.
+    fn send_batch<'a>(
+        &'a self,
+        _: &'a [Request<'a>],
+    ) -> BoxFuture<'a, Result<Vec<Response>, Error>>;
+    /// Send an RPC notification over the transport, without waiting for a response.
+    fn send_notification<'a>(&'a self, _: Request<'a>) -> BoxFuture<'a, Result<(), Error>>;
+    /// Format the target of this transport.
+    /// I.e. the URL/socket/...
+    fn fmt_target(&self, f: &mut fmt::Formatter) -> fmt::Result;
+}
+
+/// An asynchronous JSON-RPC client.
+///
+/// Create a new AsyncClient using one of the transport-specific constructors e.g.,
+/// [`AsyncClient::simple_http`] for a bare-minimum HTTP transport.
+pub struct AsyncClient {
+    pub(crate) transport: Box<dyn AsyncTransport>,
+    ids: Box<dyn IdGenerator>,
+    checks: ResponseChecks,
+}
+
+impl AsyncClient {
+    /// Creates a new client with the given transport.
+    ///
+    /// Requests are numbered sequentially, see [`AsyncClient::builder`] for more options.
+    pub fn with_transport<T: AsyncTransport>(transport: T) -> AsyncClient {
+        AsyncClient::builder(transport).build()
+    }
+
+    /// Returns a builder for a client with the given transport.
+    pub fn builder<T: AsyncTransport>(transport: T) -> Builder {
+        Builder::new(transport)
+    }
+
+    /// Builds a request.
+    ///
+    /// The parameters are passed as for [`crate::Client::build_request`].
+    pub fn build_request<'a, P: Into<Params<'a>>>(
+        &self,
+        method: &'a str,
+        params: P,
+    ) -> Request<'a> {
+        self.checks.version.request(method, params.into(), Some(self.ids.next_id()))
+    }
+
+    /// Builds a notification, i.e. a request without an `id`, or with a `null` one in
+    /// JSON-RPC 1.0.
+    ///
+    /// The parameters are passed as for [`crate::Client::build_request`].
+    pub fn build_notification<'a, P: Into<Params<'a>>>(
+        &self,
+        method: &'a str,
+        params: P,
+    ) -> Request<'a> {
+        self.checks.version.request(method, params.into(), None)
+    }
+
+    /// Sends a request to a client.
+    pub async fn send_request(&self, request: Request<'_>) -> Result<Response, Error> {
+        self.transport.send_request(request).await
+    }
+
+    /// Sends a notification to the client, without waiting for a response.
+    pub async fn send_notification(&self, request: Request<'_>) -> Result<(), Error> {
+        self.transport.send_notification(request).await
+    }
+
+    /// Sends a batch of requests to the client.
+    ///
+    /// This behaves exactly like [`crate::Client::send_batch`]: the return vector holds the
+    /// response for the request at the corresponding index, or [`None`] if no response was
+    /// provided.
+    pub async fn send_batch(
+        &self,
+        requests: &[Request<'_>],
+    ) -> Result<Vec<Option<Response>>, Error> {
+        if requests.is_empty() {
+            return Err(Error::EmptyBatch);
+        }
+
+        let responses = self.transport.send_batch(requests).await?;
+        let responses = match_batch_responses(requests, responses)?;
+        if self.checks.strictness == Strictness::Strict {
+            check_batch_responses(self.checks, requests, &responses)?;
+        }
+        Ok(responses)
+    }
+
+    /// Make a request and deserialize the response.
+    ///
+    /// The parameters are passed as for [`crate::Client::build_request`].
+    pub async fn call<'p, R: for<'a> serde::de::Deserialize<'a>>(
+        &self,
+        method: &str,
+        params: impl Into<Params<'p>>,
+    ) -> Result<R, Error> {
+        let request = self.build_request(method, params.into());
+        let id = request.id.clone();
+
+        let response = self.send_request(request).await?;
+        check_response(self.checks, id.as_ref(), &response)?;
+        response.result()
+    }
+
+    /// Sends a notification, i.e. a request to which the server does not reply.
+    ///
+    /// The parameters are passed as for [`crate::Client::build_request`].
+    pub async fn notify<'p>(
+        &self,
+        method: &str,
+        params: impl Into<Params<'p>>,
+    ) -> Result<(), Error> {
+        let request = self.build_notification(method, params.into());
+        self.send_notification(request).await
+    }
+}
+
+impl fmt::Debug for AsyncClient {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "jsonrpc::AsyncClient(")?;
+        self.transport.fmt_target(f)?;
+        write!(f, ")")
+    }
+}
+
+/// Builder for an [`AsyncClient`].
+pub struct Builder {
+    transport: Box<dyn AsyncTransport>,
+    ids: Box<dyn IdGenerator>,
+    checks: ResponseChecks,
+}
+
+impl Builder {
+    /// Constructs a new [`Builder`] for a client with the given transport.
+    pub fn new<T: AsyncTransport>(transport: T) -> Builder {
+        Builder {
+            transport: Box::new(transport),
+            ids: Box::new(SequentialIds::new()),
+            checks: ResponseChecks::default(),
+        }
+    }
+
+    /// Sets the strategy used to generate the IDs of requests.
+    pub fn id_generator<G: IdGenerator>(mut self, ids: G) -> Self {
+        self.ids = Box::new(ids);
+        self
+    }
+
+    /// Sets how closely responses are checked, as for [`crate::client::Builder::strictness`].
+    pub fn strictness(mut self, strictness: Strictness) -> Self {
+        self.checks.strictness = strictness;
+        self
+    }
+
+    /// Sets the version of the protocol spoken, as for
+    /// [`crate::client::Builder::protocol_version`].
+    pub fn protocol_version(mut self, version: ProtocolVersion) -> Self {
+        self.checks.version = version;
+        self
+    }
+
+    /// Builds the final [`AsyncClient`].
+    pub fn build(self) -> AsyncClient {
+        AsyncClient {
+            transport: self.transport,
+            ids: self.ids,
+            checks: self.checks,
+        }
+    }
+}
+
+impl fmt::Debug for Builder {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "jsonrpc::async_client::Builder(")?;
+        self.transport.fmt_target(f)?;
+        write!(f, ")")
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::task::{Context, Poll, RawWaker, RawWakerVTable, Waker};
+    use std::{mem, thread};
+
+    static WAKER_VTABLE: RawWakerVTable =
+        RawWakerVTable::new(clone_waker, wake, wake_by_ref, drop_waker);
+
+    // The data of the wakers built by `block_on` is an `Arc<thread::Thread>` turned into a raw
+    // pointer, which each waker owns a reference of.
+
+    fn clone_waker(data: *const ()) -> RawWaker {
+        let thread = unsafe { Arc::from_raw(data as *const thread::Thread) };
+        let clone = thread.clone();
+        mem::forget(thread);
+        RawWaker::new(Arc::into_raw(clone) as *const (), &WAKER_VTABLE)
+    }
+
+    fn wake(data: *const ()) {
+        let thread = unsafe { Arc::from_raw(data as *const thread::Thread) };
+        thread.unpark();
+    }
+
+    fn wake_by_ref(data: *const ()) {
+        let thread = unsafe { &*(data as *const thread::Thread) };
+        thread.unpark();
+    }
+
+    fn drop_waker(data: *const ()) {
+        drop(unsafe { Arc::from_raw(data as *const thread::Thread) });
+    }
+
+    /// Minimal executor driving a single future to completion on the current thread.
+    pub(crate) fn block_on<F: Future>(fut: F) -> F::Output {
+        let thread = Arc::new(thread::current());
+        let raw = RawWaker::new(Arc::into_raw(thread) as *const (), &WAKER_VTABLE);
+        let waker = unsafe { Waker::from_raw(raw) };
+        let mut cx = Context::from_waker(&waker);
+        let mut fut = Box::pin(fut);
+        loop {
+            if let Poll::Ready(output) = fut.as_mut().poll(&mut cx) {
+                return output;
+            }
+            thread::park();
+        }
+    }
+
+    /// Replies to every request with its own ID as result.
+    struct EchoTransport;
+    impl AsyncTransport for EchoTransport {
+        fn send_request<'a>(&'a self, req: Request<'a>) -> BoxFuture<'a, Result<Response, Error>> {
+            let id = req.id.unwrap_or_default();
+            Box::pin(async move { Ok(crate::error::result_to_response(Ok(id.clone()), id)) })
+        }
+        fn send_batch<'a>(
+            &'a self,
+            reqs: &'a [Request<'a>],
+        ) -> BoxFuture<'a, Result<Vec<Response>, Error>> {
+            Box::pin(async move {
+                Ok(reqs
+                    .iter()
+                    .rev()
+                    .filter_map(|r| r.id.clone())
+                    .map(|id| crate::error::result_to_response(Ok(id.clone()), id))
+                    .collect())
+            })
+        }
+        fn send_notification<'a>(&'a self, _: Request<'a>) -> BoxFuture<'a, Result<(), Error>> {
+            Box::pin(async { Ok(()) })
+        }
+        fn fmt_target(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "echo")
+        }
+    }
+
+    #[test]
+    fn async_call() {
+        let client = AsyncClient::with_transport(EchoTransport);
+        assert_eq!(block_on(client.call::<u64>("test", &[])).unwrap(), 1);
+        assert_eq!(block_on(client.call::<u64>("test", &[])).unwrap(), 2);
+        block_on(client.notify("test", &[])).unwrap();
+        assert_eq!(format!("{:?}", client), "jsonrpc::AsyncClient(echo)");
+    }
+
+    #[test]
+    fn async_custom_ids() {
+        let client = AsyncClient::builder(EchoTransport)
+            .id_generator(crate::id::PrefixedIds::new("test"))
+            .build();
+        assert_eq!(block_on(client.call::<String>("test", &[])).unwrap(), "test-1");
+    }
+
+    #[test]
+    fn async_batch() {
+        let client = AsyncClient::with_transport(EchoTransport);
+        let batch = [
+            client.build_request("test", &[]),
+            client.build_notification("test", &[]),
+            client.build_request("test", &[]),
+        ];
+        let responses = block_on(client.send_batch(&batch)).unwrap();
+        assert_eq!(responses[0].as_ref().unwrap().result::<u64>().unwrap(), 1);
+        assert!(responses[1].is_none());
+        assert_eq!(responses[2].as_ref().unwrap().result::<u64>().unwrap(), 2);
+
+        match block_on(client.send_batch(&[])) {
+            Err(Error::EmptyBatch) => {}
+            r => panic!("expected empty batch error, got {:?}", r),
+        }
+    }
+}
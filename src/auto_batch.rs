@@ -0,0 +1,287 @@
+//! # Automatic batching
+//!
+//! Support for coalescing the calls made concurrently through a [`Client`] into batches, so
+//! that many threads making small calls share round trips. Enable it with
+//! [`crate::client::Builder::auto_batch`].
+//!
+//! There is no background thread: the first caller to find no batch in preparation becomes
+//! the leader. It waits for the batching window to elapse, or for the batch to fill up, and
+//! sends the calls queued in the meantime with [`Client::send_batch`], handing every other
+//! caller its response.
+//!
+
+use std::collections::HashMap;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+use std::{error, fmt};
+
+use serde_json;
+use serde_json::value::RawValue;
+
+use crate::client::Client;
+use crate::{Params, Request, Response};
+
+/// Error that can occur while using automatic batching.
+#[derive(Debug)]
+pub enum Error {
+    /// The batch containing the call failed as a whole, e.g. because the connection dropped.
+    ///
+    /// The error is shared by all the calls of the batch.
+    BatchFailed(Arc<crate::Error>),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match *self {
+            Error::BatchFailed(ref e) => write!(f, "batch failed: {}", e),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            Error::BatchFailed(ref e) => Some(&**e),
+        }
+    }
+}
+
+impl From<Error> for crate::Error {
+    fn from(e: Error) -> crate::Error {
+        crate::Error::Transport(Box::new(e))
+    }
+}
+
+/// Parameters of a queued call, owned so that the leader can send them on behalf of the caller.
+enum OwnedParams {
+    None,
+    ByPosition(Vec<Box<RawValue>>),
+    ByName(Vec<(String, Box<RawValue>)>),
+}
+
+/// A call waiting to be sent.
+struct Pending {
+    ticket: u64,
+    method: String,
+    params: OwnedParams,
+    id: serde_json::Value,
+    jsonrpc: Option<String>,
+}
+
+impl Pending {
+    fn new(ticket: u64, request: Request) -> Pending {
+        let params = match request.params {
+            Params::None => OwnedParams::None,
+            Params::ByPosition(params) => OwnedParams::ByPosition(params.to_vec()),
+            Params::ByName(params) => OwnedParams::ByName(
+                params.iter().map(|&(name, ref value)| (name.to_owned(), value.clone())).collect(),
+            ),
+        };
+        Pending {
+            ticket,
+            method: request.method.to_owned(),
+            params,
+            id: request.id.unwrap_or_default(),
+            jsonrpc: request.jsonrpc.map(str::to_owned),
+        }
+    }
+}
+
+#[derive(Default)]
+struct State {
+    next_ticket: u64,
+    pending: Vec<Pending>,
+    /// Whether a leader is currently collecting or sending a batch.
+    leading: bool,
+    /// Results waiting to be picked up by their caller, by ticket.
+    done: HashMap<u64, Result<Response, crate::Error>>,
+}
+
+/// Coalesces calls into batches, see the [module docs](self).
+pub(crate) struct AutoBatcher {
+    window: Duration,
+    max_batch: usize,
+    state: Mutex<State>,
+    cond: Condvar,
+}
+
+impl AutoBatcher {
+    /// Creates a batcher waiting up to `window` for calls, sending at most `max_batch` calls
+    /// per batch.
+    pub(crate) fn new(window: Duration, max_batch: usize) -> AutoBatcher {
+        AutoBatcher {
+            window,
+            max_batch: max_batch.max(1),
+            state: Mutex::new(State::default()),
+            cond: Condvar::new(),
+        }
+    }
+
+    /// Queues the request and waits for its response, sending a batch if no one else is.
+    pub(crate) fn send(&self, client: &Client, request: Request) -> Result<Response, crate::Error> {
+        let mut state = self.state.lock().expect("poisoned mutex");
+        let ticket = state.next_ticket;
+        state.next_ticket += 1;
+        state.pending.push(Pending::new(ticket, request));
+        if state.pending.len() >= self.max_batch {
+            self.cond.notify_all();
+        }
+
+        loop {
+            if let Some(result) = state.done.remove(&ticket) {
+                return result;
+            }
+
+            if !state.leading && state.pending.iter().any(|p| p.ticket == ticket) {
+                state.leading = true;
+                let deadline = Instant::now() + self.window;
+                while state.pending.len() < self.max_batch {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        break;
+                    }
+                    state =
+                        self.cond.wait_timeout(state, deadline - now).expect("poisoned mutex").0;
+                }
+                let n = state.pending.len().min(self.max_batch);
+                let batch: Vec<Pending> = state.pending.drain(..n).collect();
+                drop(state);
+
+                let results = send_batch(client, &batch);
+
+                state = self.state.lock().expect("poisoned mutex");
+                state.leading = false;
+                state.done.extend(results);
+                self.cond.notify_all();
+                continue;
+            }
+
+            state = self.cond.wait(state).expect("poisoned mutex");
+        }
+    }
+}
+
+impl fmt::Debug for AutoBatcher {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("AutoBatcher")
+            .field("window", &self.window)
+            .field("max_batch", &self.max_batch)
+            .finish()
+    }
+}
+
+/// Sends the queued calls, returning the result of each of them by ticket.
+fn send_batch(client: &Client, batch: &[Pending]) -> Vec<(u64, Result<Response, crate::Error>)> {
+    // Named parameters are borrowed as `(&str, _)` pairs by requests.
+    let named: Vec<Vec<(&str, Box<RawValue>)>> = batch
+        .iter()
+        .map(|p| match p.params {
+            OwnedParams::ByName(ref params) => {
+                params.iter().map(|(name, value)| (name.as_str(), value.clone())).collect()
+            }
+            _ => vec![],
+        })
+        .collect();
+    let requests: Vec<Request> = batch
+        .iter()
+        .zip(&named)
+        .map(|(p, named)| Request {
+            method: &p.method,
+            params: match p.params {
+                OwnedParams::None => Params::None,
+                OwnedParams::ByPosition(ref params) => Params::ByPosition(params),
+                OwnedParams::ByName(_) => Params::ByName(named),
+            },
+            id: Some(p.id.clone()),
+            jsonrpc: p.jsonrpc.as_deref(),
+        })
+        .collect();
+
+    // A lone call is sent as is, and keeps its own error.
+    if let [ref pending] = *batch {
+        let request = requests.into_iter().next().expect("one request");
+        return vec![(pending.ticket, client.send_request(request))];
+    }
+
+    match client.send_batch(&requests) {
+        Ok(responses) => batch
+            .iter()
+            .zip(responses)
+            .map(|(p, response)| {
+                let result =
+                    response.ok_or_else(|| crate::Error::MissingBatchResponse(p.id.clone()));
+                (p.ticket, result)
+            })
+            .collect(),
+        Err(e) => {
+            let e = Arc::new(e);
+            batch.iter().map(|p| (p.ticket, Err(Error::BatchFailed(e.clone()).into()))).collect()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::Ordering;
+    use std::thread;
+
+    use super::*;
+    use crate::arg;
+    use crate::client::tests::Node;
+
+    /// Makes `n` concurrent calls, returning their results.
+    fn concurrent_calls(client: &Arc<Client>, n: usize) -> Vec<Result<u64, crate::Error>> {
+        let threads: Vec<_> = (0..n)
+            .map(|i| {
+                let client = client.clone();
+                thread::spawn(move || {
+                    let params = [("n", arg(i))];
+                    client.call::<u64>("getblockhash", &params)
+                })
+            })
+            .collect();
+        threads.into_iter().map(|t| t.join().unwrap()).collect()
+    }
+
+    #[test]
+    fn coalesces_calls() {
+        let tp = Arc::new(Node::default());
+        let client =
+            Arc::new(Client::builder(tp.clone()).auto_batch(Duration::from_secs(10), 4).build());
+
+        // The batch is sent as soon as it is full, well before the window elapsed.
+        let mut ids: Vec<u64> =
+            concurrent_calls(&client, 4).into_iter().map(Result::unwrap).collect();
+        ids.sort_unstable();
+        assert_eq!(ids, [1, 2, 3, 4]);
+        assert_eq!(tp.batches.load(Ordering::SeqCst), 1);
+        assert_eq!(tp.requests.load(Ordering::SeqCst), 0);
+
+        // A lone call is sent once the window elapsed, without a batch.
+        let client = Client::builder(tp.clone()).auto_batch(Duration::from_millis(10), 4).build();
+        assert_eq!(client.call::<u64>("getblockhash", &[]).unwrap(), 1);
+        assert_eq!(tp.requests.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn shared_batch_error() {
+        let tp = Arc::new(Node::default());
+        tp.down.store(true, Ordering::SeqCst);
+        let client =
+            Arc::new(Client::builder(tp.clone()).auto_batch(Duration::from_secs(10), 3).build());
+
+        for result in concurrent_calls(&client, 3) {
+            match result {
+                Err(crate::Error::Transport(e)) => match e.downcast_ref() {
+                    Some(Error::BatchFailed(e)) => match **e {
+                        crate::Error::Transport(_) => {}
+                        ref e => panic!("expected transport error, got {:?}", e),
+                    },
+                    _ => panic!("expected batch failure, got {:?}", e),
+                },
+                r => panic!("expected transport error, got {:?}", r),
+            }
+        }
+        assert_eq!(tp.batches.load(Ordering::SeqCst), 1);
+    }
+}
@@ -0,0 +1,335 @@
+//! # Load-balancing transport
+//!
+//! A [`Transport`] spreading calls over several equivalent endpoints, to scale read-heavy
+//! traffic horizontally. Each call is sent to a single endpoint, picked according to a
+//! [`Strategy`]; in particular a batch is never split, so that the responses can be matched to
+//! the requests as usual.
+//!
+//! Unlike [`crate::failover`], failed calls are not retried on another endpoint. Balancing
+//! over several failover transports (or the other way around) combines both behaviors.
+//!
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::{error, fmt};
+
+use crate::client::{ReadBody, Transport};
+use crate::{Request, Response};
+
+/// Error that can occur while using the balancing transport.
+#[derive(Debug)]
+pub enum Error {
+    /// The transport was built without any endpoint.
+    NoEndpoints,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match *self {
+            Error::NoEndpoints => f.write_str("no endpoints to send the request to"),
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+impl From<Error> for crate::Error {
+    fn from(e: Error) -> crate::Error {
+        crate::Error::Transport(Box::new(e))
+    }
+}
+
+/// How the endpoint of each call is picked.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Strategy {
+    /// Endpoints take turns, in order.
+    RoundRobin,
+    /// The endpoint with the fewest calls in progress is picked, taking turns on ties.
+    LeastInFlight,
+    /// Endpoints take turns in proportion to their weight, interleaving them as evenly as
+    /// possible.
+    Weighted,
+}
+
+impl Default for Strategy {
+    fn default() -> Self {
+        Strategy::RoundRobin
+    }
+}
+
+struct Endpoint {
+    transport: Box<dyn Transport>,
+    weight: u32,
+    in_flight: AtomicUsize,
+}
+
+/// Counts a call as in flight on an endpoint until dropped.
+struct InFlight<'a>(&'a Endpoint);
+
+impl<'a> InFlight<'a> {
+    fn new(endpoint: &'a Endpoint) -> Self {
+        endpoint.in_flight.fetch_add(1, Ordering::SeqCst);
+        InFlight(endpoint)
+    }
+}
+
+impl<'a> Drop for InFlight<'a> {
+    fn drop(&mut self) {
+        self.0.in_flight.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// A transport balancing calls between several endpoints, see the [module docs](self).
+pub struct BalancingTransport {
+    endpoints: Vec<Endpoint>,
+    strategy: Strategy,
+    /// Turn counter for [`Strategy::RoundRobin`] and [`Strategy::LeastInFlight`].
+    next: AtomicUsize,
+    /// Current weights of the endpoints for [`Strategy::Weighted`].
+    current_weights: Mutex<Vec<i64>>,
+}
+
+impl BalancingTransport {
+    /// Returns a builder for [`BalancingTransport`].
+    pub fn builder() -> Builder {
+        Builder::new()
+    }
+
+    /// Returns the number of calls in progress on each endpoint, in order.
+    pub fn in_flight(&self) -> Vec<usize> {
+        self.endpoints.iter().map(|e| e.in_flight.load(Ordering::SeqCst)).collect()
+    }
+
+    /// Picks the endpoint for the next call.
+    fn pick(&self) -> Result<&Endpoint, crate::Error> {
+        let n = self.endpoints.len();
+        if n == 0 {
+            return Err(Error::NoEndpoints.into());
+        }
+
+        let idx = match self.strategy {
+            Strategy::RoundRobin => self.next.fetch_add(1, Ordering::Relaxed) % n,
+            Strategy::LeastInFlight => {
+                let start = self.next.fetch_add(1, Ordering::Relaxed);
+                (0..n)
+                    .map(|i| (start + i) % n)
+                    .min_by_key(|&i| self.endpoints[i].in_flight.load(Ordering::SeqCst))
+                    .expect("at least one endpoint")
+            }
+            Strategy::Weighted => {
+                // Smooth weighted round-robin: every endpoint gains its weight, the one with
+                // the highest current weight is picked and loses the total weight.
+                let mut current = self.current_weights.lock().expect("poisoned mutex");
+                let mut total = 0;
+                let mut best = 0;
+                for (i, endpoint) in self.endpoints.iter().enumerate() {
+                    current[i] += i64::from(endpoint.weight);
+                    total += i64::from(endpoint.weight);
+                    if current[i] > current[best] {
+                        best = i;
+                    }
+                }
+                current[best] -= total;
+                best
+            }
+        };
+        Ok(&self.endpoints[idx])
+    }
+}
+
+impl Transport for BalancingTransport {
+    fn send_request(&self, req: Request) -> Result<Response, crate::Error> {
+        let endpoint = self.pick()?;
+        let _guard = InFlight::new(endpoint);
+        endpoint.transport.send_request(req)
+    }
+
+    fn send_request_streaming(
+        &self,
+        req: Request,
+        read_body: &mut ReadBody,
+    ) -> Result<(), crate::Error> {
+        let endpoint = self.pick()?;
+        let _guard = InFlight::new(endpoint);
+        endpoint.transport.send_request_streaming(req, read_body)
+    }
+
+    fn send_batch(&self, reqs: &[Request]) -> Result<Vec<Response>, crate::Error> {
+        let endpoint = self.pick()?;
+        let _guard = InFlight::new(endpoint);
+        endpoint.transport.send_batch(reqs)
+    }
+
+    fn send_notification(&self, req: Request) -> Result<(), crate::Error> {
+        let endpoint = self.pick()?;
+        let _guard = InFlight::new(endpoint);
+        endpoint.transport.send_notification(req)
+    }
+
+    fn fmt_target(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "balance(")?;
+        for (i, endpoint) in self.endpoints.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            endpoint.transport.fmt_target(f)?;
+        }
+        write!(f, ")")
+    }
+}
+
+impl fmt::Debug for BalancingTransport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "jsonrpc::balance::BalancingTransport(")?;
+        self.fmt_target(f)?;
+        write!(f, ")")
+    }
+}
+
+/// Builder for [`BalancingTransport`].
+pub struct Builder {
+    tp: BalancingTransport,
+}
+
+impl Builder {
+    /// Constructs a new [`Builder`] without endpoints, using [`Strategy::RoundRobin`].
+    pub fn new() -> Builder {
+        Builder {
+            tp: BalancingTransport {
+                endpoints: vec![],
+                strategy: Strategy::default(),
+                next: AtomicUsize::new(0),
+                current_weights: Mutex::new(vec![]),
+            },
+        }
+    }
+
+    /// Sets the strategy picking the endpoint of each call.
+    pub fn strategy(mut self, strategy: Strategy) -> Self {
+        self.tp.strategy = strategy;
+        self
+    }
+
+    /// Adds an endpoint with a weight of 1.
+    pub fn endpoint<T: Transport>(self, transport: T) -> Self {
+        self.weighted_endpoint(transport, 1)
+    }
+
+    /// Adds an endpoint with the given weight.
+    ///
+    /// Weights are only used by [`Strategy::Weighted`], and are at least 1.
+    pub fn weighted_endpoint<T: Transport>(mut self, transport: T, weight: u32) -> Self {
+        self.tp.endpoints.push(Endpoint {
+            transport: Box::new(transport),
+            weight: weight.max(1),
+            in_flight: AtomicUsize::new(0),
+        });
+        self
+    }
+
+    /// Builds the final [`BalancingTransport`].
+    pub fn build(mut self) -> BalancingTransport {
+        self.tp.current_weights = Mutex::new(vec![0; self.tp.endpoints.len()]);
+        self.tp
+    }
+}
+
+impl Default for Builder {
+    fn default() -> Self {
+        Builder::new()
+    }
+}
+
+impl fmt::Debug for Builder {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "jsonrpc::balance::Builder(")?;
+        self.tp.fmt_target(f)?;
+        write!(f, ")")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc;
+    use std::sync::Arc;
+    use std::thread;
+
+    use super::*;
+    use crate::client::tests::Node;
+    use crate::Client;
+
+    fn names(client: &Client, calls: usize) -> String {
+        (0..calls).map(|_| client.call::<String>("test", &[]).unwrap()).collect()
+    }
+
+    #[test]
+    fn round_robin() {
+        let tp = BalancingTransport::builder()
+            .endpoint(Node::named("a"))
+            .endpoint(Node::named("b"))
+            .endpoint(Node::named("c"))
+            .build();
+        let client = Client::with_transport(tp);
+        assert_eq!(format!("{:?}", client), "jsonrpc::Client(balance(a, b, c))");
+        assert_eq!(names(&client, 7), "abcabca");
+
+        // Batches and notifications take a turn too.
+        let batch = [client.build_request("test", &[]), client.build_request("test", &[])];
+        assert_eq!(client.send_batch(&batch).unwrap().len(), 2);
+        client.notify("test", &[]).unwrap();
+        assert_eq!(names(&client, 2), "ab");
+        assert_eq!(client.call_streaming::<String>("test", &[]).unwrap(), "c");
+
+        match Client::with_transport(BalancingTransport::builder().build()).notify("test", &[]) {
+            Err(crate::Error::Transport(e)) => {
+                assert_eq!(e.to_string(), "no endpoints to send the request to")
+            }
+            r => panic!("expected transport error, got {:?}", r),
+        }
+    }
+
+    #[test]
+    fn weighted() {
+        let tp = BalancingTransport::builder()
+            .strategy(Strategy::Weighted)
+            .weighted_endpoint(Node::named("a"), 5)
+            .weighted_endpoint(Node::named("b"), 1)
+            .weighted_endpoint(Node::named("c"), 1)
+            .build();
+        let client = Client::with_transport(tp);
+        assert_eq!(names(&client, 14), "aabacaaaabacaa");
+    }
+
+    #[test]
+    fn least_in_flight() {
+        let (tx, rx) = mpsc::channel();
+        let slow = Node {
+            gate: Some(Mutex::new(rx)),
+            ..Node::named("slow")
+        };
+        let tp = Arc::new(
+            BalancingTransport::builder()
+                .strategy(Strategy::LeastInFlight)
+                .endpoint(slow)
+                .endpoint(Node::named("fast"))
+                .build(),
+        );
+        let client = Arc::new(Client::with_transport(tp.clone()));
+
+        // The first call goes to the slow endpoint and stays in flight.
+        let pending = {
+            let client = client.clone();
+            thread::spawn(move || client.call::<String>("test", &[]).unwrap())
+        };
+        while tp.in_flight() != [1, 0] {
+            thread::yield_now();
+        }
+
+        // Meanwhile, every call goes to the idle endpoint.
+        assert_eq!(names(&client, 3), "fastfastfast");
+
+        tx.send(()).unwrap();
+        assert_eq!(pending.join().unwrap(), "slow");
+        assert_eq!(tp.in_flight(), [0, 0]);
+    }
+}
@@ -0,0 +1,229 @@
+//! # Typed batches
+//!
+//! A [`BatchBuilder`] collects the calls of a batch, handing out a typed [`BatchHandle`] for
+//! each of them. Once the batch is sent, every handle extracts the result of its own call from
+//! the [`BatchResponses`], independently of the other calls.
+//!
+
+use std::fmt;
+use std::marker::PhantomData;
+
+use serde;
+use serde_json;
+
+use crate::client::{check_response, Client, ResponseChecks};
+use crate::error::Error;
+use crate::{Params, Request, Response};
+
+/// Collects the calls of a batch, see [`Client::batch`].
+pub struct BatchBuilder<'a> {
+    client: &'a Client,
+    requests: Vec<Request<'a>>,
+}
+
+impl<'a> BatchBuilder<'a> {
+    /// Creates an empty batch to be sent with the given client.
+    pub fn new(client: &'a Client) -> BatchBuilder<'a> {
+        BatchBuilder {
+            client,
+            requests: vec![],
+        }
+    }
+
+    /// Adds a call to the batch, returning the handle to its result.
+    ///
+    /// The parameters are passed as for [`Client::build_request`].
+    pub fn call<T, P>(&mut self, method: &'a str, params: P) -> BatchHandle<T>
+    where
+        T: for<'de> serde::de::Deserialize<'de>,
+        P: Into<Params<'a>>,
+    {
+        let request = self.client.build_request(method, params);
+        let handle = BatchHandle {
+            index: self.requests.len(),
+            id: request.id.clone().unwrap_or_default(),
+            result: PhantomData,
+        };
+        self.requests.push(request);
+        handle
+    }
+
+    /// Adds a notification to the batch.
+    ///
+    /// The parameters are passed as for [`Client::build_request`].
+    pub fn notify<P: Into<Params<'a>>>(&mut self, method: &'a str, params: P) {
+        let request = self.client.build_notification(method, params);
+        self.requests.push(request);
+    }
+
+    /// Returns the number of calls and notifications in the batch.
+    pub fn len(&self) -> usize {
+        self.requests.len()
+    }
+
+    /// Returns whether the batch is empty.
+    pub fn is_empty(&self) -> bool {
+        self.requests.is_empty()
+    }
+
+    /// Returns the requests of the batch.
+    pub fn requests(&self) -> &[Request<'a>] {
+        &self.requests
+    }
+
+    /// Sends the batch.
+    ///
+    /// An error is only returned if the batch as a whole failed; errors of individual calls are
+    /// returned by their handles.
+    pub fn send(self) -> Result<BatchResponses, Error> {
+        let responses = self.client.send_batch(&self.requests)?;
+        Ok(BatchResponses {
+            responses,
+            checks: self.client.checks(),
+        })
+    }
+}
+
+impl<'a> fmt::Debug for BatchBuilder<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("BatchBuilder").field("requests", &self.requests).finish()
+    }
+}
+
+/// The responses to a batch sent with [`BatchBuilder::send`].
+#[derive(Debug, Clone)]
+pub struct BatchResponses {
+    responses: Vec<Option<Response>>,
+    checks: ResponseChecks,
+}
+
+impl BatchResponses {
+    /// Returns the response to the request at the given index of the batch, if any.
+    pub fn response(&self, index: usize) -> Option<&Response> {
+        self.responses.get(index).and_then(Option::as_ref)
+    }
+
+    /// Returns the responses, in the same order as the requests of the batch.
+    pub fn into_inner(self) -> Vec<Option<Response>> {
+        self.responses
+    }
+}
+
+/// Handle to the result of a call added to a batch with [`BatchBuilder::call`].
+pub struct BatchHandle<T> {
+    index: usize,
+    id: serde_json::Value,
+    result: PhantomData<fn() -> T>,
+}
+
+impl<T> BatchHandle<T> {
+    /// Returns the ID of the request of this call.
+    pub fn id(&self) -> &serde_json::Value {
+        &self.id
+    }
+}
+
+impl<T: for<'de> serde::de::Deserialize<'de>> BatchHandle<T> {
+    /// Extracts the result of this call from the responses to its batch.
+    ///
+    /// Returns [`Error::MissingBatchResponse`] if the server did not reply to this call.
+    pub fn result(&self, responses: &BatchResponses) -> Result<T, Error> {
+        let response = match responses.response(self.index) {
+            Some(response) => response,
+            None => return Err(Error::MissingBatchResponse(self.id.clone())),
+        };
+        check_response(responses.checks, Some(&self.id), response)?;
+        response.result()
+    }
+}
+
+impl<T> fmt::Debug for BatchHandle<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("BatchHandle").field("index", &self.index).field("id", &self.id).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arg;
+    use crate::client::tests::EchoTransport;
+    use crate::client::Transport;
+    use crate::error::{result_to_response, standard_error, StandardError};
+
+    /// Fails the calls to `fail`, and doesn't reply to calls to `drop`.
+    struct Picky;
+    impl Transport for Picky {
+        fn send_request(&self, req: Request) -> Result<Response, Error> {
+            EchoTransport.send_request(req)
+        }
+        fn send_batch(&self, reqs: &[Request]) -> Result<Vec<Response>, Error> {
+            Ok(reqs
+                .iter()
+                .filter(|r| r.method != "drop")
+                .filter_map(|r| {
+                    let id = r.id.clone()?;
+                    Some(match r.method {
+                        "fail" => result_to_response(
+                            Err(standard_error(StandardError::MethodNotFound, None)),
+                            id,
+                        ),
+                        _ => result_to_response(Ok(serde_json::Value::from(r.method)), id),
+                    })
+                })
+                .collect())
+        }
+        fn send_notification(&self, _: Request) -> Result<(), Error> {
+            Ok(())
+        }
+        fn fmt_target(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "picky")
+        }
+    }
+
+    #[test]
+    fn typed_results() {
+        let client = Client::with_transport(EchoTransport);
+        let mut batch = client.batch();
+        let params = [arg(1)];
+        let first = batch.call::<u64, _>("getblockhash", &params);
+        batch.notify("ping", &[]);
+        let second = batch.call::<serde_json::Value, _>("getblockcount", &[]);
+        assert_eq!(batch.len(), 3);
+
+        let responses = batch.send().unwrap();
+        assert_eq!(first.result(&responses).unwrap(), 1);
+        assert_eq!(second.result(&responses).unwrap(), 2);
+        assert!(responses.response(1).is_none());
+    }
+
+    #[test]
+    fn independent_errors() {
+        let client = Client::with_transport(Picky);
+        let mut batch = client.batch();
+        let ok = batch.call::<String, _>("ok", &[]);
+        let failed = batch.call::<String, _>("fail", &[]);
+        let dropped = batch.call::<String, _>("drop", &[]);
+        let mistyped = batch.call::<u64, _>("ok", &[]);
+        let responses = batch.send().unwrap();
+
+        assert_eq!(ok.result(&responses).unwrap(), "ok");
+        match failed.result(&responses) {
+            Err(Error::Rpc(ref e)) if e.code == -32601 => {}
+            r => panic!("expected RPC error, got {:?}", r),
+        }
+        match dropped.result(&responses) {
+            Err(Error::MissingBatchResponse(ref id)) => assert_eq!(id, dropped.id()),
+            r => panic!("expected missing response, got {:?}", r),
+        }
+        match mistyped.result(&responses) {
+            Err(Error::Json(_)) => {}
+            r => panic!("expected JSON error, got {:?}", r),
+        }
+
+        match client.batch().send() {
+            Err(Error::EmptyBatch) => {}
+            r => panic!("expected empty batch error, got {:?}", r),
+        }
+    }
+}
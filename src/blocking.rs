@@ -0,0 +1,190 @@
+//! Helper threads running the blocking socket I/O of the asynchronous transports.
+//!
+//! Each thread polling futures gets a pool of helper threads, which grows as work comes in while
+//! more jobs are queued than its helpers are idle. Pools together grow up to [`MAX_HELPERS`]
+//! threads, except that each may always start one, so that its work makes progress.
+//!
+
+use std::cell::Cell;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::{panic, thread};
+
+/// The most helper threads alive at once, across all pools.
+const MAX_HELPERS: usize = 32;
+
+/// Number of helper threads alive, across all threads polling futures.
+static HELPERS: AtomicUsize = AtomicUsize::new(0);
+
+/// A unit of blocking work run by a helper thread.
+type Job = Box<dyn FnOnce() + Send>;
+
+/// The helper threads running the blocking work of futures polled on one thread.
+///
+/// Helper threads exit along with the thread owning the pool.
+struct Pool {
+    jobs: mpsc::Sender<Job>,
+    queue: Arc<Mutex<mpsc::Receiver<Job>>>,
+    /// Number of helper threads started by this pool.
+    workers: Cell<usize>,
+    /// Number of helper threads of this pool waiting for a job.
+    idle: Arc<AtomicUsize>,
+    /// Number of jobs queued but not yet taken by a helper thread.
+    pending: Arc<AtomicUsize>,
+}
+
+impl Pool {
+    fn new() -> Pool {
+        let (jobs, queue) = mpsc::channel();
+        Pool {
+            jobs,
+            queue: Arc::new(Mutex::new(queue)),
+            workers: Cell::new(0),
+            idle: Arc::new(AtomicUsize::new(0)),
+            pending: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// Queues `job`, starting a helper thread to run it if needed and allowed.
+    fn execute(&self, job: Job) {
+        // Helpers only stop being idle by taking a job, so checking before sending ensures the
+        // job isn't counted on the helper taking it.
+        let idle = self.idle.load(Ordering::SeqCst);
+        let pending = self.pending.fetch_add(1, Ordering::SeqCst) + 1;
+        // The pool holds the receiving end, so sending cannot fail.
+        let _ = self.jobs.send(job);
+        if pending <= idle {
+            return;
+        }
+        if self.workers.get() > 0 && HELPERS.load(Ordering::SeqCst) >= MAX_HELPERS {
+            return;
+        }
+
+        HELPERS.fetch_add(1, Ordering::SeqCst);
+        self.workers.set(self.workers.get() + 1);
+        let queue = self.queue.clone();
+        let idle = self.idle.clone();
+        let pending = self.pending.clone();
+        thread::spawn(move || {
+            loop {
+                idle.fetch_add(1, Ordering::SeqCst);
+                let job = queue.lock().expect("poisoned mutex").recv();
+                // Stop counting as idle before the job stops counting as pending, so that a busy
+                // helper is never counted on for a new job.
+                idle.fetch_sub(1, Ordering::SeqCst);
+                match job {
+                    Ok(job) => {
+                        pending.fetch_sub(1, Ordering::SeqCst);
+                        job()
+                    }
+                    // The owning thread exited.
+                    Err(_) => break,
+                }
+            }
+            HELPERS.fetch_sub(1, Ordering::SeqCst);
+        });
+    }
+}
+
+thread_local! {
+    static POOL: Pool = Pool::new();
+}
+
+/// State shared between a [`Blocking`] future and the helper thread computing its output.
+struct Shared<T> {
+    output: Option<thread::Result<T>>,
+    waker: Option<Waker>,
+}
+
+/// Future resolving to the output of a closure running on a helper thread.
+pub(crate) struct Blocking<T> {
+    shared: Arc<Mutex<Shared<T>>>,
+}
+
+/// Runs the blocking closure `f` on a helper thread and returns a future of its output.
+///
+/// The helper threads are pooled, so that closures may wait for a free one. If `f` panics, the
+/// panic is resumed when polling the future.
+pub(crate) fn spawn_blocking<T, F>(f: F) -> Blocking<T>
+where
+    T: Send + 'static,
+    F: FnOnce() -> T + Send + 'static,
+{
+    let shared = Arc::new(Mutex::new(Shared {
+        output: None,
+        waker: None,
+    }));
+
+    let job_shared = shared.clone();
+    POOL.with(|pool| {
+        pool.execute(Box::new(move || {
+            let output = panic::catch_unwind(panic::AssertUnwindSafe(f));
+            let mut shared = job_shared.lock().expect("poisoned mutex");
+            shared.output = Some(output);
+            if let Some(waker) = shared.waker.take() {
+                waker.wake();
+            }
+        }))
+    });
+
+    Blocking {
+        shared,
+    }
+}
+
+impl<T> Future for Blocking<T> {
+    type Output = T;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<T> {
+        let mut shared = self.shared.lock().expect("poisoned mutex");
+        match shared.output.take() {
+            Some(Ok(output)) => Poll::Ready(output),
+            Some(Err(payload)) => panic::resume_unwind(payload),
+            None => {
+                shared.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::async_client::tests::block_on;
+
+    #[test]
+    fn many_jobs() {
+        let jobs: Vec<_> = (0..3 * MAX_HELPERS).map(|i| spawn_blocking(move || i)).collect();
+        for (i, job) in jobs.into_iter().enumerate() {
+            assert_eq!(block_on(job), i);
+        }
+        POOL.with(|pool| assert!(pool.workers.get() <= MAX_HELPERS));
+    }
+
+    #[test]
+    fn concurrent_jobs() {
+        // Leave a helper idle, which must not be counted on for both jobs.
+        block_on(spawn_blocking(|| ()));
+        while POOL.with(|pool| pool.idle.load(Ordering::SeqCst)) == 0 {
+            thread::yield_now();
+        }
+        let (tx, rx) = mpsc::channel();
+        let waiting = spawn_blocking(move || rx.recv_timeout(Duration::from_secs(5)));
+        let sending = spawn_blocking(move || tx.send(()));
+        POOL.with(|pool| assert_eq!(pool.workers.get(), 2));
+        block_on(sending).unwrap();
+        block_on(waiting).unwrap();
+    }
+
+    #[test]
+    #[should_panic(expected = "oops")]
+    fn blocking_panic() {
+        block_on(spawn_blocking(|| panic!("oops")))
+    }
+}
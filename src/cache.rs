@@ -0,0 +1,489 @@
+//! # Response caching
+//!
+//! A [`Transport`] wrapper caching the responses to calls whose result never (or rarely)
+//! changes, e.g. `getblock <hash>`. Responses are cached by method name and parameters, and
+//! whether and for how long each method is cached is configured with a [`CachePolicy`].
+//!
+//! The cache holds a bounded number of responses, evicting the least recently used ones first.
+//! Only successful responses are cached.
+//!
+
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use serde_json;
+
+use crate::client::{ReadBody, Transport};
+use crate::error::Error;
+use crate::layer::Layer;
+use crate::stream::Tee;
+use crate::util::HashableValue;
+use crate::{Request, Response, ResponseBody};
+
+/// How long the responses to a method are cached.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CachePolicy {
+    /// Responses are not cached.
+    Never,
+    /// Responses are cached until evicted.
+    Forever,
+    /// Responses are cached for the given duration.
+    Ttl(Duration),
+}
+
+/// Configuration of a [`CachingTransport`].
+///
+/// The configuration is also a [`Layer`], so it can be applied to the transport of a client
+/// with [`crate::client::Builder::layer`]. Every transport it wraps gets its own cache.
+#[derive(Clone, Debug)]
+pub struct CacheConfig {
+    policies: HashMap<String, CachePolicy>,
+    default_policy: CachePolicy,
+    capacity: usize,
+}
+
+impl CacheConfig {
+    /// Creates a configuration holding up to 1024 responses, which doesn't cache any method
+    /// yet.
+    pub fn new() -> CacheConfig {
+        CacheConfig {
+            policies: HashMap::new(),
+            default_policy: CachePolicy::Never,
+            capacity: 1024,
+        }
+    }
+
+    /// Sets the policy of the given method.
+    pub fn policy<S: Into<String>>(mut self, method: S, policy: CachePolicy) -> Self {
+        self.policies.insert(method.into(), policy);
+        self
+    }
+
+    /// Sets the policy of the methods without a policy of their own.
+    pub fn default_policy(mut self, policy: CachePolicy) -> Self {
+        self.default_policy = policy;
+        self
+    }
+
+    /// Sets the maximum number of cached responses.
+    pub fn capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity;
+        self
+    }
+
+    /// Returns the policy of the given method.
+    pub fn policy_of(&self, method: &str) -> CachePolicy {
+        self.policies.get(method).copied().unwrap_or(self.default_policy)
+    }
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        CacheConfig::new()
+    }
+}
+
+impl<T: Transport> Layer<T> for CacheConfig {
+    type Transport = CachingTransport<T>;
+
+    fn layer(&self, inner: T) -> CachingTransport<T> {
+        CachingTransport::new(inner, self.clone())
+    }
+}
+
+/// Statistics of a [`CachingTransport`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    /// Number of calls answered from the cache.
+    pub hits: u64,
+    /// Number of cacheable calls which had to be sent, including those whose cached response
+    /// had expired.
+    pub misses: u64,
+    /// Number of responses evicted to make room for new ones.
+    pub evictions: u64,
+    /// Number of responses currently cached.
+    pub entries: usize,
+}
+
+/// Cache key: the method name and the parameters.
+type Key = (String, HashableValue<'static>);
+
+struct Entry {
+    response: Response,
+    expires: Option<Instant>,
+    /// When the entry was last used, as a key into [`Cache::lru`].
+    tick: u64,
+}
+
+#[derive(Default)]
+struct Cache {
+    entries: HashMap<Key, Entry>,
+    /// Cache keys ordered from least to most recently used.
+    lru: BTreeMap<u64, Key>,
+    next_tick: u64,
+    stats: CacheStats,
+}
+
+impl Cache {
+    /// Looks up a cached response, marking it as recently used.
+    fn get(&mut self, key: &Key) -> Option<Response> {
+        let expired = match self.entries.get(key) {
+            Some(entry) => entry.expires.map_or(false, |e| e <= Instant::now()),
+            None => {
+                self.stats.misses += 1;
+                return None;
+            }
+        };
+        if expired {
+            if let Some(entry) = self.entries.remove(key) {
+                self.lru.remove(&entry.tick);
+            }
+            self.stats.misses += 1;
+            return None;
+        }
+
+        let tick = self.next_tick;
+        self.next_tick += 1;
+        let entry = self.entries.get_mut(key).expect("checked above");
+        let key = self.lru.remove(&entry.tick).expect("entries are in the LRU list");
+        entry.tick = tick;
+        self.lru.insert(tick, key);
+        self.stats.hits += 1;
+        Some(entry.response.clone())
+    }
+
+    /// Caches a response, evicting the least recently used ones if the cache is full.
+    fn insert(&mut self, key: Key, response: Response, ttl: Option<Duration>, capacity: usize) {
+        if capacity == 0 {
+            return;
+        }
+        if let Some(old) = self.entries.remove(&key) {
+            self.lru.remove(&old.tick);
+        }
+        while self.entries.len() >= capacity {
+            let oldest = match self.lru.keys().next() {
+                Some(&tick) => tick,
+                None => break,
+            };
+            if let Some(key) = self.lru.remove(&oldest) {
+                self.entries.remove(&key);
+                self.stats.evictions += 1;
+            }
+        }
+
+        let tick = self.next_tick;
+        self.next_tick += 1;
+        self.lru.insert(tick, key.clone());
+        self.entries.insert(
+            key,
+            Entry {
+                response,
+                expires: ttl.map(|ttl| Instant::now() + ttl),
+                tick,
+            },
+        );
+    }
+}
+
+/// A transport caching responses, see the [module docs](self).
+pub struct CachingTransport<T> {
+    inner: T,
+    config: CacheConfig,
+    cache: Mutex<Cache>,
+}
+
+impl<T: Transport> CachingTransport<T> {
+    /// Wraps `inner` with an empty cache configured by `config`.
+    pub fn new(inner: T, config: CacheConfig) -> CachingTransport<T> {
+        CachingTransport {
+            inner,
+            config,
+            cache: Mutex::new(Cache::default()),
+        }
+    }
+
+    /// Returns a reference to the wrapped transport.
+    pub fn inner(&self) -> &T {
+        &self.inner
+    }
+
+    /// Returns the statistics of the cache.
+    pub fn stats(&self) -> CacheStats {
+        let cache = self.cache.lock().expect("poisoned mutex");
+        CacheStats {
+            entries: cache.entries.len(),
+            ..cache.stats
+        }
+    }
+
+    /// Empties the cache, keeping the statistics.
+    pub fn clear(&self) {
+        let mut cache = self.cache.lock().expect("poisoned mutex");
+        cache.entries.clear();
+        cache.lru.clear();
+    }
+
+    /// Returns the cache key and time to live of the request, if its response may be cached.
+    fn cache_key(&self, req: &Request) -> Option<(Key, Option<Duration>)> {
+        if req.is_notification() {
+            return None;
+        }
+        let ttl = match self.config.policy_of(req.method) {
+            CachePolicy::Never => return None,
+            CachePolicy::Forever => None,
+            CachePolicy::Ttl(ttl) => Some(ttl),
+        };
+        // Parameters which can't be represented as a value are simply not cached.
+        let params = serde_json::to_value(req.params).ok()?;
+        Some(((req.method.to_owned(), HashableValue(Cow::Owned(params))), ttl))
+    }
+
+    fn lookup(&self, key: &Key) -> Option<Response> {
+        self.cache.lock().expect("poisoned mutex").get(key)
+    }
+
+    fn store(&self, key: Key, ttl: Option<Duration>, response: &Response) {
+        if response.error.is_some() {
+            return;
+        }
+        let mut cache = self.cache.lock().expect("poisoned mutex");
+        cache.insert(key, response.clone(), ttl, self.config.capacity);
+    }
+}
+
+/// Builds the response to the request with the given ID from a cached response.
+fn cached_response(id: Option<&serde_json::Value>, response: Response) -> Response {
+    Response {
+        id: id.cloned().unwrap_or_default(),
+        ..response
+    }
+}
+
+impl<T: Transport> Transport for CachingTransport<T> {
+    fn send_request(&self, req: Request) -> Result<Response, Error> {
+        let (key, ttl) = match self.cache_key(&req) {
+            Some(key) => key,
+            None => return self.inner.send_request(req),
+        };
+        if let Some(response) = self.lookup(&key) {
+            return Ok(cached_response(req.id.as_ref(), response));
+        }
+
+        let response = self.inner.send_request(req)?;
+        self.store(key, ttl, &response);
+        Ok(response)
+    }
+
+    fn send_request_streaming(&self, req: Request, read_body: &mut ReadBody) -> Result<(), Error> {
+        let (key, ttl) = match self.cache_key(&req) {
+            Some(key) => key,
+            None => return self.inner.send_request_streaming(req, read_body),
+        };
+        if let Some(response) = self.lookup(&key) {
+            let body =
+                serde_json::to_vec(&ResponseBody(&cached_response(req.id.as_ref(), response)))?;
+            return read_body(&mut &body[..]);
+        }
+
+        // Keep a copy of the body as it is read, to cache the response it holds.
+        let mut copy = vec![];
+        self.inner.send_request_streaming(req, &mut |body| {
+            let mut tee = Tee::new(body);
+            let result = read_body(&mut tee);
+            copy = tee.into_copy();
+            result
+        })?;
+        if let Ok(response) = serde_json::from_slice(&copy) {
+            self.store(key, ttl, &response);
+        }
+        Ok(())
+    }
+
+    fn send_batch(&self, reqs: &[Request]) -> Result<Vec<Response>, Error> {
+        let mut responses = vec![];
+        let mut misses = vec![];
+        let mut keys = HashMap::new();
+        for req in reqs {
+            match self.cache_key(req) {
+                Some((key, ttl)) => match self.lookup(&key) {
+                    Some(response) => responses.push(cached_response(req.id.as_ref(), response)),
+                    None => {
+                        if let Some(ref id) = req.id {
+                            keys.insert(HashableValue(Cow::Owned(id.clone())), (key, ttl));
+                        }
+                        misses.push(req.clone());
+                    }
+                },
+                None => misses.push(req.clone()),
+            }
+        }
+
+        // Only send the calls which were not answered from the cache.
+        if !misses.is_empty() {
+            for response in self.inner.send_batch(&misses)? {
+                if let Some((key, ttl)) =
+                    keys.remove(&HashableValue(Cow::Owned(response.id.clone())))
+                {
+                    self.store(key, ttl, &response);
+                }
+                responses.push(response);
+            }
+        }
+        Ok(responses)
+    }
+
+    fn send_notification(&self, req: Request) -> Result<(), Error> {
+        self.inner.send_notification(req)
+    }
+
+    fn fmt_target(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.inner.fmt_target(f)
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for CachingTransport<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("CachingTransport")
+            .field("inner", &self.inner)
+            .field("config", &self.config)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::Ordering;
+    use std::sync::Arc;
+    use std::thread;
+
+    use super::*;
+    use crate::arg;
+    use crate::client::tests::{Canned, Node};
+    use crate::Client;
+
+    fn config() -> CacheConfig {
+        CacheConfig::new()
+            .policy("getblock", CachePolicy::Forever)
+            .policy("getblockcount", CachePolicy::Ttl(Duration::from_millis(50)))
+    }
+
+    #[test]
+    fn policies() {
+        let tp = Arc::new(CachingTransport::new(Node::default(), config()));
+        let client = Client::with_transport(tp.clone());
+        let (a, b) = ([arg("a")], [arg("b")]);
+
+        assert_eq!(client.call::<u64>("getblock", &a).unwrap(), 1);
+        assert_eq!(client.call::<u64>("getblock", &b).unwrap(), 2);
+        // Cached responses are answered with the ID of the new request.
+        assert_eq!(client.call::<u64>("getblock", &a).unwrap(), 1);
+        assert_eq!(client.call::<u64>("getblock", &b).unwrap(), 2);
+
+        // Not cached.
+        assert_eq!(client.call::<u64>("getmempoolinfo", &[]).unwrap(), 5);
+        assert_eq!(client.call::<u64>("getmempoolinfo", &[]).unwrap(), 6);
+
+        assert_eq!(client.call::<u64>("getblockcount", &[]).unwrap(), 7);
+        assert_eq!(client.call::<u64>("getblockcount", &[]).unwrap(), 7);
+        thread::sleep(Duration::from_millis(60));
+        assert_eq!(client.call::<u64>("getblockcount", &[]).unwrap(), 9);
+
+        assert_eq!(
+            tp.stats(),
+            CacheStats {
+                hits: 3,
+                misses: 4,
+                evictions: 0,
+                entries: 3,
+            }
+        );
+        tp.clear();
+        assert_eq!(client.call::<u64>("getblock", &a).unwrap(), 10);
+    }
+
+    #[test]
+    fn streaming() {
+        let tp = Arc::new(CachingTransport::new(Node::default(), config()));
+        let client = Client::with_transport(tp.clone());
+        let a = [arg("a")];
+
+        // Streamed responses are cached, and cached responses are streamed.
+        assert_eq!(client.call_streaming::<u64>("getblock", &a).unwrap(), 1);
+        assert_eq!(client.call::<u64>("getblock", &a).unwrap(), 1);
+        assert_eq!(client.call_streaming::<u64>("getblock", &a).unwrap(), 1);
+        assert_eq!(client.call_streaming::<u64>("getmempoolinfo", &[]).unwrap(), 4);
+        assert_eq!(tp.inner().streamed.load(Ordering::SeqCst), 2);
+        assert_eq!(tp.stats().hits, 2);
+    }
+
+    #[test]
+    fn lru_eviction() {
+        let tp = Arc::new(CachingTransport::new(Node::default(), config().capacity(2)));
+        let client = Client::with_transport(tp.clone());
+        let (a, b, c) = ([arg("a")], [arg("b")], [arg("c")]);
+
+        assert_eq!(client.call::<u64>("getblock", &a).unwrap(), 1);
+        assert_eq!(client.call::<u64>("getblock", &b).unwrap(), 2);
+        // Using `a` makes `b` the least recently used response.
+        assert_eq!(client.call::<u64>("getblock", &a).unwrap(), 1);
+        assert_eq!(client.call::<u64>("getblock", &c).unwrap(), 4);
+        assert_eq!(client.call::<u64>("getblock", &a).unwrap(), 1);
+        assert_eq!(client.call::<u64>("getblock", &b).unwrap(), 6);
+
+        let stats = tp.stats();
+        assert_eq!((stats.evictions, stats.entries), (2, 2));
+    }
+
+    #[test]
+    fn batches() {
+        let tp = Arc::new(CachingTransport::new(Node::default(), config()));
+        let client = Client::with_transport(tp.clone());
+        let (a, b) = ([arg("a")], [arg("b")]);
+
+        assert_eq!(client.call::<u64>("getblock", &a).unwrap(), 1);
+        let batch = [
+            client.build_request("getblock", &a),
+            client.build_request("getblock", &b),
+            client.build_notification("getblock", &b),
+            client.build_request("getmempoolinfo", &[]),
+        ];
+        let responses = client.send_batch(&batch).unwrap();
+        let results: Vec<_> =
+            responses.iter().map(|r| r.as_ref().map(|r| r.result::<u64>().unwrap())).collect();
+        assert_eq!(results, [Some(1), Some(3), None, Some(4)]);
+        assert_eq!(tp.inner().requests.load(Ordering::SeqCst), 1);
+        assert_eq!(tp.inner().batches.load(Ordering::SeqCst), 1);
+
+        // The response to `b` was cached from the batch.
+        assert_eq!(client.call::<u64>("getblock", &b).unwrap(), 3);
+
+        // Errors are not cached.
+        let node = Node::default();
+        node.rpc_error.store(true, Ordering::SeqCst);
+        let tp = Arc::new(CachingTransport::new(node, config()));
+        let client = Client::with_transport(tp.clone());
+        for _ in 0..2 {
+            match client.call::<u64>("getblock", &a) {
+                Err(Error::Rpc(ref e)) if e.code == -32603 => {}
+                r => panic!("expected RPC error, got {:?}", r),
+            }
+        }
+        assert_eq!(tp.inner().requests.load(Ordering::SeqCst), 2);
+        assert_eq!(tp.stats().hits, 0);
+    }
+
+    #[test]
+    fn original_version() {
+        // JSON-RPC 1.0 servers reply without a `jsonrpc` member.
+        let tp = Canned(r#"{"result":1,"error":null,"id":1}"#);
+        let client = Client::with_transport(CachingTransport::new(tp, config()));
+        let a = [arg("a")];
+        for _ in 0..2 {
+            let response = client.send_request(client.build_request("getblock", &a)).unwrap();
+            assert_eq!(response.jsonrpc, None);
+        }
+    }
+}
@@ -0,0 +1,511 @@
+//! # Record and replay
+//!
+//! Support for testing code built on a [`crate::Client`] without a running server. A
+//! [`RecordingTransport`] wraps a real transport and writes every call it makes, with its
+//! outcome, to a *cassette*: a file with one JSON object per line. A [`ReplayTransport`] then
+//! serves the recorded outcomes from the cassette.
+//!
+//! Calls are replayed by matching their method and parameters; the `id` of requests is
+//! ignored, and the replayed responses get the `id` of the new requests. If the same call was
+//! recorded several times, its outcomes are replayed in order, the last one being repeated once
+//! they are used up.
+//!
+//! Errors other than RPC errors, which are part of the responses, are recorded as their message
+//! and replayed as [`Error::Recorded`] transport errors.
+//!
+
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::Path;
+use std::sync::Mutex;
+use std::{error, fmt};
+
+use serde::{Deserialize, Serialize};
+use serde_json;
+
+use crate::client::{ReadBody, Transport};
+use crate::stream::Tee;
+use crate::{Request, Response};
+
+/// Error that can occur while recording or replaying calls.
+#[derive(Debug)]
+pub enum Error {
+    /// Reading or writing the cassette failed.
+    Io(io::Error),
+    /// A line of the cassette is not a valid recording.
+    Json(serde_json::Error),
+    /// The cassette has no recording of the call, described by its method(s).
+    NoRecording(String),
+    /// The recorded call failed with this error.
+    Recorded(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match *self {
+            Error::Io(ref e) => write!(f, "cassette I/O error: {}", e),
+            Error::Json(ref e) => write!(f, "invalid cassette: {}", e),
+            Error::NoRecording(ref call) => write!(f, "no recording of call {}", call),
+            Error::Recorded(ref e) => write!(f, "recorded error: {}", e),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            Error::Io(ref e) => Some(e),
+            Error::Json(ref e) => Some(e),
+            Error::NoRecording(_) | Error::Recorded(_) => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(e: serde_json::Error) -> Self {
+        Error::Json(e)
+    }
+}
+
+impl From<Error> for crate::Error {
+    fn from(e: Error) -> crate::Error {
+        crate::Error::Transport(Box::new(e))
+    }
+}
+
+/// A recorded request.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Call {
+    method: String,
+    params: serde_json::Value,
+    /// Only used to match the responses of a batch to its requests.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    id: Option<serde_json::Value>,
+}
+
+impl Call {
+    fn new(req: &Request) -> Result<Call, Error> {
+        Ok(Call {
+            method: req.method.to_owned(),
+            params: serde_json::to_value(req.params)?,
+            id: req.id.clone(),
+        })
+    }
+
+    /// Returns whether the calls match, ignoring their IDs.
+    fn matches(&self, other: &Call) -> bool {
+        self.method == other.method
+            && self.params == other.params
+            && self.id.is_none() == other.id.is_none()
+    }
+}
+
+/// The outcome of a recorded call.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Outcome<T> {
+    Ok(T),
+    Err(String),
+}
+
+impl<T> Outcome<T> {
+    fn new(result: &Result<T, crate::Error>) -> Outcome<T>
+    where
+        T: Clone,
+    {
+        match *result {
+            Ok(ref t) => Outcome::Ok(t.clone()),
+            Err(ref e) => Outcome::error(e),
+        }
+    }
+
+    fn error(error: &crate::Error) -> Outcome<T> {
+        match *error {
+            // Unlike the catch-all, leaves out the "transport error: " prefix, which replaying the
+            // error adds back.
+            crate::Error::Transport(ref e) => Outcome::Err(e.to_string()),
+            ref e => Outcome::Err(e.to_string()),
+        }
+    }
+}
+
+/// A line of a cassette.
+///
+/// The enum is externally tagged: internally tagged enums can't hold raw JSON values.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Interaction {
+    Request {
+        request: Call,
+        response: Outcome<Response>,
+    },
+    Batch {
+        requests: Vec<Call>,
+        responses: Outcome<Vec<Response>>,
+    },
+    Notification {
+        request: Call,
+        outcome: Outcome<()>,
+    },
+}
+
+/// A transport recording the calls made through it to a cassette, see the
+/// [module docs](self).
+pub struct RecordingTransport<T> {
+    inner: T,
+    cassette: Mutex<Box<dyn Write + Send>>,
+}
+
+impl<T: Transport> RecordingTransport<T> {
+    /// Wraps `inner`, recording to a new cassette file at `path`.
+    ///
+    /// The file is truncated if it already exists.
+    pub fn create<P: AsRef<Path>>(inner: T, path: P) -> Result<RecordingTransport<T>, Error> {
+        Ok(RecordingTransport::new(inner, File::create(path)?))
+    }
+
+    /// Wraps `inner`, recording to the given writer.
+    pub fn new<W: Write + Send + 'static>(inner: T, cassette: W) -> RecordingTransport<T> {
+        RecordingTransport {
+            inner,
+            cassette: Mutex::new(Box::new(cassette)),
+        }
+    }
+
+    /// Returns a reference to the wrapped transport.
+    pub fn inner(&self) -> &T {
+        &self.inner
+    }
+
+    fn record(&self, interaction: &Interaction) -> Result<(), Error> {
+        let mut line = serde_json::to_vec(interaction)?;
+        line.push(b'\n');
+        let mut cassette = self.cassette.lock().expect("poisoned mutex");
+        cassette.write_all(&line)?;
+        cassette.flush()?;
+        Ok(())
+    }
+}
+
+impl<T: Transport> Transport for RecordingTransport<T> {
+    fn send_request(&self, req: Request) -> Result<Response, crate::Error> {
+        let request = Call::new(&req)?;
+        let result = self.inner.send_request(req);
+        self.record(&Interaction::Request {
+            request,
+            response: Outcome::new(&result),
+        })?;
+        result
+    }
+
+    fn send_request_streaming(
+        &self,
+        req: Request,
+        read_body: &mut ReadBody,
+    ) -> Result<(), crate::Error> {
+        let request = Call::new(&req)?;
+        // Keep a copy of the body as it is read, to record the response it holds.
+        let mut copy = vec![];
+        let result = self.inner.send_request_streaming(req, &mut |body| {
+            let mut tee = Tee::new(body);
+            let result = read_body(&mut tee);
+            copy = tee.into_copy();
+            result
+        });
+        // Reading the body fails on the RPC error of the response, which is recorded all the same.
+        let (response, result) = match (result, serde_json::from_slice::<Response>(&copy)) {
+            (result, Ok(response)) => (Outcome::Ok(response), result),
+            (Ok(()), Err(e)) => {
+                let e = crate::Error::from(e);
+                (Outcome::error(&e), Err(e))
+            }
+            (Err(e), Err(_)) => (Outcome::error(&e), Err(e)),
+        };
+        self.record(&Interaction::Request {
+            request,
+            response,
+        })?;
+        result
+    }
+
+    fn send_batch(&self, reqs: &[Request]) -> Result<Vec<Response>, crate::Error> {
+        let requests = reqs.iter().map(Call::new).collect::<Result<_, _>>()?;
+        let result = self.inner.send_batch(reqs);
+        self.record(&Interaction::Batch {
+            requests,
+            responses: Outcome::new(&result),
+        })?;
+        result
+    }
+
+    fn send_notification(&self, req: Request) -> Result<(), crate::Error> {
+        let request = Call::new(&req)?;
+        let result = self.inner.send_notification(req);
+        self.record(&Interaction::Notification {
+            request,
+            outcome: Outcome::new(&result),
+        })?;
+        result
+    }
+
+    fn fmt_target(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.inner.fmt_target(f)
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for RecordingTransport<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("RecordingTransport").field("inner", &self.inner).finish()
+    }
+}
+
+/// A recorded interaction, with the number of times it has been replayed.
+struct Recording {
+    interaction: Interaction,
+    replays: usize,
+}
+
+/// A transport replaying the calls recorded in a cassette, see the [module docs](self).
+pub struct ReplayTransport {
+    recordings: Mutex<Vec<Recording>>,
+}
+
+impl ReplayTransport {
+    /// Loads the cassette file at `path`.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<ReplayTransport, Error> {
+        ReplayTransport::from_reader(BufReader::new(File::open(path)?))
+    }
+
+    /// Loads a cassette from the given reader.
+    pub fn from_reader<R: BufRead>(cassette: R) -> Result<ReplayTransport, Error> {
+        let mut recordings = vec![];
+        for line in cassette.lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            recordings.push(Recording {
+                interaction: serde_json::from_str(&line)?,
+                replays: 0,
+            });
+        }
+        Ok(ReplayTransport {
+            recordings: Mutex::new(recordings),
+        })
+    }
+
+    /// Returns the number of recorded interactions which were never replayed.
+    pub fn unused(&self) -> usize {
+        self.recordings.lock().expect("poisoned mutex").iter().filter(|r| r.replays == 0).count()
+    }
+
+    /// Finds the interaction to replay: the first matching one not replayed yet, or else the
+    /// last matching one.
+    fn replay<R, F>(&self, find: F) -> Option<R>
+    where
+        F: Fn(&Interaction) -> Option<R>,
+    {
+        let mut recordings = self.recordings.lock().expect("poisoned mutex");
+        let mut last = None;
+        for (idx, recording) in recordings.iter().enumerate() {
+            if find(&recording.interaction).is_some() {
+                last = Some(idx);
+                if recording.replays == 0 {
+                    break;
+                }
+            }
+        }
+        let recording = &mut recordings[last?];
+        recording.replays += 1;
+        find(&recording.interaction)
+    }
+}
+
+/// Returns the outcome of a recorded call, rewriting the `id` of the response(s).
+fn replay_outcome<T>(outcome: &Outcome<T>, rewrite: impl FnOnce(T) -> T) -> Result<T, crate::Error>
+where
+    T: Clone,
+{
+    match *outcome {
+        Outcome::Ok(ref t) => Ok(rewrite(t.clone())),
+        Outcome::Err(ref e) => Err(Error::Recorded(e.clone()).into()),
+    }
+}
+
+impl Transport for ReplayTransport {
+    fn send_request(&self, req: Request) -> Result<Response, crate::Error> {
+        let call = Call::new(&req)?;
+        let outcome = self.replay(|i| match *i {
+            Interaction::Request {
+                ref request,
+                ref response,
+            } if request.matches(&call) => Some(response.clone()),
+            _ => None,
+        });
+        match outcome {
+            Some(outcome) => replay_outcome(&outcome, |mut response| {
+                response.id = req.id.clone().unwrap_or_default();
+                response
+            }),
+            None => Err(Error::NoRecording(call.method).into()),
+        }
+    }
+
+    fn send_batch(&self, reqs: &[Request]) -> Result<Vec<Response>, crate::Error> {
+        let calls = reqs.iter().map(Call::new).collect::<Result<Vec<_>, _>>()?;
+        let outcome = self.replay(|i| match *i {
+            Interaction::Batch {
+                ref requests,
+                ref responses,
+            } if requests.len() == calls.len()
+                && requests.iter().zip(&calls).all(|(a, b)| a.matches(b)) =>
+            {
+                Some((requests.clone(), responses.clone()))
+            }
+            _ => None,
+        });
+        match outcome {
+            Some((recorded, outcome)) => replay_outcome(&outcome, |responses| {
+                // Map the recorded IDs to the IDs of the new requests at the same position.
+                responses
+                    .into_iter()
+                    .map(|mut response| {
+                        let pos = recorded.iter().position(|c| c.id.as_ref() == Some(&response.id));
+                        if let Some(id) = pos.and_then(|pos| calls[pos].id.clone()) {
+                            response.id = id;
+                        }
+                        response
+                    })
+                    .collect()
+            }),
+            None => {
+                let methods: Vec<_> = calls.iter().map(|c| c.method.as_str()).collect();
+                Err(Error::NoRecording(format!("[{}]", methods.join(", "))).into())
+            }
+        }
+    }
+
+    fn send_notification(&self, req: Request) -> Result<(), crate::Error> {
+        let call = Call::new(&req)?;
+        let outcome = self.replay(|i| match *i {
+            Interaction::Notification {
+                ref request,
+                ref outcome,
+            } if request.matches(&call) => Some(outcome.clone()),
+            _ => None,
+        });
+        match outcome {
+            Some(outcome) => replay_outcome(&outcome, |()| ()),
+            None => Err(Error::NoRecording(call.method).into()),
+        }
+    }
+
+    fn fmt_target(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "replay")
+    }
+}
+
+impl fmt::Debug for ReplayTransport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let n = self.recordings.lock().expect("poisoned mutex").len();
+        write!(f, "jsonrpc::cassette::ReplayTransport({} recordings)", n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::arg;
+    use crate::client::tests::Node;
+    use crate::Client;
+
+    /// A writer whose output can be inspected after it was handed out.
+    #[derive(Clone, Default)]
+    struct SharedBuf(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuf {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Makes the same calls, whatever the transport.
+    fn session(client: &Client) -> Vec<String> {
+        let params = [arg("a")];
+        let named = [("verbose", arg(true))];
+        let batch =
+            [client.build_request("getblock", &params), client.build_request("getblock", &named)];
+        let responses = client.send_batch(&batch).unwrap();
+        vec![
+            format!("{:?}", client.call::<u64>("getblockcount", &[])),
+            format!("{:?}", client.call::<u64>("getblockcount", &[])),
+            format!("{:?}", client.call_streaming::<u64>("getblockcount", &params)),
+            format!("{:?}", client.call_streaming::<u64>("fail", &named).unwrap_err()),
+            format!("{}", client.call::<u64>("down", &[]).unwrap_err()),
+            format!("{:?}", client.notify("ping", &[])),
+            format!("{:?}", responses[0].as_ref().unwrap().result::<u64>()),
+            format!("{:?}", responses[1].as_ref().unwrap().result::<u64>()),
+        ]
+    }
+
+    #[test]
+    fn record_and_replay() {
+        let buf = SharedBuf::default();
+        let client = Client::with_transport(RecordingTransport::new(Node::default(), buf.clone()));
+        let recorded = session(&client);
+        assert_eq!(recorded[..3], ["Ok(3)", "Ok(4)", "Ok(5)"]);
+        assert_eq!(recorded[4], "transport error: connection refused");
+
+        let cassette = buf.0.lock().unwrap().clone();
+        assert_eq!(cassette.split(|&b| b == b'\n').filter(|l| !l.is_empty()).count(), 7);
+
+        // The IDs of the new client differ from the recorded ones.
+        let replay = Arc::new(ReplayTransport::from_reader(&cassette[..]).unwrap());
+        let client = Client::builder(replay.clone())
+            .id_generator(crate::id::SequentialIds::starting_at(1000))
+            .build();
+        let replayed = session(&client);
+        assert_eq!(replayed[..4], recorded[..4]);
+        assert_eq!(replayed[4], "transport error: recorded error: connection refused");
+        assert_eq!(replayed[5..], recorded[5..]);
+        assert_eq!(replay.unused(), 0);
+
+        // Repeated calls replay the last recording.
+        assert_eq!(client.call::<u64>("getblockcount", &[]).unwrap(), 4);
+        match client.call::<u64>("getblockhash", &[]) {
+            Err(crate::Error::Transport(e)) => {
+                assert_eq!(e.to_string(), "no recording of call getblockhash")
+            }
+            r => panic!("expected transport error, got {:?}", r),
+        }
+    }
+
+    #[test]
+    fn cassette_file() {
+        let path =
+            std::env::temp_dir().join(format!("jsonrpc-cassette-{}.jsonl", std::process::id()));
+        let client =
+            Client::with_transport(RecordingTransport::create(Node::default(), &path).unwrap());
+        assert_eq!(client.call::<u64>("getblockcount", &[]).unwrap(), 1);
+
+        let client = Client::with_transport(ReplayTransport::open(&path).unwrap());
+        assert_eq!(client.call::<u64>("getblockcount", &[]).unwrap(), 1);
+        std::fs::remove_file(&path).unwrap();
+
+        match ReplayTransport::from_reader(&b"{\"request\": {}}\n"[..]) {
+            Err(Error::Json(_)) => {}
+            r => panic!("expected invalid cassette, got {:?}", r),
+        }
+    }
+}
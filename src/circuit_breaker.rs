@@ -0,0 +1,369 @@
+//! # Circuit breaker
+//!
+//! A [`Transport`] wrapper which stops sending calls to an endpoint that keeps failing, so that
+//! callers fail fast with [`Error::CircuitOpen`] instead of each waiting for a timeout.
+//!
+//! The breaker starts *closed*, letting calls through. After a number of consecutive failures
+//! it *opens*, and rejects every call for a cool-down period. It then becomes *half-open*,
+//! letting a few trial calls through: if they succeed the breaker closes again, and if any of
+//! them fails it opens for another cool-down period.
+//!
+//! By default only transport errors count as failures; RPC errors show that the server is up.
+//!
+
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::client::{ReadBody, Transport};
+use crate::error::Error;
+use crate::layer::Layer;
+use crate::{Request, Response};
+
+/// The state of a circuit breaker.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CircuitState {
+    /// Calls go through.
+    Closed,
+    /// Calls are rejected until the cool-down period is over.
+    Open,
+    /// A limited number of trial calls go through to find out whether the endpoint is back.
+    HalfOpen,
+}
+
+/// Internal state of a [`CircuitBreakerTransport`].
+#[derive(Debug)]
+enum State {
+    Closed {
+        failures: usize,
+    },
+    Open {
+        since: Instant,
+    },
+    HalfOpen {
+        in_flight: usize,
+        successes: usize,
+    },
+}
+
+/// Configuration of a [`CircuitBreakerTransport`].
+///
+/// The configuration is also a [`Layer`], so it can be applied to the transport of a client
+/// with [`crate::client::Builder::layer`]. Every transport it wraps gets its own breaker.
+#[derive(Clone)]
+pub struct CircuitBreaker {
+    failure_threshold: usize,
+    cool_down: Duration,
+    half_open_calls: usize,
+    failure_if: Arc<dyn Fn(&Error) -> bool + Send + Sync>,
+}
+
+impl CircuitBreaker {
+    /// Creates a breaker with the default configuration: it opens after 5 consecutive transport
+    /// errors, stays open for 30s, and closes again after a single successful trial call.
+    pub fn new() -> CircuitBreaker {
+        CircuitBreaker {
+            failure_threshold: 5,
+            cool_down: Duration::from_secs(30),
+            half_open_calls: 1,
+            failure_if: Arc::new(|e| match *e {
+                Error::Transport(_) => true,
+                _ => false,
+            }),
+        }
+    }
+
+    /// Sets the number of consecutive failures after which the breaker opens.
+    pub fn failure_threshold(mut self, failures: usize) -> Self {
+        self.failure_threshold = failures.max(1);
+        self
+    }
+
+    /// Sets how long the breaker stays open before letting trial calls through.
+    pub fn cool_down(mut self, cool_down: Duration) -> Self {
+        self.cool_down = cool_down;
+        self
+    }
+
+    /// Sets the number of trial calls which must succeed while half-open to close the breaker,
+    /// which is also the number of trial calls allowed at the same time.
+    pub fn half_open_calls(mut self, calls: usize) -> Self {
+        self.half_open_calls = calls.max(1);
+        self
+    }
+
+    /// Sets the predicate deciding which errors count as failures, instead of transport errors.
+    pub fn failure_if<F>(mut self, failure_if: F) -> Self
+    where
+        F: Fn(&Error) -> bool + Send + Sync + 'static,
+    {
+        self.failure_if = Arc::new(failure_if);
+        self
+    }
+}
+
+impl Default for CircuitBreaker {
+    fn default() -> Self {
+        CircuitBreaker::new()
+    }
+}
+
+impl fmt::Debug for CircuitBreaker {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("CircuitBreaker")
+            .field("failure_threshold", &self.failure_threshold)
+            .field("cool_down", &self.cool_down)
+            .field("half_open_calls", &self.half_open_calls)
+            .finish()
+    }
+}
+
+impl<T: Transport> Layer<T> for CircuitBreaker {
+    type Transport = CircuitBreakerTransport<T>;
+
+    fn layer(&self, inner: T) -> CircuitBreakerTransport<T> {
+        CircuitBreakerTransport::new(inner, self.clone())
+    }
+}
+
+/// A transport guarded by a circuit breaker, see the [module docs](self).
+#[derive(Debug)]
+pub struct CircuitBreakerTransport<T> {
+    inner: T,
+    config: CircuitBreaker,
+    state: Mutex<State>,
+}
+
+impl<T: Transport> CircuitBreakerTransport<T> {
+    /// Wraps `inner` with a closed breaker configured by `config`.
+    pub fn new(inner: T, config: CircuitBreaker) -> CircuitBreakerTransport<T> {
+        CircuitBreakerTransport {
+            inner,
+            config,
+            state: Mutex::new(State::Closed {
+                failures: 0,
+            }),
+        }
+    }
+
+    /// Returns a reference to the wrapped transport.
+    pub fn inner(&self) -> &T {
+        &self.inner
+    }
+
+    /// Returns the current state of the breaker.
+    ///
+    /// An open breaker whose cool-down period is over is reported as open until the next call
+    /// goes through.
+    pub fn state(&self) -> CircuitState {
+        match *self.state.lock().expect("poisoned mutex") {
+            State::Closed {
+                ..
+            } => CircuitState::Closed,
+            State::Open {
+                ..
+            } => CircuitState::Open,
+            State::HalfOpen {
+                ..
+            } => CircuitState::HalfOpen,
+        }
+    }
+
+    /// Sends a call through the breaker, rejecting it if the breaker is open.
+    fn call<R, F>(&self, call: F) -> Result<R, Error>
+    where
+        F: FnOnce() -> Result<R, Error>,
+    {
+        self.acquire()?;
+        let result = call();
+        let failed = match result {
+            Err(ref e) => (self.config.failure_if)(e),
+            Ok(_) => false,
+        };
+        self.record(failed);
+        result
+    }
+
+    /// Checks whether a call may go through, moving from open to half-open if the cool-down
+    /// period is over.
+    fn acquire(&self) -> Result<(), Error> {
+        let mut state = self.state.lock().expect("poisoned mutex");
+        match *state {
+            State::Closed {
+                ..
+            } => Ok(()),
+            State::Open {
+                since,
+            } => {
+                if since.elapsed() < self.config.cool_down {
+                    return Err(Error::CircuitOpen);
+                }
+                *state = State::HalfOpen {
+                    in_flight: 1,
+                    successes: 0,
+                };
+                Ok(())
+            }
+            State::HalfOpen {
+                ref mut in_flight,
+                ..
+            } => {
+                if *in_flight >= self.config.half_open_calls {
+                    return Err(Error::CircuitOpen);
+                }
+                *in_flight += 1;
+                Ok(())
+            }
+        }
+    }
+
+    /// Updates the state with the outcome of a call.
+    fn record(&self, failed: bool) {
+        let mut state = self.state.lock().expect("poisoned mutex");
+        let open = State::Open {
+            since: Instant::now(),
+        };
+        match *state {
+            State::Closed {
+                ref mut failures,
+            } => {
+                if !failed {
+                    *failures = 0;
+                } else if *failures + 1 >= self.config.failure_threshold {
+                    *state = open;
+                } else {
+                    *failures += 1;
+                }
+            }
+            State::HalfOpen {
+                ref mut in_flight,
+                ref mut successes,
+            } => {
+                if failed {
+                    *state = open;
+                } else if *successes + 1 >= self.config.half_open_calls {
+                    *state = State::Closed {
+                        failures: 0,
+                    };
+                } else {
+                    *in_flight -= 1;
+                    *successes += 1;
+                }
+            }
+            // A call which was let through before the breaker opened.
+            State::Open {
+                ..
+            } => {}
+        }
+    }
+}
+
+impl<T: Transport> Transport for CircuitBreakerTransport<T> {
+    fn send_request(&self, req: Request) -> Result<Response, Error> {
+        self.call(|| self.inner.send_request(req))
+    }
+
+    fn send_request_streaming(&self, req: Request, read_body: &mut ReadBody) -> Result<(), Error> {
+        self.call(|| self.inner.send_request_streaming(req, read_body))
+    }
+
+    fn send_batch(&self, reqs: &[Request]) -> Result<Vec<Response>, Error> {
+        self.call(|| self.inner.send_batch(reqs))
+    }
+
+    fn send_notification(&self, req: Request) -> Result<(), Error> {
+        self.call(|| self.inner.send_notification(req))
+    }
+
+    fn fmt_target(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.inner.fmt_target(f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::Ordering;
+    use std::thread;
+
+    use super::*;
+    use crate::client::tests::Node;
+    use crate::Client;
+
+    #[test]
+    fn open_and_close() {
+        let node = Arc::new(Node::default());
+        let tp = Arc::new(CircuitBreakerTransport::new(
+            node.clone(),
+            CircuitBreaker::new().failure_threshold(2).cool_down(Duration::from_millis(50)),
+        ));
+        let client = Client::with_transport(tp.clone());
+
+        // RPC errors don't count as failures.
+        node.rpc_error.store(true, Ordering::SeqCst);
+        for _ in 0..3 {
+            assert!(client.call::<u64>("test", &[]).is_err());
+        }
+        assert_eq!(tp.state(), CircuitState::Closed);
+        node.rpc_error.store(false, Ordering::SeqCst);
+
+        node.down.store(true, Ordering::SeqCst);
+        assert!(client.call::<u64>("test", &[]).is_err());
+        assert_eq!(tp.state(), CircuitState::Closed);
+        assert!(client.call::<u64>("test", &[]).is_err());
+        assert_eq!(tp.state(), CircuitState::Open);
+
+        // Calls now fail fast without reaching the node.
+        match client.call::<u64>("test", &[]) {
+            Err(Error::CircuitOpen) => {}
+            r => panic!("expected open circuit, got {:?}", r),
+        }
+        match client.notify("test", &[]) {
+            Err(Error::CircuitOpen) => {}
+            r => panic!("expected open circuit, got {:?}", r),
+        }
+        match client.call_streaming::<u64>("test", &[]) {
+            Err(Error::CircuitOpen) => {}
+            r => panic!("expected open circuit, got {:?}", r),
+        }
+        assert_eq!(node.calls(), 5);
+
+        // A failed trial call opens the breaker again.
+        thread::sleep(Duration::from_millis(60));
+        assert!(client.call::<u64>("test", &[]).is_err());
+        assert_eq!(tp.state(), CircuitState::Open);
+        assert_eq!(node.calls(), 6);
+
+        // A successful one closes it.
+        node.down.store(false, Ordering::SeqCst);
+        thread::sleep(Duration::from_millis(60));
+        let batch = [client.build_request("test", &[])];
+        assert_eq!(client.send_batch(&batch).unwrap().len(), 1);
+        assert_eq!(tp.state(), CircuitState::Closed);
+        assert!(client.call::<u64>("test", &[]).is_ok());
+    }
+
+    #[test]
+    fn half_open_calls() {
+        let node = Arc::new(Node::default());
+        let config = CircuitBreaker::new()
+            .failure_threshold(1)
+            .cool_down(Duration::from_millis(0))
+            .half_open_calls(2);
+        let tp = Arc::new(config.layer(node.clone()));
+        let client = Client::with_transport(tp.clone());
+
+        node.down.store(true, Ordering::SeqCst);
+        assert!(client.call::<u64>("test", &[]).is_err());
+        assert_eq!(tp.state(), CircuitState::Open);
+
+        // Two successful trial calls are needed to close the breaker.
+        node.down.store(false, Ordering::SeqCst);
+        assert!(client.call::<u64>("test", &[]).is_ok());
+        assert_eq!(tp.state(), CircuitState::HalfOpen);
+        assert!(client.call::<u64>("test", &[]).is_ok());
+        assert_eq!(tp.state(), CircuitState::Closed);
+
+        let e = Error::CircuitOpen;
+        assert_eq!(e.to_string(), "circuit breaker open, call not attempted");
+    }
+}
@@ -20,15 +20,23 @@
 
 use std::borrow::Cow;
 use std::collections::HashMap;
-use std::fmt;
-use std::sync::atomic;
+use std::marker::PhantomData;
+use std::mem;
+use std::sync::Arc;
+use std::time::Duration;
+use std::{fmt, io};
 
 use serde;
+use serde::de::DeserializeSeed;
 use serde_json;
-use serde_json::value::RawValue;
 
-use super::{Request, Response};
-use crate::error::Error;
+use super::{Params, Request, Response, ResponseBody};
+use crate::auto_batch::AutoBatcher;
+use crate::batch::BatchBuilder;
+use crate::error::{Error, RpcError};
+use crate::id::{IdGenerator, SequentialIds};
+use crate::layer::Layer;
+use crate::stream::{self, ForEach, ResponseSeed};
 use crate::util::HashableValue;
 
 /// An interface for a transport over which to use the JSONRPC protocol.
@@ -36,42 +44,205 @@ pub trait Transport: Send + Sync + 'static {
     /// Send an RPC request over the transport.
     fn send_request(&self, _: Request) -> Result<Response, Error>;
     /// Send a batch of RPC requests over the transport.
+    ///
+    /// The batch may contain notifications, for which the server will not return a response. If
+    /// the batch only consists of notifications, no response is waited for.
     fn send_batch(&self, _: &[Request]) -> Result<Vec<Response>, Error>;
+    /// Send an RPC notification over the transport, without waiting for a response.
+    ///
+    /// The default implementation sends it with [`Transport::send_request`] and discards the
+    /// response, transports able to skip waiting for one should override it.
+    fn send_notification(&self, req: Request) -> Result<(), Error> {
+        self.send_request(req).map(|_| ())
+    }
+    /// Send an RPC request over the transport, handing the body of the response to `read_body`
+    /// as it is received instead of parsing it into a [`Response`].
+    ///
+    /// `read_body` must be called exactly once. It fails if the response does, e.g. on its RPC
+    /// error, which wrapping transports can thus observe. The default implementation receives the
+    /// whole response with [`Transport::send_request`] and hands over its serialization,
+    /// transports reading from a connection should override it to save memory on large
+    /// responses.
+    fn send_request_streaming(&self, req: Request, read_body: &mut ReadBody) -> Result<(), Error> {
+        let response = self.send_request(req)?;
+        let body = serde_json::to_vec(&ResponseBody(&response))?;
+        read_body(&mut &body[..])
+    }
     /// Format the target of this transport.
     /// I.e. the URL/socket/...
     fn fmt_target(&self, f: &mut fmt::Formatter) -> fmt::Result;
 }
 
+/// A callback reading the body of a response, see [`Transport::send_request_streaming`].
+pub type ReadBody<'a> = dyn FnMut(&mut dyn io::BufRead) -> Result<(), Error> + 'a;
+
+impl Transport for Box<dyn Transport> {
+    fn send_request(&self, req: Request) -> Result<Response, Error> {
+        (**self).send_request(req)
+    }
+
+    fn send_request_streaming(&self, req: Request, read_body: &mut ReadBody) -> Result<(), Error> {
+        (**self).send_request_streaming(req, read_body)
+    }
+
+    fn send_batch(&self, reqs: &[Request]) -> Result<Vec<Response>, Error> {
+        (**self).send_batch(reqs)
+    }
+
+    fn send_notification(&self, req: Request) -> Result<(), Error> {
+        (**self).send_notification(req)
+    }
+
+    fn fmt_target(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        (**self).fmt_target(f)
+    }
+}
+
+impl<T: Transport> Transport for Arc<T> {
+    fn send_request(&self, req: Request) -> Result<Response, Error> {
+        (**self).send_request(req)
+    }
+
+    fn send_request_streaming(&self, req: Request, read_body: &mut ReadBody) -> Result<(), Error> {
+        (**self).send_request_streaming(req, read_body)
+    }
+
+    fn send_batch(&self, reqs: &[Request]) -> Result<Vec<Response>, Error> {
+        (**self).send_batch(reqs)
+    }
+
+    fn send_notification(&self, req: Request) -> Result<(), Error> {
+        (**self).send_notification(req)
+    }
+
+    fn fmt_target(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        (**self).fmt_target(f)
+    }
+}
+
+/// How closely responses are checked against the JSON-RPC 2.0 specification.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Strictness {
+    /// Responses are accepted as long as their `id` is that of the request and their `jsonrpc`
+    /// member, if any, is "2.0".
+    ///
+    /// This copes with servers answering in the style of JSON-RPC 1.0, e.g. with both a `result`
+    /// and an `error` member, as Bitcoin Core did before version 28.
+    Lenient,
+    /// Responses must follow the specification: the `jsonrpc` member must be "2.0", exactly one
+    /// of the `result` and `error` members must be present, the error code must not be one of
+    /// the codes reserved by the specification without being defined by it, and the `id` must
+    /// be a string, a number or null of the type of the request ID.
+    ///
+    /// Whether the `result` and `error` members are present, as opposed to `null`, is only known
+    /// when the client parses the response itself, as it does for single calls. For batches,
+    /// calls sent in automatic batches and calls of the async client, a `null` member counts as
+    /// absent. Duplicate members are rejected while parsing in both modes.
+    Strict,
+}
+
+impl Default for Strictness {
+    fn default() -> Self {
+        Strictness::Lenient
+    }
+}
+
+/// The version of the JSON-RPC protocol spoken by a client.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ProtocolVersion {
+    /// JSON-RPC 1.0.
+    ///
+    /// Requests have no `jsonrpc` member and always have `params`, notifications have a `null`
+    /// `id`. Responses have both a `result` and an `error` member, one of them `null`, and
+    /// errors may come with a `null` `id`. Batches, including automatic ones, are not part of
+    /// JSON-RPC 1.0 and are sent as in 2.0, nor are parameters by name, which are sent as is.
+    V1,
+    /// JSON-RPC 2.0.
+    V2,
+}
+
+impl ProtocolVersion {
+    /// Builds a request in this version of the protocol, a notification if `id` is `None`.
+    pub(crate) fn request<'a>(
+        self,
+        method: &'a str,
+        params: Params<'a>,
+        id: Option<serde_json::Value>,
+    ) -> Request<'a> {
+        match self {
+            ProtocolVersion::V1 => Request {
+                method,
+                // Parameters can't be omitted, and notifications have a `null` ID.
+                params: match params {
+                    Params::None => Params::ByPosition(&[]),
+                    params => params,
+                },
+                id: Some(id.unwrap_or_default()),
+                jsonrpc: None,
+            },
+            ProtocolVersion::V2 => Request {
+                method,
+                params,
+                id,
+                jsonrpc: Some("2.0"),
+            },
+        }
+    }
+}
+
+impl Default for ProtocolVersion {
+    fn default() -> Self {
+        ProtocolVersion::V2
+    }
+}
+
 /// A JSON-RPC client.
 ///
 /// Create a new Client using one of the transport-specific constructors e.g.,
 /// [`Client::simple_http`] for a bare-minimum HTTP transport.
 pub struct Client {
     pub(crate) transport: Box<dyn Transport>,
-    nonce: atomic::AtomicUsize,
+    ids: Box<dyn IdGenerator>,
+    batcher: Option<AutoBatcher>,
+    checks: ResponseChecks,
 }
 
 impl Client {
     /// Creates a new client with the given transport.
+    ///
+    /// Requests are numbered sequentially, see [`Client::builder`] for more options.
     pub fn with_transport<T: Transport>(transport: T) -> Client {
-        Client {
-            transport: Box::new(transport),
-            nonce: atomic::AtomicUsize::new(1),
-        }
+        Client::builder(transport).build()
+    }
+
+    /// Returns a builder for a client with the given transport.
+    pub fn builder<T: Transport>(transport: T) -> Builder {
+        Builder::new(transport)
     }
 
     /// Builds a request.
     ///
-    /// To construct the arguments, one can use one of the shorthand methods
-    /// [`crate::arg`] or [`crate::try_arg`].
-    pub fn build_request<'a>(&self, method: &'a str, params: &'a [Box<RawValue>]) -> Request<'a> {
-        let nonce = self.nonce.fetch_add(1, atomic::Ordering::Relaxed);
-        Request {
-            method,
-            params,
-            id: serde_json::Value::from(nonce),
-            jsonrpc: Some("2.0"),
-        }
+    /// The parameters can be given by position, e.g. `&[arg(1), arg(2)]`, by name, e.g.
+    /// `&[("height", arg(1))]`, or omitted with [`Params::None`]. To construct the arguments,
+    /// one can use one of the shorthand methods [`crate::arg`] or [`crate::try_arg`].
+    pub fn build_request<'a, P: Into<Params<'a>>>(
+        &self,
+        method: &'a str,
+        params: P,
+    ) -> Request<'a> {
+        self.checks.version.request(method, params.into(), Some(self.ids.next_id()))
+    }
+
+    /// Builds a notification, i.e. a request without an `id`, or with a `null` one in
+    /// JSON-RPC 1.0.
+    ///
+    /// The parameters are passed as for [`Client::build_request`].
+    pub fn build_notification<'a, P: Into<Params<'a>>>(
+        &self,
+        method: &'a str,
+        params: P,
+    ) -> Request<'a> {
+        self.checks.version.request(method, params.into(), None)
     }
 
     /// Sends a request to a client.
@@ -79,15 +250,21 @@ impl Client {
         self.transport.send_request(request)
     }
 
+    /// Sends a notification to the client, without waiting for a response.
+    pub fn send_notification(&self, request: Request) -> Result<(), Error> {
+        self.transport.send_notification(request)
+    }
+
     /// Sends a batch of requests to the client.
     ///
     /// Note that the requests need to have valid IDs, so it is advised to create the requests
-    /// with [`Client::build_request`].
+    /// with [`Client::build_request`]. Notifications created with
+    /// [`Client::build_notification`] may be mixed into the batch.
     ///
     /// # Returns
     ///
     /// The return vector holds the response for the request at the corresponding index. If no
-    /// response was provided, it's [`None`].
+    /// response was provided, e.g. because the request is a notification, it's [`None`].
     pub fn send_batch(&self, requests: &[Request]) -> Result<Vec<Option<Response>>, Error> {
         if requests.is_empty() {
             return Err(Error::EmptyBatch);
@@ -96,55 +273,381 @@ impl Client {
         // If the request body is invalid JSON, the response is a single response object.
         // We ignore this case since we are confident we are producing valid JSON.
         let responses = self.transport.send_batch(requests)?;
-        if responses.len() > requests.len() {
-            return Err(Error::WrongBatchResponseSize);
+        let responses = match_batch_responses(requests, responses)?;
+        if self.checks.strictness == Strictness::Strict {
+            check_batch_responses(self.checks, requests, &responses)?;
         }
+        Ok(responses)
+    }
 
-        //TODO(stevenroose) check if the server preserved order to avoid doing the mapping
+    /// Returns how closely responses are checked, see [`Builder::strictness`].
+    pub fn strictness(&self) -> Strictness {
+        self.checks.strictness
+    }
 
-        // First index responses by ID and catch duplicate IDs.
-        let mut by_id = HashMap::with_capacity(requests.len());
-        for resp in responses.into_iter() {
-            let id = HashableValue(Cow::Owned(resp.id.clone()));
-            if let Some(dup) = by_id.insert(id, resp) {
-                return Err(Error::BatchDuplicateResponseId(dup.id));
-            }
-        }
-        // Match responses to the requests.
-        let results =
-            requests.iter().map(|r| by_id.remove(&HashableValue(Cow::Borrowed(&r.id)))).collect();
+    /// Returns the version of the protocol spoken, see [`Builder::protocol_version`].
+    pub fn protocol_version(&self) -> ProtocolVersion {
+        self.checks.version
+    }
 
-        // Since we're also just producing the first duplicate ID, we can also just produce the
-        // first incorrect ID in case there are multiple.
-        if let Some(id) = by_id.keys().next() {
-            return Err(Error::WrongBatchResponseId((*id.0).clone()));
-        }
+    /// Returns how responses are checked.
+    pub(crate) fn checks(&self) -> ResponseChecks {
+        self.checks
+    }
 
-        Ok(results)
+    /// Starts a batch whose calls each get a typed handle to their result.
+    pub fn batch(&self) -> BatchBuilder<'_> {
+        BatchBuilder::new(self)
     }
 
     /// Make a request and deserialize the response.
     ///
-    /// To construct the arguments, one can use one of the shorthand methods
-    /// [`crate::arg`] or [`crate::try_arg`].
-    pub fn call<R: for<'a> serde::de::Deserialize<'a>>(
+    /// The parameters are passed as for [`Client::build_request`]. To construct the arguments,
+    /// one can use one of the shorthand methods [`crate::arg`] or [`crate::try_arg`].
+    ///
+    /// If automatic batching is enabled, see [`Builder::auto_batch`], the call may be sent in a
+    /// batch together with concurrent calls. Otherwise, with [`Strictness::Strict`], the call is
+    /// made as with [`Client::call_streaming`], to check which members the response has.
+    pub fn call<'p, R: for<'a> serde::de::Deserialize<'a>>(
+        &self,
+        method: &str,
+        params: impl Into<Params<'p>>,
+    ) -> Result<R, Error> {
+        if self.checks.strictness == Strictness::Strict && self.batcher.is_none() {
+            // Only the raw response tells which of its members are present.
+            return self.call_streaming(method, params);
+        }
+        let request = self.build_request(method, params.into());
+        let id = request.id.clone();
+
+        let response = match self.batcher {
+            Some(ref batcher) => batcher.send(self, request)?,
+            None => self.send_request(request)?,
+        };
+        check_response(self.checks, id.as_ref(), &response)?;
+        response.result()
+    }
+
+    /// Make a request and deserialize the result straight from the response as it is received.
+    ///
+    /// This avoids holding large responses in memory in full before deserializing them. The
+    /// parameters are passed as for [`Client::build_request`]. The call is never batched
+    /// automatically.
+    pub fn call_streaming<'p, R: for<'a> serde::de::Deserialize<'a>>(
         &self,
         method: &str,
-        args: &[Box<RawValue>],
+        params: impl Into<Params<'p>>,
     ) -> Result<R, Error> {
-        let request = self.build_request(method, args);
+        self.call_seed(method, params, PhantomData::<R>)
+    }
+
+    /// Make a request and deserialize the result with `seed` as it is received.
+    ///
+    /// See [`Client::call_streaming`]. The seed may e.g. process the elements of a large array
+    /// one by one, as [`Client::for_each`] does.
+    ///
+    /// The seed is run as the `result` member is received, before the ID, version and `error`
+    /// member of the response are checked. It may thus see the result of a response which the
+    /// call then fails on, e.g. because it answers another request.
+    pub fn call_seed<'p, 'de, S: DeserializeSeed<'de>>(
+        &self,
+        method: &str,
+        params: impl Into<Params<'p>>,
+        seed: S,
+    ) -> Result<S::Value, Error> {
+        let request = self.build_request(method, params.into());
         let id = request.id.clone();
 
-        let response = self.send_request(request)?;
-        if response.jsonrpc.is_some() && response.jsonrpc != Some(From::from("2.0")) {
-            return Err(Error::VersionMismatch);
+        let mut seed = Some(seed);
+        let mut value = None;
+        self.transport.send_request_streaming(request, &mut |body| {
+            let seed = seed.take().ok_or(stream::Error::NoBody)?;
+            let mut de = serde_json::Deserializer::from_reader(body);
+            let envelope = ResponseSeed(seed).deserialize(&mut de)?;
+            // Checked here so that transports see the RPC error of the response, if any.
+            value = Some(envelope.into_result(self.checks, id.as_ref())?);
+            Ok(())
+        })?;
+        Ok(value.ok_or(stream::Error::NoBody)?)
+    }
+
+    /// Make a request whose result is an array, and call `f` on each of its elements as they
+    /// are received.
+    ///
+    /// See [`Client::call_streaming`]. As with [`Client::call_seed`], `f` is called before the
+    /// rest of the response is checked, so it may see elements of a response which the call then
+    /// fails on.
+    pub fn for_each<'p, T, F>(
+        &self,
+        method: &str,
+        params: impl Into<Params<'p>>,
+        f: F,
+    ) -> Result<(), Error>
+    where
+        T: for<'a> serde::de::Deserialize<'a>,
+        F: FnMut(T),
+    {
+        self.call_seed(method, params, ForEach::new(f))
+    }
+
+    /// Sends a notification, i.e. a request to which the server does not reply.
+    ///
+    /// The parameters are passed as for [`Client::build_request`].
+    pub fn notify<'p>(&self, method: &str, params: impl Into<Params<'p>>) -> Result<(), Error> {
+        let request = self.build_notification(method, params.into());
+        self.send_notification(request)
+    }
+}
+
+/// Builder for a [`Client`].
+pub struct Builder {
+    transport: Box<dyn Transport>,
+    ids: Box<dyn IdGenerator>,
+    batcher: Option<AutoBatcher>,
+    checks: ResponseChecks,
+}
+
+impl Builder {
+    /// Constructs a new [`Builder`] for a client with the given transport.
+    pub fn new<T: Transport>(transport: T) -> Builder {
+        Builder {
+            transport: Box::new(transport),
+            ids: Box::new(SequentialIds::new()),
+            batcher: None,
+            checks: ResponseChecks::default(),
         }
-        if response.id != id {
-            return Err(Error::NonceMismatch);
+    }
+
+    /// Sets the strategy used to generate the IDs of requests.
+    pub fn id_generator<G: IdGenerator>(mut self, ids: G) -> Self {
+        self.ids = Box::new(ids);
+        self
+    }
+
+    /// Wraps the transport with the given layer.
+    ///
+    /// Layers added later wrap the ones added before, so the last layer sees every call first.
+    pub fn layer<L: Layer<Box<dyn Transport>>>(mut self, layer: L) -> Self {
+        self.transport = Box::new(layer.layer(self.transport));
+        self
+    }
+
+    /// Enables automatic batching of concurrent calls, see [`crate::auto_batch`].
+    ///
+    /// Calls made with [`Client::call`] wait up to `window` for other calls to join their batch,
+    /// and batches are sent as soon as they hold `max_batch` calls.
+    pub fn auto_batch(mut self, window: Duration, max_batch: usize) -> Self {
+        self.batcher = Some(AutoBatcher::new(window, max_batch));
+        self
+    }
+
+    /// Sets how closely responses are checked against the specification.
+    ///
+    /// This applies to the results of calls and batches, responses returned as is by
+    /// [`Client::send_request`] are not checked. The default is [`Strictness::Lenient`].
+    pub fn strictness(mut self, strictness: Strictness) -> Self {
+        self.checks.strictness = strictness;
+        self
+    }
+
+    /// Sets the version of the protocol spoken, see [`ProtocolVersion`].
+    ///
+    /// The default is [`ProtocolVersion::V2`].
+    pub fn protocol_version(mut self, version: ProtocolVersion) -> Self {
+        self.checks.version = version;
+        self
+    }
+
+    /// Builds the final [`Client`].
+    pub fn build(self) -> Client {
+        Client {
+            transport: self.transport,
+            ids: self.ids,
+            batcher: self.batcher,
+            checks: self.checks,
         }
+    }
+}
 
-        response.result()
+impl fmt::Debug for Builder {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "jsonrpc::client::Builder(")?;
+        self.transport.fmt_target(f)?;
+        write!(f, ")")
+    }
+}
+
+/// How responses are checked, as configured on a client.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) struct ResponseChecks {
+    pub(crate) version: ProtocolVersion,
+    pub(crate) strictness: Strictness,
+}
+
+/// Which of the `result` and `error` members of a response are present, even if `null`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct Members {
+    pub(crate) result: bool,
+    pub(crate) error: bool,
+}
+
+/// Checks that the response to a single request has the expected version and ID, and is
+/// otherwise valid for the given checks.
+///
+/// The members present in the response are unknown once it's parsed, see
+/// [`Strictness::Strict`].
+pub(crate) fn check_response(
+    checks: ResponseChecks,
+    id: Option<&serde_json::Value>,
+    response: &Response,
+) -> Result<(), Error> {
+    check_envelope(
+        checks,
+        id,
+        response.jsonrpc.as_deref(),
+        &response.id,
+        response.result.is_some(),
+        response.error.as_ref(),
+        None,
+    )
+}
+
+/// Checks the responses to a batch against the IDs of their requests, see [`check_response`].
+pub(crate) fn check_batch_responses(
+    checks: ResponseChecks,
+    requests: &[Request],
+    responses: &[Option<Response>],
+) -> Result<(), Error> {
+    for (request, response) in requests.iter().zip(responses) {
+        if let Some(ref response) = *response {
+            check_response(checks, request.id.as_ref(), response)?;
+        }
+    }
+    Ok(())
+}
+
+/// Checks the members of a response against the ID of the request, see [`check_response`].
+///
+/// `has_result` tells whether the result is other than `null`, and `members` which members are
+/// present, if known.
+pub(crate) fn check_envelope(
+    checks: ResponseChecks,
+    id: Option<&serde_json::Value>,
+    jsonrpc: Option<&str>,
+    response_id: &serde_json::Value,
+    has_result: bool,
+    error: Option<&RpcError>,
+    members: Option<Members>,
+) -> Result<(), Error> {
+    let strict = checks.strictness == Strictness::Strict;
+    match checks.version {
+        ProtocolVersion::V2 => {
+            match jsonrpc {
+                None if strict => return Err(Error::MissingVersion),
+                None | Some("2.0") => {}
+                Some(_) => return Err(Error::VersionMismatch),
+            }
+            if strict {
+                match members {
+                    Some(Members {
+                        result: true,
+                        error: true,
+                    }) => return Err(Error::ResultAndError),
+                    Some(Members {
+                        result: false,
+                        ..
+                    }) if error.is_none() => return Err(Error::NoResultOrError),
+                    None if has_result && error.is_some() => return Err(Error::ResultAndError),
+                    _ => {}
+                }
+                if let Some(e) = error {
+                    if is_reserved_code(e.code) {
+                        return Err(Error::ReservedErrorCode(e.code));
+                    }
+                }
+                match *response_id {
+                    serde_json::Value::Null
+                    | serde_json::Value::Number(_)
+                    | serde_json::Value::String(_) => {}
+                    _ => return Err(Error::InvalidResponseId(response_id.clone())),
+                }
+            }
+        }
+        ProtocolVersion::V1 => {
+            if strict {
+                if jsonrpc.is_some() {
+                    return Err(Error::VersionMismatch);
+                }
+                // Both members are present, and at least one of them is null.
+                if members.map_or(false, |m| !m.result || !m.error) {
+                    return Err(Error::NoResultOrError);
+                }
+                if has_result && error.is_some() {
+                    return Err(Error::ResultAndError);
+                }
+            }
+            // Errors about requests whose ID couldn't be determined have a null ID.
+            if error.is_some() && response_id.is_null() {
+                return Ok(());
+            }
+        }
+    }
+    if strict {
+        if let Some(id) = id {
+            if mem::discriminant(id) != mem::discriminant(response_id) {
+                return Err(Error::IdTypeMismatch(response_id.clone()));
+            }
+        }
+    }
+    if id != Some(response_id) {
+        return Err(Error::NonceMismatch);
+    }
+    Ok(())
+}
+
+/// Returns whether an error code is reserved by the specification without being defined by it.
+fn is_reserved_code(code: i32) -> bool {
+    // Parse error, the errors from invalid request to internal error, and server errors.
+    let defined = code == -32700 || (-32603..=-32600).contains(&code) || code >= -32099;
+    (-32768..=-32000).contains(&code) && !defined
+}
+
+/// Matches the responses to a batch to their requests by ID.
+///
+/// The returned vector holds the response for the request at the corresponding index, or
+/// [`None`] if no response was provided.
+pub(crate) fn match_batch_responses(
+    requests: &[Request],
+    responses: Vec<Response>,
+) -> Result<Vec<Option<Response>>, Error> {
+    let n_expected = requests.iter().filter(|r| !r.is_notification()).count();
+    if responses.len() > n_expected {
+        return Err(Error::WrongBatchResponseSize);
+    }
+
+    //TODO(stevenroose) check if the server preserved order to avoid doing the mapping
+
+    // First index responses by ID and catch duplicate IDs.
+    let mut by_id = HashMap::with_capacity(requests.len());
+    for resp in responses.into_iter() {
+        let id = HashableValue(Cow::Owned(resp.id.clone()));
+        if let Some(dup) = by_id.insert(id, resp) {
+            return Err(Error::BatchDuplicateResponseId(dup.id));
+        }
     }
+    // Match responses to the requests.
+    let results = requests
+        .iter()
+        .map(|r| r.id.as_ref().and_then(|id| by_id.remove(&HashableValue(Cow::Borrowed(id)))))
+        .collect();
+
+    // Since we're also just producing the first duplicate ID, we can also just produce the
+    // first incorrect ID in case there are multiple.
+    if let Some(id) = by_id.keys().next() {
+        return Err(Error::WrongBatchResponseId((*id.0).clone()));
+    }
+
+    Ok(results)
 }
 
 impl fmt::Debug for crate::Client {
@@ -156,9 +659,12 @@ impl fmt::Debug for crate::Client {
 }
 
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::sync::{mpsc, Mutex};
+
     use super::*;
-    use std::sync;
+    use crate::error::{standard_error, StandardError};
 
     struct DummyTransport;
     impl Transport for DummyTransport {
@@ -168,6 +674,9 @@ mod tests {
         fn send_batch(&self, _: &[Request]) -> Result<Vec<Response>, Error> {
             Ok(vec![])
         }
+        fn send_notification(&self, _: Request) -> Result<(), Error> {
+            Ok(())
+        }
         fn fmt_target(&self, _: &mut fmt::Formatter) -> fmt::Result {
             Ok(())
         }
@@ -176,11 +685,360 @@ mod tests {
     #[test]
     fn sanity() {
         let client = Client::with_transport(DummyTransport);
-        assert_eq!(client.nonce.load(sync::atomic::Ordering::Relaxed), 1);
         let req1 = client.build_request("test", &[]);
-        assert_eq!(client.nonce.load(sync::atomic::Ordering::Relaxed), 2);
+        assert_eq!(req1.id, Some(serde_json::Value::from(1)));
         let req2 = client.build_request("test", &[]);
-        assert_eq!(client.nonce.load(sync::atomic::Ordering::Relaxed), 3);
+        assert_eq!(req2.id, Some(serde_json::Value::from(2)));
         assert!(req1.id != req2.id);
     }
+
+    #[test]
+    fn custom_ids() {
+        let client = Client::builder(EchoTransport)
+            .id_generator(crate::id::PrefixedIds::new("test"))
+            .build();
+        let req = client.build_request("test", &[]);
+        assert_eq!(req.id, Some(serde_json::Value::from("test-1")));
+
+        // Calls and batches are matched whatever the type of the IDs.
+        assert_eq!(client.call::<String>("test", &[]).unwrap(), "test-2");
+        let batch = [client.build_request("test", &[]), client.build_request("test", &[])];
+        let responses = client.send_batch(&batch).unwrap();
+        assert_eq!(responses[0].as_ref().unwrap().result::<String>().unwrap(), "test-3");
+        assert_eq!(responses[1].as_ref().unwrap().result::<String>().unwrap(), "test-4");
+
+        let client =
+            Client::builder(EchoTransport).id_generator(crate::id::RandomIds::new()).build();
+        let batch = [client.build_request("test", &[]), client.build_request("test", &[])];
+        let responses = client.send_batch(&batch).unwrap();
+        assert_eq!(responses[0].as_ref().unwrap().id, *batch[0].id.as_ref().unwrap());
+        assert_eq!(responses[1].as_ref().unwrap().id, *batch[1].id.as_ref().unwrap());
+    }
+
+    /// Replies to every request with its own ID as result, to batches in reverse order.
+    pub(crate) struct EchoTransport;
+    impl Transport for EchoTransport {
+        fn send_request(&self, req: Request) -> Result<Response, Error> {
+            let id = req.id.unwrap_or_default();
+            Ok(crate::error::result_to_response(Ok(id.clone()), id))
+        }
+        fn send_batch(&self, reqs: &[Request]) -> Result<Vec<Response>, Error> {
+            Ok(reqs
+                .iter()
+                .rev()
+                .filter_map(|r| r.id.clone())
+                .map(|id| crate::error::result_to_response(Ok(id.clone()), id))
+                .collect())
+        }
+        fn send_notification(&self, _: Request) -> Result<(), Error> {
+            Ok(())
+        }
+        fn fmt_target(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "echo")
+        }
+    }
+
+    /// A server behind an [`EchoTransport`], which can be taken down or made to answer with RPC
+    /// errors, counting the requests, batches and notifications it receives.
+    ///
+    /// Requests for the `down` method always fail with a transport error, and requests for the
+    /// `fail` method with an RPC error.
+    #[derive(Default)]
+    pub(crate) struct Node {
+        /// Replied to requests instead of their ID, if set.
+        pub(crate) name: Option<&'static str>,
+        pub(crate) down: AtomicBool,
+        pub(crate) rpc_error: AtomicBool,
+        /// Requests wait for a message before being answered, if set.
+        pub(crate) gate: Option<Mutex<mpsc::Receiver<()>>>,
+        pub(crate) requests: AtomicUsize,
+        pub(crate) batches: AtomicUsize,
+        pub(crate) notifications: AtomicUsize,
+        /// Requests whose response was streamed, which are counted as requests too.
+        pub(crate) streamed: AtomicUsize,
+    }
+
+    impl Node {
+        /// Creates a node replying with its name.
+        pub(crate) fn named(name: &'static str) -> Node {
+            Node {
+                name: Some(name),
+                ..Default::default()
+            }
+        }
+
+        /// Returns the number of requests, batches and notifications received.
+        pub(crate) fn calls(&self) -> usize {
+            self.requests.load(Ordering::SeqCst)
+                + self.batches.load(Ordering::SeqCst)
+                + self.notifications.load(Ordering::SeqCst)
+        }
+
+        /// Fails with a transport error while down, or for the `down` method.
+        fn check_up(&self, method: &str) -> Result<(), Error> {
+            if self.down.load(Ordering::SeqCst) || method == "down" {
+                Err(Error::Transport(Box::new(io::Error::from(io::ErrorKind::ConnectionRefused))))
+            } else {
+                Ok(())
+            }
+        }
+    }
+
+    impl Transport for Node {
+        fn send_request(&self, req: Request) -> Result<Response, Error> {
+            self.requests.fetch_add(1, Ordering::SeqCst);
+            if let Some(ref gate) = self.gate {
+                gate.lock().unwrap().recv().unwrap();
+            }
+            self.check_up(req.method)?;
+            let id = req.id.clone().unwrap_or_default();
+            if req.method == "fail" || self.rpc_error.load(Ordering::SeqCst) {
+                let error = standard_error(StandardError::InternalError, None);
+                Ok(crate::error::result_to_response(Err(error), id))
+            } else if let Some(name) = self.name {
+                Ok(crate::error::result_to_response(Ok(name.into()), id))
+            } else {
+                EchoTransport.send_request(req)
+            }
+        }
+        fn send_request_streaming(
+            &self,
+            req: Request,
+            read_body: &mut ReadBody,
+        ) -> Result<(), Error> {
+            self.streamed.fetch_add(1, Ordering::SeqCst);
+            let body = serde_json::to_vec(&ResponseBody(&self.send_request(req)?))?;
+            read_body(&mut &body[..])
+        }
+        fn send_batch(&self, reqs: &[Request]) -> Result<Vec<Response>, Error> {
+            self.batches.fetch_add(1, Ordering::SeqCst);
+            self.check_up("")?;
+            EchoTransport.send_batch(reqs)
+        }
+        fn send_notification(&self, req: Request) -> Result<(), Error> {
+            self.notifications.fetch_add(1, Ordering::SeqCst);
+            self.check_up(req.method)?;
+            EchoTransport.send_notification(req)
+        }
+        fn fmt_target(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "{}", self.name.unwrap_or("node"))
+        }
+    }
+
+    /// Replies to every request with the given response.
+    pub(crate) struct Canned(pub(crate) &'static str);
+
+    impl Transport for Canned {
+        fn send_request(&self, _: Request) -> Result<Response, Error> {
+            Ok(serde_json::from_str(self.0)?)
+        }
+        fn send_request_streaming(
+            &self,
+            _: Request,
+            read_body: &mut ReadBody,
+        ) -> Result<(), Error> {
+            read_body(&mut self.0.as_bytes())
+        }
+        fn send_batch(&self, _: &[Request]) -> Result<Vec<Response>, Error> {
+            Ok(vec![serde_json::from_str(self.0)?])
+        }
+        fn send_notification(&self, _: Request) -> Result<(), Error> {
+            Ok(())
+        }
+        fn fmt_target(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "canned")
+        }
+    }
+
+    #[test]
+    fn strictness() {
+        use crate::metrics::ErrorKind;
+
+        let cases = [
+            (r#"{"jsonrpc":"2.0","result":1,"id":1}"#, None, None),
+            (r#"{"jsonrpc":"2.0","result":null,"id":1}"#, None, None),
+            (r#"{"result":1,"id":1}"#, None, Some(ErrorKind::MissingVersion)),
+            (r#"{"jsonrpc":"1.0","result":1,"id":1}"#, Some(ErrorKind::VersionMismatch), None),
+            (
+                r#"{"jsonrpc":"2.0","result":null,"error":{"code":-1,"message":"x"},"id":1}"#,
+                Some(ErrorKind::Rpc(-1)),
+                Some(ErrorKind::ResultAndError),
+            ),
+            (
+                r#"{"jsonrpc":"2.0","error":{"code":-32601,"message":"x"},"id":1}"#,
+                Some(ErrorKind::Rpc(-32601)),
+                None,
+            ),
+            (
+                r#"{"jsonrpc":"2.0","error":{"code":-32500,"message":"x"},"id":1}"#,
+                Some(ErrorKind::Rpc(-32500)),
+                Some(ErrorKind::ReservedErrorCode),
+            ),
+            (r#"{"jsonrpc":"2.0","error":null,"id":1}"#, None, Some(ErrorKind::NoResultOrError)),
+            (
+                r#"{"jsonrpc":"2.0","result":1,"error":null,"id":1}"#,
+                None,
+                Some(ErrorKind::ResultAndError),
+            ),
+            (
+                r#"{"jsonrpc":"2.0","result":1,"id":[1]}"#,
+                Some(ErrorKind::NonceMismatch),
+                Some(ErrorKind::InvalidResponseId),
+            ),
+            (
+                r#"{"jsonrpc":"2.0","result":1,"id":"1"}"#,
+                Some(ErrorKind::NonceMismatch),
+                Some(ErrorKind::IdTypeMismatch),
+            ),
+            (r#"{"jsonrpc":"2.0","result":1,"id":2}"#, Some(ErrorKind::NonceMismatch), None),
+            // Duplicate members are rejected in both modes.
+            (r#"{"jsonrpc":"2.0","result":1,"id":2,"id":1}"#, Some(ErrorKind::Json), None),
+            (
+                r#"{"jsonrpc":"2.0","error":{"code":1,"message":"x"},"error":null,"id":1}"#,
+                Some(ErrorKind::Json),
+                None,
+            ),
+            (r#"{"jsonrpc":"2.0","jsonrpc":"1.0","result":1,"id":1}"#, Some(ErrorKind::Json), None),
+        ];
+        for &(response, lenient, strict) in &cases {
+            // Errors caught by lenient checks are caught by strict checks too.
+            let strict = strict.or(lenient);
+            for &(strictness, expected) in
+                &[(Strictness::Lenient, lenient), (Strictness::Strict, strict)]
+            {
+                let build = || Client::builder(Canned(response)).strictness(strictness).build();
+                for result in &[
+                    build().call::<Option<u64>>("test", &[]),
+                    build().call_streaming::<Option<u64>>("test", &[]),
+                ] {
+                    let kind = result.as_ref().err().map(ErrorKind::of);
+                    assert_eq!(kind, expected, "{:?} response {}", strictness, response);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn strict_batches() {
+        let build =
+            |response| Client::builder(Canned(response)).strictness(Strictness::Strict).build();
+        let client = build(r#"{"jsonrpc":"2.0","result":1,"id":1}"#);
+        let batch = [client.build_notification("ping", &[]), client.build_request("test", &[])];
+        let responses = client.send_batch(&batch).unwrap();
+        assert!(responses[0].is_none());
+        assert_eq!(responses[1].as_ref().unwrap().result::<u64>().unwrap(), 1);
+
+        let client = build(r#"{"result":1,"id":1}"#);
+        match client.send_batch(&[client.build_request("test", &[])]) {
+            Err(Error::MissingVersion) => {}
+            r => panic!("expected missing version, got {:?}", r),
+        }
+    }
+
+    #[test]
+    fn protocol_v1() {
+        use crate::metrics::ErrorKind;
+
+        let client = Client::builder(EchoTransport).protocol_version(ProtocolVersion::V1).build();
+        assert_eq!(client.protocol_version(), ProtocolVersion::V1);
+        let request = serde_json::to_string(&client.build_request("test", Params::None)).unwrap();
+        assert_eq!(request, r#"{"method":"test","params":[],"id":1}"#);
+        let params = [crate::arg(1)];
+        let notification = client.build_notification("ping", &params);
+        assert!(notification.is_notification());
+        let notification = serde_json::to_string(&notification).unwrap();
+        assert_eq!(notification, r#"{"method":"ping","params":[1],"id":null}"#);
+
+        let cases = [
+            (r#"{"result":1,"error":null,"id":1}"#, None, None),
+            (r#"{"result":null,"error":null,"id":1}"#, None, None),
+            (r#"{"result":1,"id":1}"#, None, Some(ErrorKind::NoResultOrError)),
+            (
+                r#"{"error":{"code":1,"message":"x"},"id":1}"#,
+                Some(ErrorKind::Rpc(1)),
+                Some(ErrorKind::NoResultOrError),
+            ),
+            (r#"{"jsonrpc":"2.0","result":1,"id":1}"#, None, Some(ErrorKind::VersionMismatch)),
+            (r#"{"error":null,"id":1}"#, None, Some(ErrorKind::NoResultOrError)),
+            (
+                r#"{"result":null,"error":{"code":1,"message":"x"},"id":null}"#,
+                Some(ErrorKind::Rpc(1)),
+                None,
+            ),
+            (
+                r#"{"result":1,"error":{"code":1,"message":"x"},"id":1}"#,
+                Some(ErrorKind::Rpc(1)),
+                Some(ErrorKind::ResultAndError),
+            ),
+            (
+                r#"{"result":1,"error":null,"id":null}"#,
+                Some(ErrorKind::NonceMismatch),
+                Some(ErrorKind::IdTypeMismatch),
+            ),
+        ];
+        for &(response, lenient, strict) in &cases {
+            let strict = strict.or(lenient);
+            for &(strictness, expected) in
+                &[(Strictness::Lenient, lenient), (Strictness::Strict, strict)]
+            {
+                let build = || {
+                    Client::builder(Canned(response))
+                        .protocol_version(ProtocolVersion::V1)
+                        .strictness(strictness)
+                        .build()
+                };
+                for result in &[
+                    build().call::<Option<u64>>("test", &[]),
+                    build().call_streaming::<Option<u64>>("test", &[]),
+                ] {
+                    let kind = result.as_ref().err().map(ErrorKind::of);
+                    assert_eq!(kind, expected, "{:?} response {}", strictness, response);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn streaming_fallback() {
+        // The echo transport doesn't stream, the response is handed over once received.
+        let client = Client::with_transport(EchoTransport);
+        assert_eq!(client.call_streaming::<u64>("test", &[]).unwrap(), 1);
+        match client.for_each("test", &[], |_: u64| {}) {
+            Err(Error::Json(_)) => {}
+            r => panic!("expected JSON error, got {:?}", r),
+        }
+    }
+
+    #[test]
+    fn batch_with_notifications() {
+        let client = Client::with_transport(EchoTransport);
+        let batch = [
+            client.build_request("test", &[]),
+            client.build_notification("test", &[]),
+            client.build_request("test", &[]),
+        ];
+        assert!(batch[1].is_notification());
+
+        let responses = client.send_batch(&batch).unwrap();
+        assert_eq!(responses.len(), 3);
+        assert_eq!(responses[0].as_ref().unwrap().result::<u64>().unwrap(), 1);
+        assert!(responses[1].is_none());
+        assert_eq!(responses[2].as_ref().unwrap().result::<u64>().unwrap(), 2);
+
+        client.notify("test", &[]).unwrap();
+    }
+
+    #[test]
+    fn build_request_params() {
+        let client = Client::with_transport(DummyTransport);
+
+        let positional = [crate::arg(1)];
+        let req = client.build_request("test", &positional);
+        assert_eq!(serde_json::to_string(&req.params).unwrap(), "[1]");
+        let named = [("height", crate::arg(1))];
+        let req = client.build_request("test", &named);
+        assert_eq!(serde_json::to_string(&req.params).unwrap(), r#"{"height":1}"#);
+        let req = client.build_request("test", Params::None);
+        assert!(req.params.is_none());
+    }
 }
@@ -46,6 +46,22 @@ pub enum Error {
     BatchDuplicateResponseId(serde_json::Value),
     /// Batch response contained an ID that didn't correspond to any request ID
     WrongBatchResponseId(serde_json::Value),
+    /// Batch response did not contain a response to the request with the given ID
+    MissingBatchResponse(serde_json::Value),
+    /// The circuit breaker of the transport is open, so the call was not attempted
+    CircuitOpen,
+    /// Response had no jsonrpc field, in strict mode
+    MissingVersion,
+    /// Response had both a result and an error, in strict mode
+    ResultAndError,
+    /// Response had neither a result nor an error, in strict mode
+    NoResultOrError,
+    /// Response had an error code reserved by the specification, in strict mode
+    ReservedErrorCode(i32),
+    /// Response had an ID which is neither a string, a number nor null, in strict mode
+    InvalidResponseId(serde_json::Value),
+    /// Response had an ID of a different type than the request ID, in strict mode
+    IdTypeMismatch(serde_json::Value),
 }
 
 impl From<serde_json::Error> for Error {
@@ -70,10 +86,24 @@ impl fmt::Display for Error {
                 write!(f, "duplicate RPC batch response ID: {}", v)
             }
             Error::WrongBatchResponseId(ref v) => write!(f, "wrong RPC batch response ID: {}", v),
+            Error::MissingBatchResponse(ref v) => {
+                write!(f, "missing RPC batch response for ID: {}", v)
+            }
             Error::NonceMismatch => write!(f, "Nonce of response did not match nonce of request"),
             Error::VersionMismatch => write!(f, "`jsonrpc` field set to non-\"2.0\""),
             Error::EmptyBatch => write!(f, "batches can't be empty"),
             Error::WrongBatchResponseSize => write!(f, "too many responses returned in batch"),
+            Error::CircuitOpen => write!(f, "circuit breaker open, call not attempted"),
+            Error::MissingVersion => write!(f, "`jsonrpc` field missing"),
+            Error::ResultAndError => write!(f, "response has both a result and an error"),
+            Error::NoResultOrError => write!(f, "response has neither a result nor an error"),
+            Error::ReservedErrorCode(c) => write!(f, "error code {} is reserved", c),
+            Error::InvalidResponseId(ref v) => {
+                write!(f, "response ID {} is not a string, a number or null", v)
+            }
+            Error::IdTypeMismatch(ref v) => {
+                write!(f, "response ID {} is not of the type of the request ID", v)
+            }
         }
     }
 }
@@ -89,7 +119,15 @@ impl error::Error for Error {
             | EmptyBatch
             | WrongBatchResponseSize
             | BatchDuplicateResponseId(_)
-            | WrongBatchResponseId(_) => None,
+            | WrongBatchResponseId(_)
+            | MissingBatchResponse(_)
+            | CircuitOpen
+            | MissingVersion
+            | ResultAndError
+            | NoResultOrError
+            | ReservedErrorCode(_)
+            | InvalidResponseId(_)
+            | IdTypeMismatch(_) => None,
             Transport(ref e) => Some(&**e),
             Json(ref e) => Some(e),
         }
@@ -0,0 +1,360 @@
+//! # Failover transport
+//!
+//! A [`Transport`] spreading calls over an ordered list of redundant endpoints: calls go to the
+//! first healthy endpoint, and fail over to the next one when an endpoint fails with a
+//! transport error, or when its circuit breaker (see [`crate::circuit_breaker`]) is open. RPC
+//! errors are returned as-is, since they come from a working server.
+//!
+//! An endpoint is marked unhealthy after a number of consecutive failures and is skipped from
+//! then on. Every probe interval, it is tried again with the next call going through the
+//! transport, and becomes healthy again as soon as a call succeeds.
+//!
+
+use std::cell::Cell;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use std::{error, fmt};
+
+use crate::client::{ReadBody, Transport};
+use crate::{Request, Response};
+
+/// Error that can occur while using the failover transport.
+#[derive(Debug)]
+pub enum Error {
+    /// The transport was built without any endpoint.
+    NoEndpoints,
+    /// Every endpoint failed, with the given errors in the order they were tried.
+    AllFailed(Vec<crate::Error>),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match *self {
+            Error::NoEndpoints => f.write_str("no endpoints to send the request to"),
+            Error::AllFailed(ref errors) => {
+                write!(f, "all {} endpoints failed", errors.len())?;
+                for (i, e) in errors.iter().enumerate() {
+                    let sep = if i == 0 {
+                        ":"
+                    } else {
+                        ";"
+                    };
+                    write!(f, "{} {}", sep, e)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            Error::NoEndpoints => None,
+            Error::AllFailed(ref errors) => errors.last().map(|e| e as _),
+        }
+    }
+}
+
+impl From<Error> for crate::Error {
+    fn from(e: Error) -> crate::Error {
+        crate::Error::Transport(Box::new(e))
+    }
+}
+
+/// Health bookkeeping of an endpoint.
+#[derive(Debug, Default)]
+struct Health {
+    /// Number of failures since the last success.
+    consecutive_failures: usize,
+    /// When the endpoint was last tried while unhealthy, or marked unhealthy; [`None`] while
+    /// it's healthy.
+    unhealthy_since: Option<Instant>,
+}
+
+struct Endpoint {
+    transport: Box<dyn Transport>,
+    health: Mutex<Health>,
+}
+
+/// A transport failing over between several endpoints, see the [module docs](self).
+pub struct FailoverTransport {
+    endpoints: Vec<Endpoint>,
+    failure_threshold: usize,
+    probe_interval: Duration,
+}
+
+impl FailoverTransport {
+    /// Returns a builder for [`FailoverTransport`].
+    pub fn builder() -> Builder {
+        Builder::new()
+    }
+
+    /// Returns whether each endpoint is currently considered healthy, in order.
+    pub fn endpoint_health(&self) -> Vec<bool> {
+        self.endpoints
+            .iter()
+            .map(|e| e.health.lock().expect("poisoned mutex").unhealthy_since.is_none())
+            .collect()
+    }
+
+    /// Sends a call to the first endpoint that doesn't fail with a transport error.
+    ///
+    /// Healthy endpoints, and unhealthy endpoints which are due a probe, are tried in order.
+    /// If all of them failed, the remaining unhealthy endpoints are tried as a last resort.
+    /// Failed calls are only sent to the next endpoint while `may_fail_over` returns true.
+    fn send<R, F>(&self, may_fail_over: &dyn Fn() -> bool, mut call: F) -> Result<R, crate::Error>
+    where
+        F: FnMut(&dyn Transport) -> Result<R, crate::Error>,
+    {
+        if self.endpoints.is_empty() {
+            return Err(Error::NoEndpoints.into());
+        }
+
+        let now = Instant::now();
+        let (eligible, skipped): (Vec<&Endpoint>, Vec<&Endpoint>) = self
+            .endpoints
+            .iter()
+            .partition(|e| match e.health.lock().expect("poisoned mutex").unhealthy_since {
+                None => true,
+                Some(since) => now.duration_since(since) >= self.probe_interval,
+            });
+
+        let mut errors = vec![];
+        for endpoint in eligible.into_iter().chain(skipped) {
+            match call(&*endpoint.transport) {
+                Err(e @ crate::Error::Transport(_)) | Err(e @ crate::Error::CircuitOpen) => {
+                    self.record_failure(endpoint);
+                    if !may_fail_over() {
+                        return Err(e);
+                    }
+                    errors.push(e);
+                }
+                result => {
+                    self.record_success(endpoint);
+                    return result;
+                }
+            }
+        }
+        Err(Error::AllFailed(errors).into())
+    }
+
+    fn record_success(&self, endpoint: &Endpoint) {
+        let mut health = endpoint.health.lock().expect("poisoned mutex");
+        health.consecutive_failures = 0;
+        health.unhealthy_since = None;
+    }
+
+    fn record_failure(&self, endpoint: &Endpoint) {
+        let mut health = endpoint.health.lock().expect("poisoned mutex");
+        health.consecutive_failures += 1;
+        if health.consecutive_failures >= self.failure_threshold {
+            // Also restarts the probe interval for endpoints that were already unhealthy.
+            health.unhealthy_since = Some(Instant::now());
+        }
+    }
+}
+
+impl Transport for FailoverTransport {
+    fn send_request(&self, req: Request) -> Result<Response, crate::Error> {
+        self.send(&|| true, |tp| tp.send_request(req.clone()))
+    }
+
+    fn send_request_streaming(
+        &self,
+        req: Request,
+        read_body: &mut ReadBody,
+    ) -> Result<(), crate::Error> {
+        // The body can only be handed over once, so a call failing after that doesn't fail over.
+        let body_read = Cell::new(false);
+        self.send(&|| !body_read.get(), |tp| {
+            tp.send_request_streaming(req.clone(), &mut |body| {
+                body_read.set(true);
+                read_body(body)
+            })
+        })
+    }
+
+    fn send_batch(&self, reqs: &[Request]) -> Result<Vec<Response>, crate::Error> {
+        self.send(&|| true, |tp| tp.send_batch(reqs))
+    }
+
+    fn send_notification(&self, req: Request) -> Result<(), crate::Error> {
+        self.send(&|| true, |tp| tp.send_notification(req.clone()))
+    }
+
+    fn fmt_target(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "failover(")?;
+        for (i, endpoint) in self.endpoints.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            endpoint.transport.fmt_target(f)?;
+        }
+        write!(f, ")")
+    }
+}
+
+impl fmt::Debug for FailoverTransport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "jsonrpc::failover::FailoverTransport(")?;
+        self.fmt_target(f)?;
+        write!(f, ")")
+    }
+}
+
+/// Builder for [`FailoverTransport`].
+pub struct Builder {
+    tp: FailoverTransport,
+}
+
+impl Builder {
+    /// Constructs a new [`Builder`] without endpoints, marking endpoints unhealthy after 3
+    /// consecutive failures and probing them every 30 seconds.
+    pub fn new() -> Builder {
+        Builder {
+            tp: FailoverTransport {
+                endpoints: vec![],
+                failure_threshold: 3,
+                probe_interval: Duration::from_secs(30),
+            },
+        }
+    }
+
+    /// Adds an endpoint, with lower priority than the ones added before.
+    pub fn endpoint<T: Transport>(mut self, transport: T) -> Self {
+        self.tp.endpoints.push(Endpoint {
+            transport: Box::new(transport),
+            health: Mutex::new(Health::default()),
+        });
+        self
+    }
+
+    /// Sets the number of consecutive failures after which an endpoint is marked unhealthy.
+    pub fn failure_threshold(mut self, failures: usize) -> Self {
+        self.tp.failure_threshold = failures.max(1);
+        self
+    }
+
+    /// Sets how long unhealthy endpoints are skipped before being probed again.
+    pub fn probe_interval(mut self, interval: Duration) -> Self {
+        self.tp.probe_interval = interval;
+        self
+    }
+
+    /// Builds the final [`FailoverTransport`].
+    pub fn build(self) -> FailoverTransport {
+        self.tp
+    }
+}
+
+impl Default for Builder {
+    fn default() -> Self {
+        Builder::new()
+    }
+}
+
+impl fmt::Debug for Builder {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "jsonrpc::failover::Builder(")?;
+        self.tp.fmt_target(f)?;
+        write!(f, ")")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::Ordering;
+    use std::sync::Arc;
+    use std::thread;
+
+    use super::*;
+    use crate::client::tests::Node;
+    use crate::Client;
+
+    #[test]
+    fn failover_and_probe() {
+        let (a, b) = (Arc::new(Node::named("a")), Arc::new(Node::named("b")));
+        let tp = Arc::new(
+            FailoverTransport::builder()
+                .endpoint(a.clone())
+                .endpoint(b.clone())
+                .failure_threshold(2)
+                .probe_interval(Duration::from_millis(50))
+                .build(),
+        );
+        let client = Client::with_transport(tp.clone());
+        assert_eq!(format!("{:?}", client), "jsonrpc::Client(failover(a, b))");
+
+        assert_eq!(client.call::<String>("test", &[]).unwrap(), "a");
+
+        // Calls fail over to `b` until `a` reaches the failure threshold.
+        a.down.store(true, Ordering::SeqCst);
+        assert_eq!(client.call::<String>("test", &[]).unwrap(), "b");
+        assert_eq!(tp.endpoint_health(), [true, true]);
+        assert_eq!(client.call_streaming::<String>("test", &[]).unwrap(), "b");
+        assert_eq!(tp.endpoint_health(), [false, true]);
+        assert_eq!(b.streamed.load(Ordering::SeqCst), 1);
+        assert_eq!(a.calls(), 3);
+
+        // `a` is now skipped.
+        assert_eq!(client.call::<String>("test", &[]).unwrap(), "b");
+        client.notify("test", &[]).unwrap();
+        assert_eq!(a.calls(), 3);
+
+        // After the probe interval, `a` is tried again and comes back.
+        a.down.store(false, Ordering::SeqCst);
+        thread::sleep(Duration::from_millis(60));
+        assert_eq!(client.call::<String>("test", &[]).unwrap(), "a");
+        assert_eq!(tp.endpoint_health(), [true, true]);
+        assert_eq!(a.calls(), 4);
+    }
+
+    #[test]
+    fn rpc_errors_and_last_resort() {
+        let (a, b) = (Arc::new(Node::named("a")), Arc::new(Node::named("b")));
+        let tp = Arc::new(
+            FailoverTransport::builder()
+                .endpoint(a.clone())
+                .endpoint(b.clone())
+                .failure_threshold(1)
+                .build(),
+        );
+        let client = Client::with_transport(tp.clone());
+
+        // RPC errors are not a reason to fail over.
+        a.rpc_error.store(true, Ordering::SeqCst);
+        match client.call::<String>("test", &[]) {
+            Err(crate::Error::Rpc(ref e)) if e.code == -32603 => {}
+            r => panic!("expected RPC error, got {:?}", r),
+        }
+        assert_eq!(b.calls(), 0);
+        a.rpc_error.store(false, Ordering::SeqCst);
+
+        // With every endpoint down, all of them are tried.
+        a.down.store(true, Ordering::SeqCst);
+        b.down.store(true, Ordering::SeqCst);
+        match client.call::<String>("test", &[]) {
+            Err(crate::Error::Transport(e)) => match e.downcast_ref() {
+                Some(Error::AllFailed(errors)) => assert_eq!(errors.len(), 2),
+                _ => panic!("expected all endpoints to fail, got {:?}", e),
+            },
+            r => panic!("expected transport error, got {:?}", r),
+        }
+        assert_eq!(tp.endpoint_health(), [false, false]);
+
+        // Unhealthy endpoints are still used when there is nothing else.
+        b.down.store(false, Ordering::SeqCst);
+        let batch = [client.build_request("test", &[])];
+        assert_eq!(client.send_batch(&batch).unwrap().len(), 1);
+        assert_eq!(tp.endpoint_health(), [false, true]);
+
+        let empty = FailoverTransport::builder().build();
+        match Client::with_transport(empty).call::<String>("test", &[]) {
+            Err(crate::Error::Transport(e)) => {
+                assert_eq!(e.to_string(), "no endpoints to send the request to")
+            }
+            r => panic!("expected transport error, got {:?}", r),
+        }
+    }
+}
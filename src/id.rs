@@ -0,0 +1,187 @@
+//! # Request identifiers
+//!
+//! Strategies for generating the `id` of the requests built by a [`crate::Client`]. By default a
+//! client numbers its requests sequentially, starting at 1; a different [`IdGenerator`] can be
+//! configured with [`crate::client::Builder::id_generator`].
+//!
+
+use std::fmt;
+use std::sync::atomic;
+
+use serde_json;
+
+use crate::util;
+
+/// A source of identifiers for requests.
+///
+/// Identifiers only need to be unique among the requests that are in flight at the same time,
+/// but generators producing globally unique identifiers make it easier to correlate requests
+/// across clients in server logs.
+pub trait IdGenerator: Send + Sync + 'static {
+    /// Returns the identifier for the next request.
+    fn next_id(&self) -> serde_json::Value;
+}
+
+impl<F> IdGenerator for F
+where
+    F: Fn() -> serde_json::Value + Send + Sync + 'static,
+{
+    fn next_id(&self) -> serde_json::Value {
+        self()
+    }
+}
+
+/// Numbers requests sequentially, e.g. `1`, `2`, `3`...
+///
+/// This is the default strategy of [`crate::Client`].
+#[derive(Debug)]
+pub struct SequentialIds {
+    next: atomic::AtomicUsize,
+}
+
+impl SequentialIds {
+    /// Creates a generator starting at 1.
+    pub fn new() -> SequentialIds {
+        SequentialIds::starting_at(1)
+    }
+
+    /// Creates a generator starting at `first`.
+    pub fn starting_at(first: usize) -> SequentialIds {
+        SequentialIds {
+            next: atomic::AtomicUsize::new(first),
+        }
+    }
+}
+
+impl Default for SequentialIds {
+    fn default() -> Self {
+        SequentialIds::new()
+    }
+}
+
+impl IdGenerator for SequentialIds {
+    fn next_id(&self) -> serde_json::Value {
+        serde_json::Value::from(self.next.fetch_add(1, atomic::Ordering::Relaxed))
+    }
+}
+
+/// Generates random strings formatted like version 4 UUIDs, e.g.
+/// `"6f1c0b5e-53d2-4a8e-9b07-3c2e1f4d5a69"`.
+///
+/// The randomness comes from the standard library's hash map seeds, which is plenty to avoid
+/// collisions between clients, but is not suitable for anything security related.
+#[derive(Debug, Default)]
+pub struct RandomIds;
+
+impl RandomIds {
+    /// Creates a new random generator.
+    pub fn new() -> RandomIds {
+        RandomIds
+    }
+}
+
+impl IdGenerator for RandomIds {
+    fn next_id(&self) -> serde_json::Value {
+        let hi = util::random_u64();
+        let lo = util::random_u64();
+        // Set the version (4) and variant (RFC 4122) bits.
+        let hi = (hi & !0xf000) | 0x4000;
+        let lo = (lo & !(0xc << 60)) | (0x8 << 60);
+        serde_json::Value::from(format!(
+            "{:08x}-{:04x}-{:04x}-{:04x}-{:012x}",
+            hi >> 32,
+            (hi >> 16) & 0xffff,
+            hi & 0xffff,
+            lo >> 48,
+            lo & 0xffff_ffff_ffff,
+        ))
+    }
+}
+
+/// Numbers requests sequentially behind a fixed prefix, e.g. `"indexer-1"`, `"indexer-2"`...
+pub struct PrefixedIds {
+    prefix: String,
+    next: atomic::AtomicUsize,
+}
+
+impl PrefixedIds {
+    /// Creates a generator with the given prefix, starting at 1.
+    pub fn new<S: Into<String>>(prefix: S) -> PrefixedIds {
+        PrefixedIds {
+            prefix: prefix.into(),
+            next: atomic::AtomicUsize::new(1),
+        }
+    }
+
+    /// Creates a generator with a random prefix, so that the identifiers of different clients
+    /// (or processes) don't collide.
+    pub fn with_random_prefix() -> PrefixedIds {
+        PrefixedIds::new(format!("{:08x}", util::random_u64() as u32))
+    }
+
+    /// Returns the prefix of the generated identifiers.
+    pub fn prefix(&self) -> &str {
+        &self.prefix
+    }
+}
+
+impl IdGenerator for PrefixedIds {
+    fn next_id(&self) -> serde_json::Value {
+        let n = self.next.fetch_add(1, atomic::Ordering::Relaxed);
+        serde_json::Value::from(format!("{}-{}", self.prefix, n))
+    }
+}
+
+impl fmt::Debug for PrefixedIds {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "PrefixedIds({:?})", self.prefix)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sequential() {
+        let ids = SequentialIds::new();
+        assert_eq!(ids.next_id(), serde_json::Value::from(1));
+        assert_eq!(ids.next_id(), serde_json::Value::from(2));
+
+        let ids = SequentialIds::starting_at(1000);
+        assert_eq!(ids.next_id(), serde_json::Value::from(1000));
+    }
+
+    #[test]
+    fn random() {
+        let ids = RandomIds::new();
+        let id1 = ids.next_id();
+        let id2 = ids.next_id();
+        assert_ne!(id1, id2);
+
+        let id = id1.as_str().unwrap();
+        assert_eq!(id.len(), 36);
+        let groups: Vec<_> = id.split('-').map(str::len).collect();
+        assert_eq!(groups, [8, 4, 4, 4, 12]);
+        assert_eq!(&id[14..15], "4");
+        assert!("89ab".contains(&id[19..20]));
+    }
+
+    #[test]
+    fn prefixed() {
+        let ids = PrefixedIds::new("indexer");
+        assert_eq!(ids.next_id(), serde_json::Value::from("indexer-1"));
+        assert_eq!(ids.next_id(), serde_json::Value::from("indexer-2"));
+
+        let a = PrefixedIds::with_random_prefix();
+        let b = PrefixedIds::with_random_prefix();
+        assert_ne!(a.prefix(), b.prefix());
+        assert_eq!(a.prefix().len(), 8);
+    }
+
+    #[test]
+    fn closure() {
+        let ids = || serde_json::Value::from("fixed");
+        assert_eq!(IdGenerator::next_id(&ids), serde_json::Value::from("fixed"));
+    }
+}
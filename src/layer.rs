@@ -0,0 +1,315 @@
+//! # Transport layers
+//!
+//! Support for wrapping a [`Transport`] with cross-cutting behavior, such as logging, metrics,
+//! retries or refreshing credentials, independently of the underlying transport.
+//!
+//! A [`Layer`] turns a transport into another transport. Layers are stacked onto the transport
+//! of a client with [`crate::client::Builder::layer`]; the layer added last is the outermost
+//! one and sees every request first.
+//!
+//! For behavior that only needs to look at (or alter) requests and responses on their way
+//! through, implementing [`Middleware`] is less work than writing a full [`Transport`].
+//!
+
+use std::fmt;
+use std::sync::Arc;
+
+use crate::client::{ReadBody, Transport};
+use crate::error::Error;
+use crate::{Request, Response};
+
+/// Wraps a transport into another transport.
+pub trait Layer<T: Transport> {
+    /// The wrapping transport.
+    type Transport: Transport;
+
+    /// Wraps `inner` into a new transport.
+    fn layer(&self, inner: T) -> Self::Transport;
+}
+
+/// The layer which leaves the transport unchanged.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Identity;
+
+impl<T: Transport> Layer<T> for Identity {
+    type Transport = T;
+
+    fn layer(&self, inner: T) -> T {
+        inner
+    }
+}
+
+/// Two layers applied one after the other; `inner` wraps the transport, then `outer` wraps the
+/// result.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Stack<Inner, Outer> {
+    inner: Inner,
+    outer: Outer,
+}
+
+impl<Inner, Outer> Stack<Inner, Outer> {
+    /// Creates a new stack of the two layers.
+    pub fn new(inner: Inner, outer: Outer) -> Self {
+        Stack {
+            inner,
+            outer,
+        }
+    }
+}
+
+impl<T, Inner, Outer> Layer<T> for Stack<Inner, Outer>
+where
+    T: Transport,
+    Inner: Layer<T>,
+    Outer: Layer<Inner::Transport>,
+{
+    type Transport = Outer::Transport;
+
+    fn layer(&self, inner: T) -> Self::Transport {
+        self.outer.layer(self.inner.layer(inner))
+    }
+}
+
+/// A layer built from a closure, see [`layer_fn`].
+#[derive(Clone, Copy, Debug)]
+pub struct LayerFn<F> {
+    f: F,
+}
+
+/// Creates a layer from a closure wrapping a transport.
+pub fn layer_fn<F>(f: F) -> LayerFn<F> {
+    LayerFn {
+        f,
+    }
+}
+
+impl<T, U, F> Layer<T> for LayerFn<F>
+where
+    T: Transport,
+    U: Transport,
+    F: Fn(T) -> U,
+{
+    type Transport = U;
+
+    fn layer(&self, inner: T) -> U {
+        (self.f)(inner)
+    }
+}
+
+/// Hooks around the calls made through a transport.
+///
+/// Every method defaults to forwarding the call to the `next` transport unchanged, so
+/// implementations only need to override the calls they are interested in. Use
+/// [`middleware`] to turn a middleware into a [`Layer`].
+///
+/// Streaming calls, see [`crate::Client::call_streaming`], go through
+/// [`Middleware::send_request_streaming`] rather than [`Middleware::send_request`], so
+/// middlewares looking at every request should override both.
+pub trait Middleware: Send + Sync + 'static {
+    /// Sends a request through the `next` transport.
+    fn send_request(&self, req: Request, next: &dyn Transport) -> Result<Response, Error> {
+        next.send_request(req)
+    }
+
+    /// Sends a request through the `next` transport, handing the body of the response over to
+    /// `read_body`.
+    fn send_request_streaming(
+        &self,
+        req: Request,
+        read_body: &mut ReadBody,
+        next: &dyn Transport,
+    ) -> Result<(), Error> {
+        next.send_request_streaming(req, read_body)
+    }
+
+    /// Sends a batch of requests through the `next` transport.
+    fn send_batch(&self, reqs: &[Request], next: &dyn Transport) -> Result<Vec<Response>, Error> {
+        next.send_batch(reqs)
+    }
+
+    /// Sends a notification through the `next` transport.
+    fn send_notification(&self, req: Request, next: &dyn Transport) -> Result<(), Error> {
+        next.send_notification(req)
+    }
+}
+
+/// A layer applying a [`Middleware`], see [`middleware`].
+pub struct MiddlewareLayer<M> {
+    middleware: Arc<M>,
+}
+
+/// Creates a layer applying the given middleware.
+pub fn middleware<M: Middleware>(middleware: M) -> MiddlewareLayer<M> {
+    MiddlewareLayer {
+        middleware: Arc::new(middleware),
+    }
+}
+
+impl<M> Clone for MiddlewareLayer<M> {
+    fn clone(&self) -> Self {
+        MiddlewareLayer {
+            middleware: self.middleware.clone(),
+        }
+    }
+}
+
+impl<M: Middleware, T: Transport> Layer<T> for MiddlewareLayer<M> {
+    type Transport = MiddlewareTransport<M, T>;
+
+    fn layer(&self, inner: T) -> Self::Transport {
+        MiddlewareTransport {
+            middleware: self.middleware.clone(),
+            inner,
+        }
+    }
+}
+
+/// A transport whose calls go through a [`Middleware`].
+pub struct MiddlewareTransport<M, T> {
+    middleware: Arc<M>,
+    inner: T,
+}
+
+impl<M, T> MiddlewareTransport<M, T> {
+    /// Returns a reference to the wrapped transport.
+    pub fn inner(&self) -> &T {
+        &self.inner
+    }
+}
+
+impl<M: Middleware, T: Transport> Transport for MiddlewareTransport<M, T> {
+    fn send_request(&self, req: Request) -> Result<Response, Error> {
+        self.middleware.send_request(req, &self.inner)
+    }
+
+    fn send_request_streaming(&self, req: Request, read_body: &mut ReadBody) -> Result<(), Error> {
+        self.middleware.send_request_streaming(req, read_body, &self.inner)
+    }
+
+    fn send_batch(&self, reqs: &[Request]) -> Result<Vec<Response>, Error> {
+        self.middleware.send_batch(reqs, &self.inner)
+    }
+
+    fn send_notification(&self, req: Request) -> Result<(), Error> {
+        self.middleware.send_notification(req, &self.inner)
+    }
+
+    fn fmt_target(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.inner.fmt_target(f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use super::*;
+    use std::sync::atomic::Ordering;
+
+    use crate::client::tests::{EchoTransport, Node};
+    use crate::Client;
+
+    /// Records the calls it sees, tagged with its name.
+    struct Log {
+        name: &'static str,
+        log: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl Middleware for Log {
+        fn send_request(&self, req: Request, next: &dyn Transport) -> Result<Response, Error> {
+            self.log.lock().unwrap().push(format!("{} {}", self.name, req.method));
+            next.send_request(req)
+        }
+
+        fn send_batch(
+            &self,
+            reqs: &[Request],
+            next: &dyn Transport,
+        ) -> Result<Vec<Response>, Error> {
+            self.log.lock().unwrap().push(format!("{} batch of {}", self.name, reqs.len()));
+            next.send_batch(reqs)
+        }
+    }
+
+    /// Counts the streaming calls it sees.
+    struct CountStreaming(Arc<Mutex<usize>>);
+
+    impl Middleware for CountStreaming {
+        fn send_request_streaming(
+            &self,
+            req: Request,
+            read_body: &mut ReadBody,
+            next: &dyn Transport,
+        ) -> Result<(), Error> {
+            *self.0.lock().unwrap() += 1;
+            next.send_request_streaming(req, read_body)
+        }
+    }
+
+    /// Rejects every request.
+    struct Reject;
+    impl Middleware for Reject {
+        fn send_request(&self, _: Request, _: &dyn Transport) -> Result<Response, Error> {
+            Err(Error::EmptyBatch)
+        }
+    }
+
+    #[test]
+    fn stacked_middleware() {
+        let log = Arc::new(Mutex::new(vec![]));
+        let client = Client::builder(EchoTransport)
+            .layer(middleware(Log {
+                name: "inner",
+                log: log.clone(),
+            }))
+            .layer(middleware(Log {
+                name: "outer",
+                log: log.clone(),
+            }))
+            .build();
+
+        assert_eq!(client.call::<u64>("test", &[]).unwrap(), 1);
+        let batch = [client.build_request("a", &[]), client.build_request("b", &[])];
+        client.send_batch(&batch).unwrap();
+        client.notify("test", &[]).unwrap();
+
+        assert_eq!(
+            *log.lock().unwrap(),
+            ["outer test", "inner test", "outer batch of 2", "inner batch of 2"]
+        );
+        assert_eq!(format!("{:?}", client), "jsonrpc::Client(echo)");
+    }
+
+    #[test]
+    fn composed_layers() {
+        let log = Arc::new(Mutex::new(vec![]));
+        let stack = Stack::new(
+            middleware(Log {
+                name: "inner",
+                log: log.clone(),
+            }),
+            Stack::new(Identity, layer_fn(|t| middleware(Reject).layer(t))),
+        );
+        let client = Client::builder(EchoTransport).layer(stack).build();
+
+        match client.call::<u64>("test", &[]) {
+            Err(Error::EmptyBatch) => {}
+            r => panic!("expected rejected request, got {:?}", r),
+        }
+        // The inner layer never saw the rejected request, but sees the batch.
+        let batch = [client.build_request("a", &[])];
+        client.send_batch(&batch).unwrap();
+        assert_eq!(*log.lock().unwrap(), ["inner batch of 1"]);
+    }
+
+    #[test]
+    fn streaming() {
+        let (node, count) = (Arc::new(Node::default()), Arc::new(Mutex::new(0)));
+        let client =
+            Client::builder(node.clone()).layer(middleware(CountStreaming(count.clone()))).build();
+
+        assert_eq!(client.call_streaming::<u64>("test", &[]).unwrap(), 1);
+        assert_eq!(*count.lock().unwrap(), 1);
+        assert_eq!(node.streamed.load(Ordering::SeqCst), 1);
+    }
+}
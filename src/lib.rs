@@ -32,20 +32,141 @@ pub extern crate serde_json;
 #[cfg(feature = "base64-compat")]
 pub extern crate base64;
 
+/// Logs a diagnostic event of a transport at debug level, formatted from the arguments as for
+/// [`format!`], along with the calls being made, see the `trace` module.
+#[cfg(any(
+    feature = "simple_http",
+    feature = "simple_tcp",
+    all(feature = "simple_uds", not(windows))
+))]
+macro_rules! trace_event {
+    ($($arg:tt)+) => {
+        #[cfg(feature = "log")]
+        {
+            log::debug!("[{}] {}", $crate::trace::current(), format_args!($($arg)+));
+        }
+    };
+}
+
+/// Enters a tracing span for the requests `$reqs` until the end of the enclosing block, if
+/// debug logging is enabled.
+#[cfg(any(
+    feature = "simple_http",
+    feature = "simple_tcp",
+    all(feature = "simple_uds", not(windows))
+))]
+macro_rules! trace_span {
+    ($reqs:expr) => {
+        #[cfg(feature = "log")]
+        let _span = if log::log_enabled!(log::Level::Debug) {
+            Some($crate::trace::Span::new($reqs).enter())
+        } else {
+            None
+        };
+    };
+}
+
+/// Wraps the closure `$f`, to be run on another thread, so that it runs in a tracing span for
+/// the requests `$reqs`.
+#[cfg(all(
+    feature = "async",
+    any(
+        feature = "simple_http",
+        feature = "simple_tcp",
+        all(feature = "simple_uds", not(windows))
+    )
+))]
+macro_rules! trace_in_span {
+    ($reqs:expr, $f:expr) => {{
+        #[cfg(feature = "log")]
+        let f = {
+            let span = if log::log_enabled!(log::Level::Debug) {
+                Some($crate::trace::Span::new($reqs))
+            } else {
+                None
+            };
+            let f = $f;
+            move || {
+                let _span = span.map($crate::trace::Span::enter);
+                f()
+            }
+        };
+        #[cfg(not(feature = "log"))]
+        let f = $f;
+        f
+    }};
+}
+
+pub mod api;
+pub mod auto_batch;
+pub mod balance;
+pub mod batch;
+#[cfg(all(
+    feature = "async",
+    any(
+        feature = "simple_http",
+        feature = "simple_tcp",
+        all(feature = "simple_uds", not(windows))
+    )
+))]
+mod blocking;
+pub mod cache;
+pub mod cassette;
+pub mod circuit_breaker;
 pub mod client;
 pub mod error;
+pub mod failover;
+pub mod id;
+pub mod layer;
+pub mod metrics;
+#[cfg(feature = "testing")]
+pub mod mock;
+#[cfg(any(feature = "simple_tcp", all(feature = "simple_uds", not(windows))))]
+pub mod pubsub;
+pub mod retry;
+#[cfg(any(
+    feature = "simple_http_server",
+    feature = "simple_tcp_server",
+    all(feature = "simple_uds_server", not(windows))
+))]
+mod serve;
+pub mod server;
+pub mod stream;
+#[cfg(all(
+    feature = "log",
+    any(
+        feature = "simple_http",
+        feature = "simple_tcp",
+        all(feature = "simple_uds", not(windows))
+    )
+))]
+mod trace;
 mod util;
 
+#[cfg(feature = "async")]
+pub mod async_client;
+
 #[cfg(feature = "simple_http")]
 pub mod simple_http;
 
+#[cfg(feature = "simple_http_server")]
+pub mod simple_http_server;
+
+#[cfg(feature = "simple_tcp_server")]
+pub mod simple_tcp_server;
+
 #[cfg(feature = "simple_tcp")]
 pub mod simple_tcp;
 
 #[cfg(all(feature = "simple_uds", not(windows)))]
 pub mod simple_uds;
 
+#[cfg(all(feature = "simple_uds_server", not(windows)))]
+pub mod simple_uds_server;
+
 // Re-export error type
+#[cfg(feature = "async")]
+pub use crate::async_client::{AsyncClient, AsyncTransport};
 pub use crate::client::{Client, Transport};
 pub use crate::error::Error;
 
@@ -72,19 +193,141 @@ pub fn arg<T: serde::Serialize>(arg: T) -> Box<RawValue> {
     }
 }
 
+/// Parameters of a JSONRPC request.
+///
+/// JSON-RPC 2.0 allows parameters to be passed either by position, as an array, or by name, as
+/// an object. The `params` member may also be left out entirely.
+///
+/// Positional parameters can be converted from slices and arrays of [`Box<RawValue>`], e.g. as
+/// produced by [`arg`], and named parameters from slices and arrays of `(&str, Box<RawValue>)`
+/// pairs.
+#[derive(Debug, Clone, Copy)]
+pub enum Params<'a> {
+    /// No parameters; the `params` member is omitted from the request.
+    None,
+    /// Parameters by position, serialized as a JSON array.
+    ByPosition(&'a [Box<RawValue>]),
+    /// Parameters by name, serialized as a JSON object.
+    ByName(&'a [(&'a str, Box<RawValue>)]),
+}
+
+impl<'a> Params<'a> {
+    /// Returns whether the `params` member should be omitted.
+    pub fn is_none(&self) -> bool {
+        match *self {
+            Params::None => true,
+            _ => false,
+        }
+    }
+}
+
+impl<'a> Default for Params<'a> {
+    fn default() -> Self {
+        Params::None
+    }
+}
+
+impl<'a> Serialize for Params<'a> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeMap;
+
+        match *self {
+            Params::None => serializer.serialize_unit(),
+            Params::ByPosition(params) => params.serialize(serializer),
+            Params::ByName(params) => {
+                let mut map = serializer.serialize_map(Some(params.len()))?;
+                for (name, value) in params {
+                    map.serialize_entry(name, value)?;
+                }
+                map.end()
+            }
+        }
+    }
+}
+
+impl<'a> From<&'a [Box<RawValue>]> for Params<'a> {
+    fn from(params: &'a [Box<RawValue>]) -> Self {
+        Params::ByPosition(params)
+    }
+}
+
+impl<'a> From<&'a Vec<Box<RawValue>>> for Params<'a> {
+    fn from(params: &'a Vec<Box<RawValue>>) -> Self {
+        Params::ByPosition(params)
+    }
+}
+
+impl<'a> From<&'a [(&'a str, Box<RawValue>)]> for Params<'a> {
+    fn from(params: &'a [(&'a str, Box<RawValue>)]) -> Self {
+        Params::ByName(params)
+    }
+}
+
+impl<'a> From<&'a Vec<(&'a str, Box<RawValue>)>> for Params<'a> {
+    fn from(params: &'a Vec<(&'a str, Box<RawValue>)>) -> Self {
+        Params::ByName(params)
+    }
+}
+
+macro_rules! impl_params_from_array {
+    ($($n:expr),*) => {
+        $(
+            impl<'a> From<&'a [Box<RawValue>; $n]> for Params<'a> {
+                fn from(params: &'a [Box<RawValue>; $n]) -> Self {
+                    Params::ByPosition(&params[..])
+                }
+            }
+
+            impl<'a> From<&'a [(&'a str, Box<RawValue>); $n]> for Params<'a> {
+                fn from(params: &'a [(&'a str, Box<RawValue>); $n]) -> Self {
+                    Params::ByName(&params[..])
+                }
+            }
+        )*
+    };
+}
+impl_params_from_array!(
+    1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23, 24, 25, 26,
+    27, 28, 29, 30, 31, 32
+);
+
+// Only implemented for positional parameters so that `&[]` is unambiguous.
+impl<'a> From<&'a [Box<RawValue>; 0]> for Params<'a> {
+    fn from(params: &'a [Box<RawValue>; 0]) -> Self {
+        Params::ByPosition(&params[..])
+    }
+}
+
 #[derive(Debug, Clone, Serialize)]
 /// A JSONRPC request object.
 pub struct Request<'a> {
     /// The name of the RPC call.
     pub method: &'a str,
     /// Parameters to the RPC call.
-    pub params: &'a [Box<RawValue>],
+    #[serde(skip_serializing_if = "Params::is_none")]
+    pub params: Params<'a>,
     /// Identifier for this Request, which should appear in the response.
-    pub id: serde_json::Value,
-    /// jsonrpc field, MUST be "2.0".
+    ///
+    /// Requests without an identifier are notifications, to which the server does not reply.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<serde_json::Value>,
+    /// jsonrpc field, MUST be "2.0", or `None` for JSON-RPC 1.0.
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub jsonrpc: Option<&'a str>,
 }
 
+impl<'a> Request<'a> {
+    /// Returns whether this request is a notification, i.e. has no `id`, or a `null` one in
+    /// JSON-RPC 1.0.
+    pub fn is_notification(&self) -> bool {
+        match self.id {
+            None => true,
+            Some(serde_json::Value::Null) => self.jsonrpc.is_none(),
+            Some(_) => false,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Deserialize, Serialize)]
 /// A JSONRPC response object.
 pub struct Response {
@@ -127,6 +370,31 @@ impl Response {
     }
 }
 
+/// Serializes a response with the members it most likely had when received: both `result` and
+/// `error` in JSON-RPC 1.0, but only one of them in JSON-RPC 2.0.
+pub(crate) struct ResponseBody<'a>(pub(crate) &'a Response);
+
+impl<'a> Serialize for ResponseBody<'a> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+
+        let response = self.0;
+        let v1 = response.jsonrpc.is_none();
+        let mut s = serializer.serialize_struct("Response", 4)?;
+        if v1 || response.error.is_none() {
+            s.serialize_field("result", &response.result)?;
+        }
+        if v1 || response.error.is_some() {
+            s.serialize_field("error", &response.error)?;
+        }
+        s.serialize_field("id", &response.id)?;
+        if let Some(ref jsonrpc) = response.jsonrpc {
+            s.serialize_field("jsonrpc", jsonrpc)?;
+        }
+        s.end()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -198,6 +466,59 @@ mod tests {
         assert_eq!(batch_response.len(), 5);
     }
 
+    #[test]
+    fn request_params() {
+        let positional = [arg(1), arg("two")];
+        let req = Request {
+            method: "test",
+            params: Params::from(&positional),
+            id: Some(From::from(1)),
+            jsonrpc: Some("2.0"),
+        };
+        assert_eq!(
+            serde_json::to_string(&req).unwrap(),
+            r#"{"method":"test","params":[1,"two"],"id":1,"jsonrpc":"2.0"}"#
+        );
+
+        let named = [("height", arg(1)), ("verbose", arg(true))];
+        let req = Request {
+            params: Params::from(&named),
+            ..req
+        };
+        assert_eq!(
+            serde_json::to_string(&req).unwrap(),
+            r#"{"method":"test","params":{"height":1,"verbose":true},"id":1,"jsonrpc":"2.0"}"#
+        );
+
+        let req = Request {
+            params: Params::None,
+            ..req
+        };
+        assert_eq!(
+            serde_json::to_string(&req).unwrap(),
+            r#"{"method":"test","id":1,"jsonrpc":"2.0"}"#
+        );
+
+        let req = Request {
+            params: Params::from(&[]),
+            ..req
+        };
+        assert_eq!(
+            serde_json::to_string(&req).unwrap(),
+            r#"{"method":"test","params":[],"id":1,"jsonrpc":"2.0"}"#
+        );
+
+        let req = Request {
+            id: None,
+            ..req
+        };
+        assert!(req.is_notification());
+        assert_eq!(
+            serde_json::to_string(&req).unwrap(),
+            r#"{"method":"test","params":[],"jsonrpc":"2.0"}"#
+        );
+    }
+
     #[test]
     fn test_arg() {
         macro_rules! test_arg {
@@ -0,0 +1,564 @@
+//! # Metrics
+//!
+//! Support for collecting metrics about the calls made through a transport. A
+//! [`MetricsTransport`] reports every call to a [`Metrics`] implementation, which can forward
+//! them to any metrics backend. [`InMemoryMetrics`] aggregates them per method, and provides
+//! snapshots of the aggregated values.
+//!
+//! Calls in a batch are reported individually, each with the duration of the whole batch. The
+//! bodies of streamed responses are counted as they are read, responses already parsed by the
+//! transport are measured by serializing them again, which doesn't account for whitespace.
+//!
+
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use serde;
+use serde_json;
+
+use crate::client::{ReadBody, Transport};
+use crate::error::Error;
+use crate::layer::Layer;
+use crate::util::HashableValue;
+use crate::{Request, Response, ResponseBody};
+
+/// The kind of error a call failed with, i.e. the variant of [`Error`] or the code of the RPC
+/// error.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[non_exhaustive]
+pub enum ErrorKind {
+    /// [`Error::Transport`].
+    Transport,
+    /// [`Error::Json`].
+    Json,
+    /// [`Error::Rpc`], or a response holding an error, with the code of the error.
+    Rpc(i32),
+    /// [`Error::NonceMismatch`].
+    NonceMismatch,
+    /// [`Error::VersionMismatch`].
+    VersionMismatch,
+    /// [`Error::EmptyBatch`].
+    EmptyBatch,
+    /// [`Error::WrongBatchResponseSize`].
+    WrongBatchResponseSize,
+    /// [`Error::BatchDuplicateResponseId`].
+    BatchDuplicateResponseId,
+    /// [`Error::WrongBatchResponseId`].
+    WrongBatchResponseId,
+    /// [`Error::MissingBatchResponse`].
+    MissingBatchResponse,
+    /// [`Error::CircuitOpen`].
+    CircuitOpen,
+    /// [`Error::MissingVersion`].
+    MissingVersion,
+    /// [`Error::ResultAndError`].
+    ResultAndError,
+    /// [`Error::NoResultOrError`].
+    NoResultOrError,
+    /// [`Error::ReservedErrorCode`].
+    ReservedErrorCode,
+    /// [`Error::InvalidResponseId`].
+    InvalidResponseId,
+    /// [`Error::IdTypeMismatch`].
+    IdTypeMismatch,
+}
+
+impl ErrorKind {
+    /// Returns the kind of the error.
+    pub fn of(error: &Error) -> ErrorKind {
+        match *error {
+            Error::Transport(_) => ErrorKind::Transport,
+            Error::Json(_) => ErrorKind::Json,
+            Error::Rpc(ref e) => ErrorKind::Rpc(e.code),
+            Error::NonceMismatch => ErrorKind::NonceMismatch,
+            Error::VersionMismatch => ErrorKind::VersionMismatch,
+            Error::EmptyBatch => ErrorKind::EmptyBatch,
+            Error::WrongBatchResponseSize => ErrorKind::WrongBatchResponseSize,
+            Error::BatchDuplicateResponseId(_) => ErrorKind::BatchDuplicateResponseId,
+            Error::WrongBatchResponseId(_) => ErrorKind::WrongBatchResponseId,
+            Error::MissingBatchResponse(_) => ErrorKind::MissingBatchResponse,
+            Error::CircuitOpen => ErrorKind::CircuitOpen,
+            Error::MissingVersion => ErrorKind::MissingVersion,
+            Error::ResultAndError => ErrorKind::ResultAndError,
+            Error::NoResultOrError => ErrorKind::NoResultOrError,
+            Error::ReservedErrorCode(_) => ErrorKind::ReservedErrorCode,
+            Error::InvalidResponseId(_) => ErrorKind::InvalidResponseId,
+            Error::IdTypeMismatch(_) => ErrorKind::IdTypeMismatch,
+        }
+    }
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ErrorKind::Transport => f.write_str("transport"),
+            ErrorKind::Json => f.write_str("json"),
+            ErrorKind::Rpc(code) => write!(f, "rpc({})", code),
+            ErrorKind::NonceMismatch => f.write_str("nonce_mismatch"),
+            ErrorKind::VersionMismatch => f.write_str("version_mismatch"),
+            ErrorKind::EmptyBatch => f.write_str("empty_batch"),
+            ErrorKind::WrongBatchResponseSize => f.write_str("wrong_batch_response_size"),
+            ErrorKind::BatchDuplicateResponseId => f.write_str("batch_duplicate_response_id"),
+            ErrorKind::WrongBatchResponseId => f.write_str("wrong_batch_response_id"),
+            ErrorKind::MissingBatchResponse => f.write_str("missing_batch_response"),
+            ErrorKind::CircuitOpen => f.write_str("circuit_open"),
+            ErrorKind::MissingVersion => f.write_str("missing_version"),
+            ErrorKind::ResultAndError => f.write_str("result_and_error"),
+            ErrorKind::NoResultOrError => f.write_str("no_result_or_error"),
+            ErrorKind::ReservedErrorCode => f.write_str("reserved_error_code"),
+            ErrorKind::InvalidResponseId => f.write_str("invalid_response_id"),
+            ErrorKind::IdTypeMismatch => f.write_str("id_type_mismatch"),
+        }
+    }
+}
+
+/// A call reported to [`Metrics::record`].
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct CallRecord<'a> {
+    /// The method called.
+    pub method: &'a str,
+    /// How long the call took.
+    pub duration: Duration,
+    /// The kind of error the call failed with, if any.
+    pub error: Option<ErrorKind>,
+    /// Size of the serialized request, in bytes.
+    pub request_bytes: usize,
+    /// Size of the response, in bytes, or 0 if there was none.
+    pub response_bytes: usize,
+    /// Whether the call was a notification.
+    pub notification: bool,
+}
+
+/// A sink for the metrics of calls.
+pub trait Metrics: Send + Sync + 'static {
+    /// Records a call.
+    fn record(&self, call: &CallRecord);
+}
+
+impl<M: Metrics> Metrics for Arc<M> {
+    fn record(&self, call: &CallRecord) {
+        (**self).record(call)
+    }
+}
+
+/// Returns the size of the value once serialized, without buffering the serialization.
+fn serialized_len<T: serde::Serialize>(value: &T) -> usize {
+    let mut counter = ByteCounter {
+        inner: io::sink(),
+        count: 0,
+    };
+    serde_json::to_writer(&mut counter, value).map(|()| counter.count).unwrap_or(0)
+}
+
+/// Counts the bytes written to, or read and consumed from, the wrapped value.
+struct ByteCounter<T> {
+    inner: T,
+    count: usize,
+}
+
+impl<W: io::Write> io::Write for ByteCounter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.count += written;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl io::Read for ByteCounter<&mut dyn io::BufRead> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.count += read;
+        Ok(read)
+    }
+}
+
+impl io::BufRead for ByteCounter<&mut dyn io::BufRead> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.inner.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.inner.consume(amt);
+        self.count += amt;
+    }
+}
+
+/// Returns the kind of error held by the response, if any.
+fn response_error(response: &Response) -> Option<ErrorKind> {
+    response.error.as_ref().map(|e| ErrorKind::Rpc(e.code))
+}
+
+/// A layer reporting the calls of transports to a [`Metrics`] sink.
+pub struct MetricsLayer<M> {
+    metrics: Arc<M>,
+}
+
+impl<M: Metrics> MetricsLayer<M> {
+    /// Creates a layer reporting to `metrics`.
+    pub fn new(metrics: Arc<M>) -> MetricsLayer<M> {
+        MetricsLayer {
+            metrics,
+        }
+    }
+}
+
+impl<M> Clone for MetricsLayer<M> {
+    fn clone(&self) -> Self {
+        MetricsLayer {
+            metrics: self.metrics.clone(),
+        }
+    }
+}
+
+impl<M: Metrics, T: Transport> Layer<T> for MetricsLayer<M> {
+    type Transport = MetricsTransport<M, T>;
+
+    fn layer(&self, inner: T) -> Self::Transport {
+        MetricsTransport::new(inner, self.metrics.clone())
+    }
+}
+
+/// A transport reporting its calls to a [`Metrics`] sink, see the [module docs](self).
+pub struct MetricsTransport<M, T> {
+    inner: T,
+    metrics: Arc<M>,
+}
+
+impl<M: Metrics, T: Transport> MetricsTransport<M, T> {
+    /// Wraps `inner`, reporting its calls to `metrics`.
+    pub fn new(inner: T, metrics: Arc<M>) -> MetricsTransport<M, T> {
+        MetricsTransport {
+            inner,
+            metrics,
+        }
+    }
+
+    /// Returns a reference to the wrapped transport.
+    pub fn inner(&self) -> &T {
+        &self.inner
+    }
+
+    /// Returns a reference to the metrics sink.
+    pub fn metrics(&self) -> &Arc<M> {
+        &self.metrics
+    }
+}
+
+impl<M: Metrics, T: Transport> Transport for MetricsTransport<M, T> {
+    fn send_request(&self, req: Request) -> Result<Response, Error> {
+        let method = req.method;
+        let request_bytes = serialized_len(&req);
+        let start = Instant::now();
+        let result = self.inner.send_request(req);
+        let (error, response_bytes) = match result {
+            Ok(ref response) => (response_error(response), serialized_len(&ResponseBody(response))),
+            Err(ref e) => (Some(ErrorKind::of(e)), 0),
+        };
+        self.metrics.record(&CallRecord {
+            method,
+            duration: start.elapsed(),
+            error,
+            request_bytes,
+            response_bytes,
+            notification: false,
+        });
+        result
+    }
+
+    fn send_request_streaming(&self, req: Request, read_body: &mut ReadBody) -> Result<(), Error> {
+        let method = req.method;
+        let request_bytes = serialized_len(&req);
+        let start = Instant::now();
+        let mut response_bytes = 0;
+        let result = self.inner.send_request_streaming(req, &mut |body| {
+            let mut counter = ByteCounter {
+                inner: body,
+                count: 0,
+            };
+            let result = read_body(&mut counter);
+            response_bytes = counter.count;
+            result
+        });
+        // The client checks the response while reading it, so this includes its RPC error.
+        self.metrics.record(&CallRecord {
+            method,
+            duration: start.elapsed(),
+            error: result.as_ref().err().map(ErrorKind::of),
+            request_bytes,
+            response_bytes,
+            notification: false,
+        });
+        result
+    }
+
+    fn send_batch(&self, reqs: &[Request]) -> Result<Vec<Response>, Error> {
+        let start = Instant::now();
+        let result = self.inner.send_batch(reqs);
+        let duration = start.elapsed();
+
+        let mut responses = HashMap::new();
+        if let Ok(ref resps) = result {
+            for response in resps {
+                responses.insert(HashableValue(Cow::Borrowed(&response.id)), response);
+            }
+        }
+        for req in reqs {
+            let response =
+                req.id.as_ref().and_then(|id| responses.get(&HashableValue(Cow::Borrowed(id))));
+            let error = match (&result, response) {
+                (Err(ref e), _) => Some(ErrorKind::of(e)),
+                (Ok(_), Some(response)) => response_error(response),
+                (Ok(_), None) if !req.is_notification() => Some(ErrorKind::MissingBatchResponse),
+                (Ok(_), None) => None,
+            };
+            self.metrics.record(&CallRecord {
+                method: req.method,
+                duration,
+                error,
+                request_bytes: serialized_len(req),
+                response_bytes: response.map_or(0, |r| serialized_len(&ResponseBody(r))),
+                notification: req.is_notification(),
+            });
+        }
+        result
+    }
+
+    fn send_notification(&self, req: Request) -> Result<(), Error> {
+        let method = req.method;
+        let request_bytes = serialized_len(&req);
+        let start = Instant::now();
+        let result = self.inner.send_notification(req);
+        self.metrics.record(&CallRecord {
+            method,
+            duration: start.elapsed(),
+            error: result.as_ref().err().map(ErrorKind::of),
+            request_bytes,
+            response_bytes: 0,
+            notification: true,
+        });
+        result
+    }
+
+    fn fmt_target(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.inner.fmt_target(f)
+    }
+}
+
+/// Upper bounds of the buckets of [`Histogram`], in microseconds.
+const BUCKETS_US: [u64; 14] = [
+    500, 1_000, 2_500, 5_000, 10_000, 25_000, 50_000, 100_000, 250_000, 500_000, 1_000_000,
+    2_500_000, 5_000_000, 10_000_000,
+];
+
+/// A latency histogram with fixed buckets, from 0.5ms to 10s.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Histogram {
+    /// Count of each bucket of [`BUCKETS_US`], and of the overflow bucket.
+    counts: [u64; 15],
+    count: u64,
+    sum: Duration,
+    max: Duration,
+}
+
+impl Histogram {
+    /// Records a duration.
+    pub fn record(&mut self, duration: Duration) {
+        let us = duration.as_micros();
+        let bucket =
+            BUCKETS_US.iter().position(|&b| us <= u128::from(b)).unwrap_or(BUCKETS_US.len());
+        self.counts[bucket] += 1;
+        self.count += 1;
+        self.sum += duration;
+        self.max = self.max.max(duration);
+    }
+
+    /// Returns the number of recorded durations.
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// Returns the sum of the recorded durations.
+    pub fn sum(&self) -> Duration {
+        self.sum
+    }
+
+    /// Returns the longest recorded duration.
+    pub fn max(&self) -> Duration {
+        self.max
+    }
+
+    /// Returns the mean of the recorded durations.
+    pub fn mean(&self) -> Duration {
+        if self.count == 0 {
+            return Duration::from_secs(0);
+        }
+        Duration::from_nanos((self.sum.as_nanos() / u128::from(self.count)) as u64)
+    }
+
+    /// Returns the buckets as `(upper bound, count)` pairs, the last bucket having no bound.
+    pub fn buckets(&self) -> Vec<(Option<Duration>, u64)> {
+        BUCKETS_US
+            .iter()
+            .map(|&b| Some(Duration::from_micros(b)))
+            .chain(Some(None))
+            .zip(self.counts.iter().copied())
+            .collect()
+    }
+
+    /// Returns an upper bound of the given quantile, between 0 and 1, of the recorded
+    /// durations, i.e. the upper bound of the bucket it falls in.
+    pub fn quantile(&self, q: f64) -> Duration {
+        let rank = (q.max(0.0).min(1.0) * self.count as f64).ceil() as u64;
+        let mut seen = 0;
+        for (bound, count) in self.buckets() {
+            seen += count;
+            if seen >= rank.max(1) {
+                return bound.unwrap_or(self.max).min(self.max);
+            }
+        }
+        self.max
+    }
+}
+
+/// Aggregated metrics of a method, see [`InMemoryMetrics`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MethodStats {
+    /// Number of calls, including failed ones.
+    pub calls: u64,
+    /// Number of failed calls, by kind of error.
+    pub errors: BTreeMap<ErrorKind, u64>,
+    /// Latency of the calls.
+    pub latency: Histogram,
+    /// Total size of the requests, in bytes.
+    pub request_bytes: u64,
+    /// Total size of the responses, in bytes.
+    pub response_bytes: u64,
+}
+
+impl MethodStats {
+    /// Returns the total number of failed calls.
+    pub fn error_count(&self) -> u64 {
+        self.errors.values().sum()
+    }
+}
+
+/// [`Metrics`] aggregated in memory, per method.
+#[derive(Debug, Default)]
+pub struct InMemoryMetrics {
+    methods: Mutex<BTreeMap<String, MethodStats>>,
+}
+
+impl InMemoryMetrics {
+    /// Creates an empty set of metrics.
+    pub fn new() -> InMemoryMetrics {
+        InMemoryMetrics::default()
+    }
+
+    /// Returns a copy of the metrics of every method called so far.
+    pub fn snapshot(&self) -> BTreeMap<String, MethodStats> {
+        self.methods.lock().expect("poisoned mutex").clone()
+    }
+
+    /// Returns a copy of the metrics of the given method, if it was called.
+    pub fn method(&self, method: &str) -> Option<MethodStats> {
+        self.methods.lock().expect("poisoned mutex").get(method).cloned()
+    }
+
+    /// Resets all metrics.
+    pub fn reset(&self) {
+        self.methods.lock().expect("poisoned mutex").clear();
+    }
+}
+
+impl Metrics for InMemoryMetrics {
+    fn record(&self, call: &CallRecord) {
+        let mut methods = self.methods.lock().expect("poisoned mutex");
+        if !methods.contains_key(call.method) {
+            methods.insert(call.method.to_owned(), MethodStats::default());
+        }
+        let stats = methods.get_mut(call.method).expect("inserted above");
+        stats.calls += 1;
+        if let Some(kind) = call.error {
+            *stats.errors.entry(kind).or_insert(0) += 1;
+        }
+        stats.latency.record(call.duration);
+        stats.request_bytes += call.request_bytes as u64;
+        stats.response_bytes += call.response_bytes as u64;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::tests::Node;
+    use crate::Client;
+
+    #[test]
+    fn per_method_metrics() {
+        let metrics = Arc::new(InMemoryMetrics::new());
+        let client =
+            Client::builder(Node::default()).layer(MetricsLayer::new(metrics.clone())).build();
+
+        client.call::<u64>("getblockcount", &[]).unwrap();
+        client.call_streaming::<u64>("getblockcount", &[]).unwrap();
+        client.call::<u64>("fail", &[]).unwrap_err();
+        client.call_streaming::<u64>("fail", &[]).unwrap_err();
+        client.call_streaming::<u64>("down", &[]).unwrap_err();
+        client.notify("ping", &[]).unwrap();
+        let batch =
+            [client.build_request("getblockcount", &[]), client.build_notification("ping", &[])];
+        client.send_batch(&batch).unwrap();
+
+        let snapshot = metrics.snapshot();
+        assert_eq!(snapshot.keys().collect::<Vec<_>>(), ["down", "fail", "getblockcount", "ping"]);
+
+        let count = &snapshot["getblockcount"];
+        assert_eq!(count.calls, 3);
+        assert_eq!(count.error_count(), 0);
+        assert_eq!(count.latency.count(), 3);
+        // E.g. `{"method":"getblockcount","params":[],"id":1,"jsonrpc":"2.0"}`.
+        assert_eq!(count.request_bytes, 3 * 61);
+        // E.g. `{"result":1,"id":1,"jsonrpc":"2.0"}`.
+        assert_eq!(count.response_bytes, 3 * 35);
+
+        let fail = &snapshot["fail"];
+        assert_eq!(fail.errors.get(&ErrorKind::Rpc(-32603)), Some(&2));
+        assert!(fail.response_bytes > 0);
+        assert_eq!(snapshot["down"].errors.get(&ErrorKind::Transport), Some(&1));
+        assert_eq!(snapshot["down"].response_bytes, 0);
+        assert_eq!(snapshot["ping"].calls, 2);
+        assert_eq!(snapshot["ping"].response_bytes, 0);
+
+        metrics.reset();
+        assert!(metrics.method("getblockcount").is_none());
+    }
+
+    #[test]
+    fn histogram() {
+        let mut histogram = Histogram::default();
+        assert_eq!(histogram.mean(), Duration::from_secs(0));
+        for ms in &[1, 2, 3, 4, 20, 20000] {
+            histogram.record(Duration::from_millis(*ms));
+        }
+        assert_eq!(histogram.count(), 6);
+        assert_eq!(histogram.max(), Duration::from_secs(20));
+        assert_eq!(histogram.mean(), Duration::from_nanos(3_338_333_333));
+        assert_eq!(histogram.quantile(0.5), Duration::from_micros(5_000));
+        assert_eq!(histogram.quantile(0.8), Duration::from_millis(25));
+        assert_eq!(histogram.quantile(1.0), Duration::from_secs(20));
+
+        let buckets = histogram.buckets();
+        assert_eq!(buckets.len(), 15);
+        assert_eq!(buckets[1], (Some(Duration::from_millis(1)), 1));
+        assert_eq!(buckets[14], (None, 1));
+    }
+}
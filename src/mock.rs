@@ -0,0 +1,478 @@
+//! # Mock transport
+//!
+//! A programmable [`Transport`] for unit-testing code built on a [`crate::Client`]. Tests
+//! declare the calls they expect, with canned results, and check at the end that every
+//! expectation was met:
+//!
+//! ```
+//! use std::sync::Arc;
+//! use jsonrpc::mock::MockTransport;
+//! use jsonrpc::Client;
+//!
+//! let mock = Arc::new(MockTransport::new());
+//! mock.expect("getblockcount").returns(800_000);
+//! mock.expect("getblockhash").with_params(serde_json::json!([800_000])).returns("00ab");
+//!
+//! let client = Client::with_transport(mock.clone());
+//! assert_eq!(client.call::<u64>("getblockcount", &[]).unwrap(), 800_000);
+//! let hash: String = client.call("getblockhash", &[jsonrpc::arg(800_000)]).unwrap();
+//! assert_eq!(hash, "00ab");
+//!
+//! mock.verify().unwrap();
+//! ```
+//!
+//! This module is only available with the `testing` feature.
+//!
+
+use std::sync::Mutex;
+use std::{error, fmt};
+
+use serde;
+use serde_json;
+use serde_json::value::RawValue;
+
+use crate::client::Transport;
+use crate::error::RpcError;
+use crate::{Request, Response};
+
+/// Error that can occur while using the mock transport.
+#[derive(Debug)]
+pub enum Error {
+    /// A call did not match any expectation.
+    UnexpectedCall(String),
+    /// A call matched an expectation, but came before the calls expected before it.
+    OutOfOrder {
+        /// The unexpected call.
+        call: String,
+        /// The expectation which was due first.
+        expected: String,
+    },
+    /// The expectation replies with a transport error.
+    Canned(String),
+    /// Verification failed, with a description of every problem.
+    Unmet(Vec<String>),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match *self {
+            Error::UnexpectedCall(ref call) => write!(f, "unexpected call {}", call),
+            Error::OutOfOrder {
+                ref call,
+                ref expected,
+            } => write!(f, "call {} out of order, expected {} first", call, expected),
+            Error::Canned(ref e) => f.write_str(e),
+            Error::Unmet(ref problems) => {
+                write!(f, "mock expectations not met:")?;
+                for problem in problems {
+                    write!(f, "\n- {}", problem)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+impl From<Error> for crate::Error {
+    fn from(e: Error) -> crate::Error {
+        crate::Error::Transport(Box::new(e))
+    }
+}
+
+/// What an expectation replies.
+#[derive(Debug, Clone)]
+enum Reply {
+    Result(Box<RawValue>),
+    RpcError(RpcError),
+    TransportError(String),
+}
+
+/// An expected call.
+#[derive(Debug)]
+struct Expected {
+    method: String,
+    /// The expected parameters, or [`None`] to accept any.
+    params: Option<serde_json::Value>,
+    reply: Reply,
+    min: usize,
+    max: Option<usize>,
+    calls: usize,
+}
+
+impl Expected {
+    fn matches(&self, call: &Call) -> bool {
+        self.method == call.method && self.params.as_ref().map_or(true, |p| *p == call.params)
+    }
+
+    fn saturated(&self) -> bool {
+        self.max.map_or(false, |max| self.calls >= max)
+    }
+
+    fn satisfied(&self) -> bool {
+        self.calls >= self.min
+    }
+}
+
+impl fmt::Display for Expected {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.method)?;
+        if let Some(ref params) = self.params {
+            write!(f, "({})", params)?;
+        }
+        Ok(())
+    }
+}
+
+/// A call made through the mock.
+struct Call {
+    method: String,
+    params: serde_json::Value,
+}
+
+impl fmt::Display for Call {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}({})", self.method, self.params)
+    }
+}
+
+#[derive(Default)]
+struct State {
+    expectations: Vec<Expected>,
+    ordered: bool,
+    /// Index of the expectation matched last, when ordered.
+    cursor: usize,
+    /// Problems found while calling, reported by [`MockTransport::verify`].
+    problems: Vec<String>,
+}
+
+impl State {
+    /// Finds the expectation matching the call and counts the call.
+    fn reply(&mut self, call: Call) -> Result<Reply, Error> {
+        let found = if self.ordered {
+            self.find_ordered(&call)
+        } else {
+            self.expectations
+                .iter()
+                .position(|e| e.matches(&call) && !e.saturated())
+                .ok_or_else(|| Error::UnexpectedCall(call.to_string()))
+        };
+        match found {
+            Ok(idx) => {
+                let expected = &mut self.expectations[idx];
+                expected.calls += 1;
+                Ok(expected.reply.clone())
+            }
+            Err(e) => {
+                self.problems.push(e.to_string());
+                Err(e)
+            }
+        }
+    }
+
+    /// Finds the next expectation in order, skipping satisfied ones which don't match.
+    fn find_ordered(&mut self, call: &Call) -> Result<usize, Error> {
+        for idx in self.cursor..self.expectations.len() {
+            let expected = &self.expectations[idx];
+            if expected.matches(call) && !expected.saturated() {
+                self.cursor = idx;
+                return Ok(idx);
+            }
+            if !expected.satisfied() {
+                if self.expectations.iter().any(|e| e.matches(call) && !e.saturated()) {
+                    return Err(Error::OutOfOrder {
+                        call: call.to_string(),
+                        expected: expected.to_string(),
+                    });
+                }
+                break;
+            }
+        }
+        Err(Error::UnexpectedCall(call.to_string()))
+    }
+}
+
+/// A transport replying to calls according to expectations, see the [module docs](self).
+///
+/// The mock is meant to be shared with the client through an [`std::sync::Arc`], so that
+/// expectations can be added and verified while the client uses it.
+#[derive(Default)]
+pub struct MockTransport {
+    state: Mutex<State>,
+}
+
+impl MockTransport {
+    /// Creates a mock without expectations, accepting expected calls in any order.
+    pub fn new() -> MockTransport {
+        MockTransport::default()
+    }
+
+    /// Creates a mock requiring the expected calls to be made in the order they were declared.
+    ///
+    /// Expectations which may be called a variable number of times, see
+    /// [`Expectation::at_least`], are skipped once satisfied.
+    pub fn ordered() -> MockTransport {
+        let mock = MockTransport::new();
+        mock.lock().ordered = true;
+        mock
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().expect("poisoned mutex")
+    }
+
+    /// Expects exactly one call to the given method, with any parameters, replying `null`.
+    ///
+    /// Use the returned [`Expectation`] to refine the expectation.
+    pub fn expect(&self, method: &str) -> Expectation<'_> {
+        let mut state = self.lock();
+        state.expectations.push(Expected {
+            method: method.to_owned(),
+            params: None,
+            reply: Reply::Result(RawValue::from_string("null".to_owned()).expect("valid JSON")),
+            min: 1,
+            max: Some(1),
+            calls: 0,
+        });
+        Expectation {
+            mock: self,
+            idx: state.expectations.len() - 1,
+        }
+    }
+
+    /// Checks that every expectation was met and that no unexpected call was made.
+    pub fn verify(&self) -> Result<(), Error> {
+        let state = self.lock();
+        let mut problems = state.problems.clone();
+        for expected in &state.expectations {
+            if !expected.satisfied() {
+                problems.push(format!(
+                    "expected {} to be called {} time(s), got {}",
+                    expected, expected.min, expected.calls
+                ));
+            }
+        }
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::Unmet(problems))
+        }
+    }
+
+    /// Returns the response to the request, or [`None`] for notifications.
+    fn respond(&self, req: &Request) -> Result<Option<Response>, crate::Error> {
+        let call = Call {
+            method: req.method.to_owned(),
+            params: serde_json::to_value(req.params)?,
+        };
+        let reply = self.lock().reply(call)?;
+        let (result, error) = match reply {
+            Reply::Result(result) => (Some(result), None),
+            Reply::RpcError(e) => (None, Some(e)),
+            Reply::TransportError(e) => return Err(Error::Canned(e).into()),
+        };
+        Ok(req.id.clone().map(|id| Response {
+            result,
+            error,
+            id,
+            jsonrpc: Some(String::from("2.0")),
+        }))
+    }
+}
+
+impl Transport for MockTransport {
+    fn send_request(&self, req: Request) -> Result<Response, crate::Error> {
+        // Requests always have an ID, but reply to one without as if it had a null one.
+        let response = self.respond(&req)?;
+        Ok(response.unwrap_or_else(|| Response {
+            result: None,
+            error: None,
+            id: serde_json::Value::Null,
+            jsonrpc: Some(String::from("2.0")),
+        }))
+    }
+
+    fn send_batch(&self, reqs: &[Request]) -> Result<Vec<Response>, crate::Error> {
+        let mut responses = vec![];
+        for req in reqs {
+            responses.extend(self.respond(req)?);
+        }
+        Ok(responses)
+    }
+
+    fn send_notification(&self, req: Request) -> Result<(), crate::Error> {
+        self.respond(&req).map(|_| ())
+    }
+
+    fn fmt_target(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "mock")
+    }
+}
+
+impl fmt::Debug for MockTransport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let state = self.lock();
+        let expectations: Vec<_> = state.expectations.iter().map(ToString::to_string).collect();
+        f.debug_struct("MockTransport").field("expectations", &expectations).finish()
+    }
+}
+
+/// Refines an expectation declared with [`MockTransport::expect`].
+pub struct Expectation<'a> {
+    mock: &'a MockTransport,
+    idx: usize,
+}
+
+impl<'a> Expectation<'a> {
+    fn update<F: FnOnce(&mut Expected)>(self, f: F) -> Self {
+        f(&mut self.mock.lock().expectations[self.idx]);
+        self
+    }
+
+    /// Only matches calls with the given parameters: an array for parameters by position, an
+    /// object for parameters by name, or `null` for omitted parameters.
+    pub fn with_params(self, params: serde_json::Value) -> Self {
+        self.update(|e| e.params = Some(params))
+    }
+
+    /// Expects the call exactly `n` times.
+    pub fn times(self, n: usize) -> Self {
+        self.update(|e| {
+            e.min = n;
+            e.max = Some(n);
+        })
+    }
+
+    /// Expects the call at least `n` times.
+    pub fn at_least(self, n: usize) -> Self {
+        self.update(|e| {
+            e.min = n;
+            e.max = None;
+        })
+    }
+
+    /// Accepts the call any number of times, including never.
+    pub fn any_times(self) -> Self {
+        self.at_least(0)
+    }
+
+    /// Replies with the given result.
+    pub fn returns<T: serde::Serialize>(self, result: T) -> Self {
+        let result = crate::arg(result);
+        self.update(|e| e.reply = Reply::Result(result))
+    }
+
+    /// Replies with the given RPC error.
+    pub fn returns_error(self, error: RpcError) -> Self {
+        self.update(|e| e.reply = Reply::RpcError(error))
+    }
+
+    /// Fails the call with a transport error with the given message.
+    pub fn fails<S: Into<String>>(self, message: S) -> Self {
+        let message = message.into();
+        self.update(|e| e.reply = Reply::TransportError(message))
+    }
+}
+
+impl<'a> fmt::Debug for Expectation<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Expectation({})", self.mock.lock().expectations[self.idx])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use serde_json::json;
+
+    use super::*;
+    use crate::error::{standard_error, StandardError};
+    use crate::{arg, Client};
+
+    #[test]
+    fn canned_replies() {
+        let mock = Arc::new(MockTransport::new());
+        mock.expect("getblockhash").with_params(json!([1])).returns("one");
+        mock.expect("getblockhash").with_params(json!([2])).returns("two").times(2);
+        mock.expect("getblock")
+            .returns_error(standard_error(StandardError::InvalidParams, None))
+            .any_times();
+        mock.expect("stop").fails("connection refused");
+        mock.expect("ping").with_params(json!(null));
+
+        let client = Client::with_transport(mock.clone());
+        assert_eq!(client.call::<String>("getblockhash", &[arg(2)]).unwrap(), "two");
+        assert_eq!(client.call::<String>("getblockhash", &[arg(1)]).unwrap(), "one");
+        match client.call::<String>("getblock", &[("blockhash", arg("00"))]) {
+            Err(crate::Error::Rpc(ref e)) if e.code == -32602 => {}
+            r => panic!("expected RPC error, got {:?}", r),
+        }
+        match client.call::<()>("stop", &[]) {
+            Err(crate::Error::Transport(e)) => assert_eq!(e.to_string(), "connection refused"),
+            r => panic!("expected transport error, got {:?}", r),
+        }
+        client.notify("ping", crate::Params::None).unwrap();
+
+        let params = [arg(2)];
+        let batch = [client.build_request("getblockhash", &params)];
+        let responses = client.send_batch(&batch).unwrap();
+        assert_eq!(responses[0].as_ref().unwrap().result::<String>().unwrap(), "two");
+
+        mock.verify().unwrap();
+    }
+
+    #[test]
+    fn unmet_expectations() {
+        let mock = Arc::new(MockTransport::new());
+        mock.expect("getblockcount").times(2);
+        mock.expect("getblockhash").at_least(1);
+
+        let client = Client::with_transport(mock.clone());
+        client.call::<()>("getblockcount", &[]).unwrap();
+        client.call::<()>("getblockcount", &[]).unwrap();
+        match client.call::<()>("getblockcount", &[]) {
+            Err(crate::Error::Transport(e)) => {
+                assert_eq!(e.to_string(), "unexpected call getblockcount([])")
+            }
+            r => panic!("expected transport error, got {:?}", r),
+        }
+
+        assert_eq!(
+            mock.verify().unwrap_err().to_string(),
+            "mock expectations not met:\n\
+             - unexpected call getblockcount([])\n\
+             - expected getblockhash to be called 1 time(s), got 0"
+        );
+    }
+
+    #[test]
+    fn ordering() {
+        let mock = Arc::new(MockTransport::ordered());
+        mock.expect("getblockcount");
+        mock.expect("getmempoolinfo").any_times();
+        mock.expect("getblockhash").times(2);
+        mock.expect("stop");
+
+        let client = Client::with_transport(mock.clone());
+        match client.call::<()>("getblockhash", &[]) {
+            Err(crate::Error::Transport(e)) => assert_eq!(
+                e.to_string(),
+                "call getblockhash([]) out of order, expected getblockcount first"
+            ),
+            r => panic!("expected transport error, got {:?}", r),
+        }
+        client.call::<()>("getblockcount", &[]).unwrap();
+        // Skips the optional `getmempoolinfo`.
+        client.call::<()>("getblockhash", &[]).unwrap();
+        client.call::<()>("getblockhash", &[]).unwrap();
+        client.call::<()>("stop", &[]).unwrap();
+        assert!(client.call::<()>("getblockcount", &[]).is_err());
+
+        match mock.verify() {
+            Err(Error::Unmet(problems)) => assert_eq!(problems.len(), 2),
+            r => panic!("expected unmet expectations, got {:?}", r),
+        }
+    }
+}
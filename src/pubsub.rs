@@ -0,0 +1,485 @@
+//! # Subscriptions
+//!
+//! A client over a persistent TCP or Unix Domain Socket connection, on which the server pushes
+//! the notifications of subscriptions, the counterpart of [`crate::server::Publisher`].
+//!
+//! A subscription is opened by a call returning its ID, after which the server sends requests
+//! without an ID whose parameters are the ID of the subscription and a value, by name:
+//! `{"subscription": 1, "result": ...}`. It is closed by another call taking the ID as its only
+//! parameter.
+//!
+//! ```no_run
+//! # #[cfg(feature = "simple_tcp")]
+//! # fn main() -> Result<(), jsonrpc::Error> {
+//! use jsonrpc::pubsub::PubSubClient;
+//!
+//! let client = PubSubClient::connect_tcp("127.0.0.1:8332")?;
+//! let blocks = client.subscribe::<String>("subscribe_blocks", &[], "unsubscribe_blocks")?;
+//! for hash in blocks.take(10) {
+//!     println!("new block {}", hash?);
+//! }
+//! # Ok(())
+//! # }
+//! # #[cfg(not(feature = "simple_tcp"))]
+//! # fn main() {}
+//! ```
+//!
+
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::marker::PhantomData;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{mpsc, Arc, Mutex, MutexGuard};
+use std::time::Duration;
+use std::{error, fmt, thread};
+
+#[cfg(feature = "simple_tcp")]
+use std::net::{self, TcpStream, ToSocketAddrs};
+#[cfg(all(feature = "simple_uds", not(windows)))]
+use std::os::unix::net::UnixStream;
+#[cfg(all(feature = "simple_uds", not(windows)))]
+use std::path::Path;
+
+use serde::Deserialize;
+use serde_json::value::RawValue;
+
+use crate::client::{check_response, ProtocolVersion, ResponseChecks};
+use crate::{Params, Response};
+
+/// Error that can occur while using a [`PubSubClient`].
+#[derive(Debug)]
+pub enum Error {
+    /// An error occurred on the socket layer.
+    SocketError(io::Error),
+    /// We didn't receive a response till the deadline ran out.
+    Timeout,
+    /// The connection was closed.
+    Closed,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match *self {
+            Error::SocketError(ref e) => write!(f, "Couldn't connect to host: {}", e),
+            Error::Timeout => f.write_str("Didn't receive response data in time, timed out."),
+            Error::Closed => f.write_str("The connection was closed."),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            Error::SocketError(ref e) => Some(e),
+            Error::Timeout | Error::Closed => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::SocketError(e)
+    }
+}
+
+impl From<Error> for crate::Error {
+    fn from(e: Error) -> crate::Error {
+        crate::Error::Transport(Box::new(e))
+    }
+}
+
+/// A connection to a server.
+trait Stream: Read + Write + Send + Sync + Sized + 'static {
+    fn try_clone(&self) -> io::Result<Self>;
+    fn shutdown(&self) -> io::Result<()>;
+}
+
+#[cfg(feature = "simple_tcp")]
+impl Stream for TcpStream {
+    fn try_clone(&self) -> io::Result<Self> {
+        TcpStream::try_clone(self)
+    }
+
+    fn shutdown(&self) -> io::Result<()> {
+        TcpStream::shutdown(self, net::Shutdown::Both)
+    }
+}
+
+#[cfg(all(feature = "simple_uds", not(windows)))]
+impl Stream for UnixStream {
+    fn try_clone(&self) -> io::Result<Self> {
+        UnixStream::try_clone(self)
+    }
+
+    fn shutdown(&self) -> io::Result<()> {
+        UnixStream::shutdown(self, std::net::Shutdown::Both)
+    }
+}
+
+/// A request waiting for its response.
+struct Pending {
+    response: mpsc::Sender<Response>,
+    /// Where to send the notifications of the subscription the request opens, if any.
+    subscription: Option<mpsc::Sender<Box<RawValue>>>,
+}
+
+/// The requests and subscriptions of a connection, shared with the thread reading from it.
+#[derive(Default)]
+struct State {
+    closed: bool,
+    pending: HashMap<u64, Pending>,
+    /// The subscriptions, by the JSON text of their ID.
+    subscriptions: HashMap<String, mpsc::Sender<Box<RawValue>>>,
+}
+
+/// The parameters of a notification.
+#[derive(Deserialize)]
+struct NotificationParams {
+    subscription: serde_json::Value,
+    result: Box<RawValue>,
+}
+
+/// A notification of a subscription.
+#[derive(Deserialize)]
+struct Notification {
+    params: NotificationParams,
+}
+
+impl State {
+    fn respond(&mut self, response: Response) {
+        let pending = match response.id.as_u64().and_then(|id| self.pending.remove(&id)) {
+            Some(pending) => pending,
+            // The caller gave up waiting.
+            None => return,
+        };
+        if let (Some(sender), None, Some(result)) =
+            (pending.subscription, &response.error, &response.result)
+        {
+            // Registered before any further message is read, as notifications may follow.
+            if let Ok(id) = serde_json::from_str::<serde_json::Value>(result.get()) {
+                self.subscriptions.insert(id.to_string(), sender);
+            }
+        }
+        let _ = pending.response.send(response);
+    }
+
+    fn notify(&mut self, notification: Notification) {
+        let id = notification.params.subscription.to_string();
+        if let Some(sender) = self.subscriptions.get(&id) {
+            if sender.send(notification.params.result).is_err() {
+                self.subscriptions.remove(&id);
+            }
+        }
+    }
+}
+
+/// Reads the messages sent by the server until the connection is closed.
+fn read_messages<R: Read>(reader: R, state: &Mutex<State>) {
+    let values = serde_json::Deserializer::from_reader(io::BufReader::new(reader))
+        .into_iter::<Box<RawValue>>();
+    for value in values {
+        let value = match value {
+            Ok(value) => value,
+            Err(_) => break,
+        };
+        let mut state = state.lock().expect("poisoned mutex");
+        if let Ok(notification) = serde_json::from_str(value.get()) {
+            state.notify(notification);
+        } else if let Ok(response) = serde_json::from_str(value.get()) {
+            state.respond(response);
+        }
+        // Anything else, e.g. a batch, wasn't asked for and is ignored.
+    }
+
+    let mut state = state.lock().expect("poisoned mutex");
+    state.closed = true;
+    // Calls and subscriptions end once their sender is dropped.
+    state.pending.clear();
+    state.subscriptions.clear();
+}
+
+/// The connection of a client, closed once all its clones and subscriptions are dropped.
+struct Connection {
+    target: String,
+    writer: Mutex<Box<dyn Write + Send>>,
+    shutdown: Box<dyn Fn() + Send + Sync>,
+    state: Arc<Mutex<State>>,
+    nonce: AtomicU64,
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        // Also stops the thread reading from the connection.
+        (self.shutdown)();
+    }
+}
+
+/// A JSON-RPC client over a persistent connection, which can subscribe to notifications.
+///
+/// Calls can be made concurrently from clones of the client, which share the connection.
+#[derive(Clone)]
+pub struct PubSubClient {
+    conn: Arc<Connection>,
+    timeout: Option<Duration>,
+}
+
+impl PubSubClient {
+    /// Connects to a server over TCP.
+    #[cfg(feature = "simple_tcp")]
+    pub fn connect_tcp<A: ToSocketAddrs>(addr: A) -> Result<PubSubClient, Error> {
+        let stream = TcpStream::connect(addr)?;
+        let target = stream.peer_addr()?.to_string();
+        PubSubClient::new(stream, target)
+    }
+
+    /// Connects to a server over a Unix Domain Socket.
+    #[cfg(all(feature = "simple_uds", not(windows)))]
+    pub fn connect_uds<P: AsRef<Path>>(sockpath: P) -> Result<PubSubClient, Error> {
+        let stream = UnixStream::connect(&sockpath)?;
+        PubSubClient::new(stream, sockpath.as_ref().display().to_string())
+    }
+
+    fn new<S: Stream>(stream: S, target: String) -> Result<PubSubClient, Error> {
+        let reader = stream.try_clone()?;
+        let closer = stream.try_clone()?;
+        let state = Arc::new(Mutex::new(State::default()));
+        let shared = state.clone();
+        thread::spawn(move || read_messages(reader, &shared));
+        let conn = Connection {
+            target,
+            writer: Mutex::new(Box::new(stream)),
+            shutdown: Box::new(move || {
+                let _ = closer.shutdown();
+            }),
+            state,
+            nonce: AtomicU64::new(1),
+        };
+        Ok(PubSubClient {
+            conn: Arc::new(conn),
+            timeout: None,
+        })
+    }
+
+    /// Sets how long to wait for the response to a call, forever by default.
+    ///
+    /// Notifications are waited for regardless of this timeout.
+    pub fn timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.conn.state.lock().expect("poisoned mutex")
+    }
+
+    fn write(&self, request: &crate::Request) -> Result<(), crate::Error> {
+        let request = serde_json::to_vec(request)?;
+        let mut writer = self.conn.writer.lock().expect("poisoned mutex");
+        writer.write_all(&request).map_err(|e| Error::SocketError(e).into())
+    }
+
+    /// Sends a request and waits for its response, sending the notifications of the
+    /// subscription it opens, if any, to `subscription`.
+    fn request(
+        &self,
+        method: &str,
+        params: Params,
+        subscription: Option<mpsc::Sender<Box<RawValue>>>,
+    ) -> Result<Response, crate::Error> {
+        let nonce = self.conn.nonce.fetch_add(1, Ordering::Relaxed);
+        let id = serde_json::Value::from(nonce);
+        let (sender, receiver) = mpsc::channel();
+        {
+            let mut state = self.state();
+            if state.closed {
+                return Err(Error::Closed.into());
+            }
+            let pending = Pending {
+                response: sender,
+                subscription,
+            };
+            state.pending.insert(nonce, pending);
+        }
+
+        let request = ProtocolVersion::V2.request(method, params, Some(id.clone()));
+        let response = self.write(&request).and_then(|()| {
+            let response = match self.timeout {
+                Some(timeout) => receiver.recv_timeout(timeout).map_err(|e| match e {
+                    mpsc::RecvTimeoutError::Timeout => Error::Timeout,
+                    mpsc::RecvTimeoutError::Disconnected => Error::Closed,
+                }),
+                None => receiver.recv().map_err(|_| Error::Closed),
+            };
+            response.map_err(crate::Error::from)
+        });
+        let response = match response {
+            Ok(response) => response,
+            Err(e) => {
+                self.state().pending.remove(&nonce);
+                return Err(e);
+            }
+        };
+        check_response(ResponseChecks::default(), Some(&id), &response)?;
+        Ok(response)
+    }
+
+    /// Makes a request and deserializes the response.
+    pub fn call<'p, R: for<'a> serde::de::Deserialize<'a>>(
+        &self,
+        method: &str,
+        params: impl Into<Params<'p>>,
+    ) -> Result<R, crate::Error> {
+        self.request(method, params.into(), None)?.result()
+    }
+
+    /// Sends a notification to the server, without waiting for a response.
+    pub fn notify<'p>(
+        &self,
+        method: &str,
+        params: impl Into<Params<'p>>,
+    ) -> Result<(), crate::Error> {
+        self.write(&ProtocolVersion::V2.request(method, params.into(), None))
+    }
+
+    /// Opens a subscription with a call to the `subscribe` method, whose notifications are then
+    /// received by iterating over the returned [`Subscription`].
+    ///
+    /// The subscription is closed with a call to the `unsubscribe` method, once dropped or with
+    /// [`Subscription::unsubscribe`].
+    pub fn subscribe<'p, T: for<'a> serde::de::Deserialize<'a>>(
+        &self,
+        subscribe: &str,
+        params: impl Into<Params<'p>>,
+        unsubscribe: &str,
+    ) -> Result<Subscription<T>, crate::Error> {
+        let (sender, receiver) = mpsc::channel();
+        let id = self.request(subscribe, params.into(), Some(sender))?.result()?;
+        Ok(Subscription {
+            client: self.clone(),
+            id,
+            unsubscribe: unsubscribe.to_owned(),
+            notifications: receiver,
+            open: true,
+            _result: PhantomData,
+        })
+    }
+}
+
+impl fmt::Debug for PubSubClient {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "jsonrpc::pubsub::PubSubClient({})", self.conn.target)
+    }
+}
+
+/// The notifications of a subscription, deserialized as `T`.
+///
+/// Iterating blocks until the next notification is received, and ends once the connection is
+/// closed.
+pub struct Subscription<T> {
+    client: PubSubClient,
+    id: serde_json::Value,
+    unsubscribe: String,
+    notifications: mpsc::Receiver<Box<RawValue>>,
+    open: bool,
+    _result: PhantomData<fn() -> T>,
+}
+
+impl<T> Subscription<T> {
+    /// Returns the ID the server gave to the subscription.
+    pub fn id(&self) -> &serde_json::Value {
+        &self.id
+    }
+
+    /// Closes the subscription, and returns whether the server knew of it.
+    pub fn unsubscribe(mut self) -> Result<bool, crate::Error> {
+        self.open = false;
+        self.forget();
+        let params = [crate::arg(&self.id)];
+        self.client.call(&self.unsubscribe, &params)
+    }
+
+    /// Stops receiving the notifications of the subscription.
+    fn forget(&self) {
+        self.client.state().subscriptions.remove(&self.id.to_string());
+    }
+}
+
+impl<T: for<'a> serde::de::Deserialize<'a>> Iterator for Subscription<T> {
+    type Item = Result<T, crate::Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let result = self.notifications.recv().ok()?;
+        Some(serde_json::from_str(result.get()).map_err(crate::Error::Json))
+    }
+}
+
+impl<T> Drop for Subscription<T> {
+    fn drop(&mut self) {
+        if self.open {
+            self.forget();
+            // The server doesn't reply to notifications, so this doesn't wait for it.
+            let params = [crate::arg(&self.id)];
+            let _ = self.client.notify(&self.unsubscribe, &params);
+        }
+    }
+}
+
+impl<T> fmt::Debug for Subscription<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Subscription")
+            .field("client", &self.client)
+            .field("id", &self.id)
+            .field("unsubscribe", &self.unsubscribe)
+            .finish()
+    }
+}
+
+#[cfg(all(test, feature = "simple_tcp", feature = "simple_tcp_server"))]
+mod tests {
+    use super::*;
+    use crate::arg;
+    use crate::error::RpcError;
+    use crate::server::{Publisher, Router};
+    use crate::simple_tcp_server::TcpServer;
+
+    #[test]
+    fn subscriptions() {
+        let publisher = Publisher::new("block");
+        let router = Router::new()
+            .subscription("subscribe", "unsubscribe", &publisher)
+            .typed_method("publish", {
+                let publisher = publisher.clone();
+                move |n: u32| -> Result<usize, RpcError> { Ok(publisher.publish(&n).unwrap()) }
+            });
+        let server = TcpServer::builder(router).bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr();
+        let handle = server.shutdown_handle();
+        let server = thread::spawn(move || server.run());
+
+        let client = PubSubClient::connect_tcp(addr).unwrap().timeout(Some(Duration::from_secs(5)));
+        let mut blocks = client.subscribe::<u32>("subscribe", &[], "unsubscribe").unwrap();
+        let mut others = client.subscribe::<u32>("subscribe", &[], "unsubscribe").unwrap();
+        assert_ne!(blocks.id(), others.id());
+        assert_eq!(publisher.subscribers(), 2);
+
+        // Notifications published while handling a request follow its reply.
+        assert_eq!(client.call::<usize>("publish", &[arg(1)]).unwrap(), 2);
+        assert_eq!(publisher.publish(&2).unwrap(), 2);
+        assert_eq!(blocks.next().unwrap().unwrap(), 1);
+        assert_eq!(blocks.next().unwrap().unwrap(), 2);
+        assert_eq!(others.next().unwrap().unwrap(), 1);
+
+        assert!(others.unsubscribe().unwrap());
+        assert_eq!(publisher.subscribers(), 1);
+        assert!(!client.call::<bool>("unsubscribe", &[arg(0)]).unwrap());
+        drop(client);
+        assert_eq!(publisher.publish(&3).unwrap(), 1);
+        assert_eq!(blocks.next().unwrap().unwrap(), 3);
+
+        // Subscriptions are closed along with their connection.
+        handle.shutdown();
+        server.join().unwrap();
+        assert_eq!(publisher.subscribers(), 0);
+        assert!(blocks.next().is_none());
+    }
+}
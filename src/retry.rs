@@ -0,0 +1,427 @@
+//! # Retrying transport
+//!
+//! A [`Transport`] wrapper which retries calls that failed because of a transient transport
+//! error, e.g. because the server restarted or the connection was dropped, waiting for an
+//! exponentially growing, jittered delay between attempts.
+//!
+//! Only calls to methods which are known to be idempotent are retried, since a call which
+//! failed on our side may still have been executed by the server. The idempotent methods are
+//! configured with [`RetryPolicy::idempotent_methods`].
+//!
+
+use std::cell::Cell;
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::Duration;
+use std::{fmt, thread};
+
+use crate::client::{ReadBody, Transport};
+use crate::error::Error;
+use crate::layer::Layer;
+use crate::util;
+use crate::{Request, Response};
+
+/// Returns whether the error is likely to go away when retrying, which is the case for
+/// transport errors, except for HTTP client errors like failed authentication.
+pub fn is_transient(err: &Error) -> bool {
+    match *err {
+        Error::Transport(ref e) => {
+            #[cfg(feature = "simple_http")]
+            {
+                if let Some(crate::simple_http::Error::HttpErrorCode(code)) = e.downcast_ref() {
+                    return !(400..500).contains(code);
+                }
+                if let Some(crate::simple_http::Error::InvalidUrl {
+                    ..
+                }) = e.downcast_ref()
+                {
+                    return false;
+                }
+            }
+            let _ = e;
+            true
+        }
+        _ => false,
+    }
+}
+
+/// Which methods may be retried.
+#[derive(Clone, Debug)]
+enum Idempotent {
+    All,
+    Methods(HashSet<String>),
+}
+
+/// Configuration of a [`RetryTransport`].
+///
+/// The policy is also a [`Layer`], so it can be applied to the transport of a client with
+/// [`crate::client::Builder::layer`].
+#[derive(Clone)]
+pub struct RetryPolicy {
+    max_retries: usize,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+    multiplier: u32,
+    jitter: f64,
+    idempotent: Idempotent,
+    retry_if: Arc<dyn Fn(&Error) -> bool + Send + Sync>,
+}
+
+impl RetryPolicy {
+    /// Creates a policy with the default configuration: up to 3 retries, with delays starting
+    /// at 100ms, doubling on every retry up to 5s and 50% jitter, and retrying errors for which
+    /// [`is_transient`] holds. No method is considered idempotent yet.
+    pub fn new() -> RetryPolicy {
+        RetryPolicy {
+            max_retries: 3,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(5),
+            multiplier: 2,
+            jitter: 0.5,
+            idempotent: Idempotent::Methods(HashSet::new()),
+            retry_if: Arc::new(is_transient),
+        }
+    }
+
+    /// Sets the maximum number of retries after the first attempt.
+    pub fn max_retries(mut self, max_retries: usize) -> Self {
+        self.max_retries = max_retries;
+        self
+    }
+
+    /// Sets the delay before the first retry.
+    pub fn initial_backoff(mut self, backoff: Duration) -> Self {
+        self.initial_backoff = backoff;
+        self
+    }
+
+    /// Sets the maximum delay between two attempts.
+    pub fn max_backoff(mut self, backoff: Duration) -> Self {
+        self.max_backoff = backoff;
+        self
+    }
+
+    /// Sets the factor by which the delay grows after every retry.
+    pub fn multiplier(mut self, multiplier: u32) -> Self {
+        self.multiplier = multiplier;
+        self
+    }
+
+    /// Sets the fraction of the delay, between 0 and 1, which is randomly taken off every delay
+    /// so that clients don't retry in lockstep.
+    pub fn jitter(mut self, jitter: f64) -> Self {
+        self.jitter = jitter.max(0.0).min(1.0);
+        self
+    }
+
+    /// Adds methods which are safe to retry.
+    pub fn idempotent_methods<I, S>(mut self, methods: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        if let Idempotent::Methods(ref mut set) = self.idempotent {
+            set.extend(methods.into_iter().map(|m| m.as_ref().to_owned()));
+        }
+        self
+    }
+
+    /// Considers every method safe to retry.
+    pub fn all_methods_idempotent(mut self) -> Self {
+        self.idempotent = Idempotent::All;
+        self
+    }
+
+    /// Sets the predicate deciding which errors are retried, instead of [`is_transient`].
+    pub fn retry_if<F>(mut self, retry_if: F) -> Self
+    where
+        F: Fn(&Error) -> bool + Send + Sync + 'static,
+    {
+        self.retry_if = Arc::new(retry_if);
+        self
+    }
+
+    /// Returns whether calls to the method may be retried.
+    pub fn is_idempotent(&self, method: &str) -> bool {
+        match self.idempotent {
+            Idempotent::All => true,
+            Idempotent::Methods(ref set) => set.contains(method),
+        }
+    }
+
+    /// Returns the delay to wait before the given retry, starting at 0 for the first retry.
+    fn backoff(&self, retry: usize) -> Duration {
+        let mut delay = self.initial_backoff;
+        for _ in 0..retry {
+            delay = delay.checked_mul(self.multiplier).unwrap_or(self.max_backoff);
+            if delay >= self.max_backoff {
+                break;
+            }
+        }
+        let delay = delay.min(self.max_backoff);
+
+        let random = util::random_u64() as f64 / std::u64::MAX as f64;
+        delay.mul_f64(1.0 - self.jitter * random)
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy::new()
+    }
+}
+
+impl fmt::Debug for RetryPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("RetryPolicy")
+            .field("max_retries", &self.max_retries)
+            .field("initial_backoff", &self.initial_backoff)
+            .field("max_backoff", &self.max_backoff)
+            .field("multiplier", &self.multiplier)
+            .field("jitter", &self.jitter)
+            .field("idempotent", &self.idempotent)
+            .finish()
+    }
+}
+
+impl<T: Transport> Layer<T> for RetryPolicy {
+    type Transport = RetryTransport<T>;
+
+    fn layer(&self, inner: T) -> RetryTransport<T> {
+        RetryTransport::new(inner, self.clone())
+    }
+}
+
+/// A transport retrying failed calls to idempotent methods, see the [module docs](self).
+///
+/// A batch is only retried if all the methods it calls are idempotent.
+#[derive(Debug)]
+pub struct RetryTransport<T> {
+    inner: T,
+    policy: RetryPolicy,
+}
+
+impl<T: Transport> RetryTransport<T> {
+    /// Wraps `inner`, retrying calls according to `policy`.
+    pub fn new(inner: T, policy: RetryPolicy) -> RetryTransport<T> {
+        RetryTransport {
+            inner,
+            policy,
+        }
+    }
+
+    /// Returns a reference to the wrapped transport.
+    pub fn inner(&self) -> &T {
+        &self.inner
+    }
+
+    /// Runs `attempt` until it succeeds, fails with an error which shouldn't be retried, or we
+    /// run out of retries. Failed attempts are only retried while `may_retry` returns true.
+    fn retry<R, F>(&self, may_retry: &dyn Fn() -> bool, mut attempt: F) -> Result<R, Error>
+    where
+        F: FnMut() -> Result<R, Error>,
+    {
+        let mut retry = 0;
+        loop {
+            match attempt() {
+                Err(ref e)
+                    if may_retry()
+                        && retry < self.policy.max_retries
+                        && (self.policy.retry_if)(e) =>
+                {
+                    thread::sleep(self.policy.backoff(retry));
+                    retry += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+impl<T: Transport> Transport for RetryTransport<T> {
+    fn send_request(&self, req: Request) -> Result<Response, Error> {
+        let idempotent = self.policy.is_idempotent(req.method);
+        self.retry(&|| idempotent, || self.inner.send_request(req.clone()))
+    }
+
+    fn send_request_streaming(&self, req: Request, read_body: &mut ReadBody) -> Result<(), Error> {
+        // The body can only be handed over once, so a call failing after that isn't retried.
+        let idempotent = self.policy.is_idempotent(req.method);
+        let body_read = Cell::new(false);
+        self.retry(&|| idempotent && !body_read.get(), || {
+            self.inner.send_request_streaming(req.clone(), &mut |body| {
+                body_read.set(true);
+                read_body(body)
+            })
+        })
+    }
+
+    fn send_batch(&self, reqs: &[Request]) -> Result<Vec<Response>, Error> {
+        let idempotent = reqs.iter().all(|r| self.policy.is_idempotent(r.method));
+        self.retry(&|| idempotent, || self.inner.send_batch(reqs))
+    }
+
+    fn send_notification(&self, req: Request) -> Result<(), Error> {
+        let idempotent = self.policy.is_idempotent(req.method);
+        self.retry(&|| idempotent, || self.inner.send_notification(req.clone()))
+    }
+
+    fn fmt_target(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.inner.fmt_target(f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::{error, io};
+
+    use super::*;
+    use crate::client::tests::EchoTransport;
+    use crate::Client;
+
+    /// Fails the given number of times with a transport error before succeeding.
+    struct Flaky {
+        failures: usize,
+        attempts: AtomicUsize,
+        error: fn() -> Error,
+    }
+
+    impl Flaky {
+        fn new(failures: usize) -> Flaky {
+            Flaky {
+                failures,
+                attempts: AtomicUsize::new(0),
+                error: || {
+                    Error::Transport(Box::new(io::Error::from(io::ErrorKind::ConnectionReset)))
+                },
+            }
+        }
+
+        fn attempt(&self) -> Result<(), Error> {
+            if self.attempts.fetch_add(1, Ordering::SeqCst) < self.failures {
+                Err((self.error)())
+            } else {
+                Ok(())
+            }
+        }
+    }
+
+    impl Transport for Flaky {
+        fn send_request(&self, req: Request) -> Result<Response, Error> {
+            self.attempt()?;
+            EchoTransport.send_request(req)
+        }
+        fn send_batch(&self, reqs: &[Request]) -> Result<Vec<Response>, Error> {
+            self.attempt()?;
+            EchoTransport.send_batch(reqs)
+        }
+        fn send_notification(&self, _: Request) -> Result<(), Error> {
+            self.attempt()
+        }
+        fn fmt_target(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "flaky")
+        }
+    }
+
+    fn policy() -> RetryPolicy {
+        RetryPolicy::new().initial_backoff(Duration::from_millis(1)).max_retries(3)
+    }
+
+    #[test]
+    fn retries_idempotent_methods() {
+        let tp = Arc::new(Flaky::new(2));
+        let client =
+            Client::builder(tp.clone()).layer(policy().idempotent_methods(&["getblock"])).build();
+        assert_eq!(client.call::<u64>("getblock", &[]).unwrap(), 1);
+        assert_eq!(tp.attempts.load(Ordering::SeqCst), 3);
+        assert_eq!(client.call_streaming::<u64>("getblock", &[]).unwrap(), 2);
+
+        // Other methods are not retried.
+        let tp = Arc::new(Flaky::new(2));
+        let client =
+            Client::builder(tp.clone()).layer(policy().idempotent_methods(&["getblock"])).build();
+        assert!(client.call::<u64>("sendrawtransaction", &[]).is_err());
+        assert_eq!(tp.attempts.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn gives_up() {
+        let tp = Arc::new(Flaky::new(10));
+        let client = Client::builder(tp.clone()).layer(policy().all_methods_idempotent()).build();
+        match client.call::<u64>("getblock", &[]) {
+            Err(Error::Transport(_)) => {}
+            r => panic!("expected transport error, got {:?}", r),
+        }
+        assert_eq!(tp.attempts.load(Ordering::SeqCst), 4);
+    }
+
+    #[test]
+    fn only_transient_errors() {
+        let mut flaky = Flaky::new(2);
+        flaky.error = || Error::NonceMismatch;
+        let tp = Arc::new(flaky);
+        let client = Client::builder(tp.clone()).layer(policy().all_methods_idempotent()).build();
+        assert!(client.call::<u64>("getblock", &[]).is_err());
+        assert_eq!(tp.attempts.load(Ordering::SeqCst), 1);
+
+        let tp = Arc::new(Flaky::new(2));
+        let never = policy().all_methods_idempotent().retry_if(|_| false);
+        let client = Client::builder(tp.clone()).layer(never).build();
+        assert!(client.call::<u64>("getblock", &[]).is_err());
+        assert_eq!(tp.attempts.load(Ordering::SeqCst), 1);
+
+        let e: Box<dyn error::Error + Send + Sync> = From::from("connection refused");
+        assert!(is_transient(&Error::Transport(e)));
+        assert!(!is_transient(&Error::Rpc(crate::error::standard_error(
+            crate::error::StandardError::InternalError,
+            None
+        ))));
+        #[cfg(feature = "simple_http")]
+        {
+            use crate::simple_http::Error as HttpError;
+            assert!(!is_transient(&HttpError::HttpErrorCode(401).into()));
+            assert!(is_transient(&HttpError::HttpErrorCode(503).into()));
+        }
+    }
+
+    #[test]
+    fn batches() {
+        let policy = policy().idempotent_methods(vec!["getblock", "getblockhash"]);
+
+        let tp = Arc::new(Flaky::new(1));
+        let client = Client::builder(tp.clone()).layer(policy.clone()).build();
+        let batch =
+            [client.build_request("getblock", &[]), client.build_request("getblockhash", &[])];
+        assert_eq!(client.send_batch(&batch).unwrap().len(), 2);
+        assert_eq!(tp.attempts.load(Ordering::SeqCst), 2);
+
+        // A single non-idempotent method prevents retrying the whole batch.
+        let tp = Arc::new(Flaky::new(1));
+        let client = Client::builder(tp.clone()).layer(policy).build();
+        let batch = [client.build_request("getblock", &[]), client.build_request("stop", &[])];
+        assert!(client.send_batch(&batch).is_err());
+        assert_eq!(tp.attempts.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn backoff() {
+        let policy = RetryPolicy::new()
+            .initial_backoff(Duration::from_millis(100))
+            .max_backoff(Duration::from_millis(1000))
+            .multiplier(3)
+            .jitter(0.0);
+        assert_eq!(policy.backoff(0), Duration::from_millis(100));
+        assert_eq!(policy.backoff(1), Duration::from_millis(300));
+        assert_eq!(policy.backoff(2), Duration::from_millis(900));
+        assert_eq!(policy.backoff(3), Duration::from_millis(1000));
+        assert_eq!(policy.backoff(100), Duration::from_millis(1000));
+
+        let policy = policy.jitter(0.5);
+        for retry in 0..5 {
+            let delay = policy.backoff(retry);
+            assert!(delay <= Duration::from_millis(1000));
+            assert!(delay >= Duration::from_millis(50));
+        }
+    }
+}
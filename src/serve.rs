@@ -0,0 +1,391 @@
+//! Accept loops shared by the bundled servers: connections are accepted, served on threads of
+//! their own or of a pool, and closed on shutdown.
+//!
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::{self, IpAddr, Ipv4Addr, Ipv6Addr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, Condvar, Mutex, MutexGuard};
+use std::time::Duration;
+use std::{panic, thread};
+
+#[cfg(all(feature = "simple_uds_server", not(windows)))]
+use std::os::unix::net::{UnixListener, UnixStream};
+
+#[cfg(any(feature = "simple_tcp_server", all(feature = "simple_uds_server", not(windows))))]
+use crate::error::StandardError;
+#[cfg(any(feature = "simple_tcp_server", all(feature = "simple_uds_server", not(windows))))]
+use crate::server::{self, Router, Session};
+
+/// How long to wait before accepting connections again after failing to, e.g. because the
+/// process ran out of file descriptors.
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(10);
+
+/// How a server distributes its connections among threads.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Workers {
+    /// Each connection is served on a thread of its own.
+    PerConnection,
+    /// Connections are served by a fixed number of threads, at least one. Once all of them are
+    /// busy, further connections wait to be accepted.
+    Pool(usize),
+}
+
+impl Default for Workers {
+    fn default() -> Self {
+        Workers::PerConnection
+    }
+}
+
+/// A connection accepted by a server.
+pub(crate) trait Connection: Read + Write + Send + Sync + Sized + 'static {
+    fn try_clone(&self) -> io::Result<Self>;
+    fn shutdown_read(&self) -> io::Result<()>;
+    fn set_timeout(&self, timeout: Option<Duration>) -> io::Result<()>;
+    #[cfg(any(feature = "simple_tcp_server", all(feature = "simple_uds_server", not(windows))))]
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()>;
+}
+
+/// A listener accepting connections.
+pub(crate) trait Listener: Send + 'static {
+    type Connection: Connection;
+
+    fn accept(&self) -> io::Result<Self::Connection>;
+
+    /// Returns a function connecting to the listener, to wake it up from [`Listener::accept`].
+    fn waker(&self) -> io::Result<Box<dyn Fn() + Send + Sync>>;
+}
+
+impl Connection for TcpStream {
+    fn try_clone(&self) -> io::Result<Self> {
+        TcpStream::try_clone(self)
+    }
+
+    fn shutdown_read(&self) -> io::Result<()> {
+        self.shutdown(net::Shutdown::Read)
+    }
+
+    fn set_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_read_timeout(self, timeout)?;
+        self.set_write_timeout(timeout)
+    }
+
+    #[cfg(any(feature = "simple_tcp_server", all(feature = "simple_uds_server", not(windows))))]
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_read_timeout(self, timeout)
+    }
+}
+
+impl Listener for TcpListener {
+    type Connection = TcpStream;
+
+    fn accept(&self) -> io::Result<TcpStream> {
+        TcpListener::accept(self).map(|(stream, _)| stream)
+    }
+
+    fn waker(&self) -> io::Result<Box<dyn Fn() + Send + Sync>> {
+        let mut addr = self.local_addr()?;
+        if addr.ip().is_unspecified() {
+            addr.set_ip(match addr.ip() {
+                IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::LOCALHOST),
+                IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::LOCALHOST),
+            });
+        }
+        Ok(Box::new(move || {
+            let _ = TcpStream::connect_timeout(&addr, Duration::from_secs(1));
+        }))
+    }
+}
+
+#[cfg(all(feature = "simple_uds_server", not(windows)))]
+impl Connection for UnixStream {
+    fn try_clone(&self) -> io::Result<Self> {
+        UnixStream::try_clone(self)
+    }
+
+    fn shutdown_read(&self) -> io::Result<()> {
+        self.shutdown(net::Shutdown::Read)
+    }
+
+    fn set_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        UnixStream::set_read_timeout(self, timeout)?;
+        self.set_write_timeout(timeout)
+    }
+
+    #[cfg(any(feature = "simple_tcp_server", all(feature = "simple_uds_server", not(windows))))]
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        UnixStream::set_read_timeout(self, timeout)
+    }
+}
+
+#[cfg(all(feature = "simple_uds_server", not(windows)))]
+impl Listener for UnixListener {
+    type Connection = UnixStream;
+
+    fn accept(&self) -> io::Result<UnixStream> {
+        UnixListener::accept(self).map(|(stream, _)| stream)
+    }
+
+    fn waker(&self) -> io::Result<Box<dyn Fn() + Send + Sync>> {
+        let addr = self.local_addr()?;
+        let path = match addr.as_pathname() {
+            Some(path) => path.to_owned(),
+            None => return Err(io::Error::new(io::ErrorKind::Other, "unnamed socket")),
+        };
+        Ok(Box::new(move || {
+            let _ = UnixStream::connect(&path);
+        }))
+    }
+}
+
+/// State shared between a server, its connections and its shutdown handles.
+struct Shared {
+    shutdown: AtomicBool,
+    wake: Box<dyn Fn() + Send + Sync>,
+    /// Functions closing the reading side of each open connection, by a unique ID.
+    connections: Mutex<HashMap<u64, Box<dyn Fn() + Send>>>,
+    /// Notified whenever a connection is closed.
+    closed: Condvar,
+}
+
+impl Shared {
+    fn connections(&self) -> MutexGuard<'_, HashMap<u64, Box<dyn Fn() + Send>>> {
+        self.connections.lock().expect("poisoned mutex")
+    }
+
+    /// Unblocks the connections waiting for a request, so that they are closed.
+    fn close_connections(&self) {
+        for close in self.connections().values() {
+            close();
+        }
+    }
+}
+
+/// A handle to stop a server.
+#[derive(Clone)]
+pub struct ShutdownHandle {
+    shared: Arc<Shared>,
+}
+
+impl ShutdownHandle {
+    /// Stops the server from accepting connections, and closes the open ones once their current
+    /// request is handled.
+    pub fn shutdown(&self) {
+        if !self.shared.shutdown.swap(true, Ordering::AcqRel) {
+            self.shared.close_connections();
+            (self.shared.wake)();
+        }
+    }
+
+    /// Returns whether the server was shut down.
+    pub fn is_shutdown(&self) -> bool {
+        self.shared.shutdown.load(Ordering::Acquire)
+    }
+}
+
+impl fmt::Debug for ShutdownHandle {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ShutdownHandle").field("shutdown", &self.is_shutdown()).finish()
+    }
+}
+
+/// A job run by a worker.
+type Job = Box<dyn FnOnce() + Send>;
+
+/// Runs the jobs of a worker pool until the sending side of the channel is dropped.
+fn run_jobs(receiver: &Mutex<mpsc::Receiver<Job>>) {
+    loop {
+        let job = receiver.lock().expect("poisoned mutex").recv();
+        match job {
+            // A panicking handler only loses its connection, not the worker.
+            Ok(job) => {
+                let _ = panic::catch_unwind(panic::AssertUnwindSafe(job));
+            }
+            Err(_) => return,
+        }
+    }
+}
+
+/// Unregisters a connection when dropped, even if serving it panicked, which closes it.
+struct Registration {
+    handle: ShutdownHandle,
+    id: u64,
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        self.handle.shared.connections().remove(&self.id);
+        self.handle.shared.closed.notify_all();
+    }
+}
+
+/// Accepts the connections of a listener until shut down.
+pub(crate) struct Acceptor<L> {
+    listener: L,
+    shared: Arc<Shared>,
+}
+
+impl<L: Listener> Acceptor<L> {
+    pub(crate) fn new(listener: L) -> io::Result<Acceptor<L>> {
+        let shared = Shared {
+            shutdown: AtomicBool::new(false),
+            wake: listener.waker()?,
+            connections: Mutex::new(HashMap::new()),
+            closed: Condvar::new(),
+        };
+        Ok(Acceptor {
+            listener,
+            shared: Arc::new(shared),
+        })
+    }
+
+    pub(crate) fn shutdown_handle(&self) -> ShutdownHandle {
+        ShutdownHandle {
+            shared: self.shared.clone(),
+        }
+    }
+
+    /// Serves each connection with `serve` until shut down, and returns once all connections
+    /// are closed.
+    ///
+    /// `serve` is expected to return once the reading side of its connection is closed, and to
+    /// stop serving requests once the server is shut down.
+    pub(crate) fn run<F>(self, workers: Workers, serve: F)
+    where
+        F: Fn(L::Connection, &ShutdownHandle) + Send + Sync + 'static,
+    {
+        let serve = Arc::new(serve);
+        let (jobs, pool) = match workers {
+            Workers::PerConnection => (None, vec![]),
+            Workers::Pool(n) => {
+                // Jobs are handed over to a free worker, not queued.
+                let (sender, receiver) = mpsc::sync_channel::<Job>(0);
+                let receiver = Arc::new(Mutex::new(receiver));
+                let pool = (0..n.max(1))
+                    .map(|_| {
+                        let receiver = receiver.clone();
+                        thread::spawn(move || run_jobs(&receiver))
+                    })
+                    .collect();
+                (Some(sender), pool)
+            }
+        };
+
+        let mut next_id = 0u64;
+        loop {
+            let conn = match self.listener.accept() {
+                Ok(conn) => conn,
+                Err(_) => {
+                    // Accepting may keep failing, e.g. once out of file descriptors.
+                    if self.shutdown_handle().is_shutdown() {
+                        break;
+                    }
+                    thread::sleep(ACCEPT_BACKOFF);
+                    continue;
+                }
+            };
+            let handle = self.shutdown_handle();
+            if handle.is_shutdown() {
+                break;
+            }
+            let registered = match conn.try_clone() {
+                Ok(registered) => registered,
+                Err(_) => continue,
+            };
+            let id = next_id;
+            next_id += 1;
+            self.shared.connections().insert(
+                id,
+                Box::new(move || {
+                    let _ = registered.shutdown_read();
+                }),
+            );
+
+            let serve = serve.clone();
+            let job: Job = Box::new(move || {
+                let registration = Registration {
+                    handle,
+                    id,
+                };
+                // Connections waiting for a worker aren't served once shut down.
+                if !registration.handle.is_shutdown() {
+                    serve(conn, &registration.handle);
+                }
+            });
+            match jobs {
+                Some(ref jobs) => {
+                    // The workers only stop once the sender is dropped.
+                    let _ = jobs.send(job);
+                }
+                None => {
+                    thread::spawn(job);
+                }
+            }
+        }
+
+        drop(jobs);
+        // Connections accepted while shutting down may have been missed by the shutdown handle.
+        self.shared.close_connections();
+        let mut connections = self.shared.connections();
+        while !connections.is_empty() {
+            connections = self.shared.closed.wait(connections).expect("poisoned mutex");
+        }
+        drop(connections);
+        for worker in pool {
+            let _ = worker.join();
+        }
+    }
+}
+
+/// Serves a connection over which requests and responses are sent as concatenated JSON values,
+/// until the client closes it.
+///
+/// Notifications are pushed over the connection to the subscriptions made on it, which keep it
+/// open even if the client is otherwise idle.
+#[cfg(any(feature = "simple_tcp_server", all(feature = "simple_uds_server", not(windows))))]
+pub(crate) fn serve_json_stream<C: Connection>(
+    conn: C,
+    router: &Router,
+    timeout: Option<Duration>,
+    handle: &ShutdownHandle,
+) -> io::Result<()> {
+    use serde_json::value::RawValue;
+
+    conn.set_timeout(timeout)?;
+    let control = conn.try_clone()?;
+    let closer = conn.try_clone()?;
+    let session = Arc::new(Session::new(conn.try_clone()?, move || {
+        let _ = closer.shutdown_read();
+    }));
+    let values = serde_json::Deserializer::from_reader(io::BufReader::new(conn))
+        .into_iter::<Box<RawValue>>();
+    for value in values {
+        session.hold();
+        let reply = match value {
+            Ok(value) => router.handle_session(value.get().as_bytes(), &session),
+            Err(ref e) if e.is_syntax() => {
+                // There is no telling where the next value starts, so the connection is closed.
+                let reply = server::error_reply(StandardError::ParseError);
+                return session.reply(&reply);
+            }
+            // The client closed the connection, possibly in the middle of a value.
+            Err(_) => return Ok(()),
+        };
+        if let Some(reply) = reply {
+            session.reply(&reply)?;
+        }
+        session.release()?;
+        if handle.is_shutdown() {
+            break;
+        }
+        // Subscribed clients wait for notifications, and may not send anything for long.
+        control.set_read_timeout(if session.is_subscribed() {
+            None
+        } else {
+            timeout
+        })?;
+    }
+    Ok(())
+}
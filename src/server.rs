@@ -0,0 +1,855 @@
+//! # Serving requests
+//!
+//! A [`Router`] dispatching JSON-RPC requests to handlers registered by method name. It
+//! works on raw request bodies and is independent of any transport: a server reads a body, hands
+//! it to [`Router::handle`], and writes back the reply, if any.
+//!
+//! The router takes care of the protocol: it answers malformed bodies, invalid requests and
+//! unknown methods with the corresponding [`StandardError`], runs the requests of a batch in
+//! order, and doesn't reply to notifications. Requests without a `jsonrpc` member, or with a
+//! "1.0" one, are handled as JSON-RPC 1.0 requests and answered in kind.
+//!
+//! Handlers either take the raw parameters of requests, or are plain functions taking each
+//! parameter as an argument, see [`Router::typed_method`].
+//!
+//! Over persistent connections, such as those of the TCP and Unix Domain Socket servers, clients
+//! can also subscribe to notifications pushed by a [`Publisher`], see [`Router::subscription`].
+//!
+//! ```
+//! use jsonrpc::error::RpcError;
+//! use jsonrpc::server::{self, Router};
+//! use serde_json::json;
+//!
+//! let router = Router::new()
+//!     .method("add", |params: Option<&_>| {
+//!         let (a, b): (i64, i64) = server::parse_params(params)?;
+//!         Ok(json!(a + b))
+//!     })
+//!     .named_method("sub", &["a", "b"], |a: i64, b: i64| -> Result<i64, RpcError> { Ok(a - b) });
+//!
+//! let reply = router.handle(br#"{"jsonrpc":"2.0","method":"add","params":[1,2],"id":1}"#);
+//! assert_eq!(reply.unwrap(), r#"{"result":3,"id":1,"jsonrpc":"2.0"}"#);
+//! let reply = router.handle(br#"{"jsonrpc":"2.0","method":"sub","params":{"a":1,"b":2},"id":2}"#);
+//! assert_eq!(reply.unwrap(), r#"{"result":-1,"id":2,"jsonrpc":"2.0"}"#);
+//! ```
+//!
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, Write};
+use std::marker::PhantomData;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, Weak};
+
+use serde::{self, Deserialize};
+use serde_json;
+use serde_json::value::RawValue;
+
+use crate::client::ProtocolVersion;
+use crate::error::{result_to_response, standard_error, RpcError, StandardError};
+use crate::{Params, Response, ResponseBody};
+
+/// Handles the requests for a method.
+pub trait Handler: Send + Sync + 'static {
+    /// Handles a request with the given parameters, `None` if the request had none.
+    ///
+    /// Notifications are handled too, their result is then discarded.
+    fn handle(&self, params: Option<&RawValue>) -> Result<serde_json::Value, RpcError>;
+}
+
+impl<F> Handler for F
+where
+    F: Fn(Option<&RawValue>) -> Result<serde_json::Value, RpcError> + Send + Sync + 'static,
+{
+    fn handle(&self, params: Option<&RawValue>) -> Result<serde_json::Value, RpcError> {
+        self(params)
+    }
+}
+
+/// Deserializes the parameters of a request, failing with [`StandardError::InvalidParams`].
+///
+/// Missing parameters are deserialized from `null`, so that they can be read as `()` or as an
+/// `Option`.
+pub fn parse_params<T: for<'a> serde::de::Deserialize<'a>>(
+    params: Option<&RawValue>,
+) -> Result<T, RpcError> {
+    let result = match params {
+        Some(params) => serde_json::from_str(params.get()),
+        None => T::deserialize(serde_json::Value::Null),
+    };
+    result.map_err(params_error)
+}
+
+/// Returns the error for parameters which failed to deserialize.
+fn params_error(e: serde_json::Error) -> RpcError {
+    invalid_params(serde_json::json!({
+        "error": e.to_string(),
+    }))
+}
+
+/// Returns a [`StandardError::InvalidParams`] error with the given data.
+fn invalid_params(data: serde_json::Value) -> RpcError {
+    standard_error(StandardError::InvalidParams, serde_json::value::to_raw_value(&data).ok())
+}
+
+/// Functions handling the requests for a method, taking each parameter as an argument, see
+/// [`Router::typed_method`].
+///
+/// This is implemented for functions and closures of up to 12 arguments which can be
+/// deserialized, returning a `Result` with an [`RpcError`] whose value can be serialized.
+pub trait TypedHandler<Args>: Send + Sync + 'static {
+    /// The number of arguments.
+    const ARITY: usize;
+
+    /// Calls the function with the given arguments, `None` for missing ones.
+    ///
+    /// `names` are the names of the arguments, if known, reported along with failures to
+    /// deserialize them.
+    fn call(
+        &self,
+        args: &[Option<&RawValue>],
+        names: &[String],
+    ) -> Result<serde_json::Value, RpcError>;
+}
+
+/// Deserializes the argument at `index` of a [`TypedHandler`].
+///
+/// Missing arguments are deserialized from `null`, so that `Option` arguments are optional.
+fn parse_arg<T: for<'a> serde::de::Deserialize<'a>>(
+    args: &[Option<&RawValue>],
+    names: &[String],
+    index: usize,
+) -> Result<T, RpcError> {
+    let result = match args[index] {
+        Some(arg) => serde_json::from_str(arg.get()),
+        None => T::deserialize(serde_json::Value::Null),
+    };
+    result.map_err(|e| {
+        let error = match args[index] {
+            Some(_) => e.to_string(),
+            None => "missing parameter".to_owned(),
+        };
+        let mut data = serde_json::json!({
+            "index": index,
+            "error": error,
+        });
+        if let Some(name) = names.get(index) {
+            data["name"] = name.as_str().into();
+        }
+        invalid_params(data)
+    })
+}
+
+macro_rules! impl_typed_handler {
+    ($n:expr; $($arg:ident $index:expr),*) => {
+        impl<F, R, $($arg),*> TypedHandler<($($arg,)*)> for F
+        where
+            F: Fn($($arg),*) -> Result<R, RpcError> + Send + Sync + 'static,
+            R: serde::Serialize,
+            $($arg: for<'a> serde::de::Deserialize<'a>,)*
+        {
+            const ARITY: usize = $n;
+
+            #[allow(unused_variables)]
+            fn call(
+                &self,
+                args: &[Option<&RawValue>],
+                names: &[String],
+            ) -> Result<serde_json::Value, RpcError> {
+                let result = self($(parse_arg::<$arg>(args, names, $index)?),*)?;
+                serde_json::to_value(result).map_err(|e| {
+                    let data = serde_json::value::to_raw_value(&e.to_string()).ok();
+                    standard_error(StandardError::InternalError, data)
+                })
+            }
+        }
+    };
+}
+impl_typed_handler!(0;);
+impl_typed_handler!(1; A 0);
+impl_typed_handler!(2; A 0, B 1);
+impl_typed_handler!(3; A 0, B 1, C 2);
+impl_typed_handler!(4; A 0, B 1, C 2, D 3);
+impl_typed_handler!(5; A 0, B 1, C 2, D 3, E 4);
+impl_typed_handler!(6; A 0, B 1, C 2, D 3, E 4, G 5);
+impl_typed_handler!(7; A 0, B 1, C 2, D 3, E 4, G 5, H 6);
+impl_typed_handler!(8; A 0, B 1, C 2, D 3, E 4, G 5, H 6, I 7);
+impl_typed_handler!(9; A 0, B 1, C 2, D 3, E 4, G 5, H 6, I 7, J 8);
+impl_typed_handler!(10; A 0, B 1, C 2, D 3, E 4, G 5, H 6, I 7, J 8, K 9);
+impl_typed_handler!(11; A 0, B 1, C 2, D 3, E 4, G 5, H 6, I 7, J 8, K 9, L 10);
+impl_typed_handler!(12; A 0, B 1, C 2, D 3, E 4, G 5, H 6, I 7, J 8, K 9, L 10, M 11);
+
+/// A [`TypedHandler`] registered with a router.
+struct Typed<F, Args> {
+    f: F,
+    /// The names of the parameters, empty if they can only be passed by position.
+    names: Vec<String>,
+    _args: PhantomData<fn(Args)>,
+}
+
+impl<F: TypedHandler<Args>, Args: 'static> Handler for Typed<F, Args> {
+    fn handle(&self, params: Option<&RawValue>) -> Result<serde_json::Value, RpcError> {
+        let mut args = vec![None; F::ARITY];
+        match params {
+            None => {}
+            Some(params) if params.get().starts_with('[') => {
+                let values: Vec<&RawValue> =
+                    serde_json::from_str(params.get()).map_err(params_error)?;
+                if values.len() > F::ARITY {
+                    return Err(invalid_params(serde_json::json!({
+                        "error": format!(
+                            "expected at most {} parameters, got {}",
+                            F::ARITY,
+                            values.len()
+                        ),
+                    })));
+                }
+                for (arg, value) in args.iter_mut().zip(values) {
+                    *arg = Some(value);
+                }
+            }
+            Some(_) if self.names.is_empty() => {
+                return Err(invalid_params(serde_json::json!({
+                    "error": "parameters can only be passed by position",
+                })));
+            }
+            Some(params) => {
+                let values: HashMap<String, &RawValue> =
+                    serde_json::from_str(params.get()).map_err(params_error)?;
+                for (name, value) in values {
+                    match self.names.iter().position(|n| *n == name) {
+                        Some(index) => args[index] = Some(value),
+                        None => {
+                            return Err(invalid_params(serde_json::json!({
+                                "error": format!("unknown parameter `{}`", name),
+                            })));
+                        }
+                    }
+                }
+            }
+        }
+        self.f.call(&args, &self.names)
+    }
+}
+
+/// A request as received by the server.
+#[derive(Deserialize)]
+struct IncomingRequest<'a> {
+    #[serde(borrow)]
+    method: Cow<'a, str>,
+    /// The parameters, `None` if missing and `Some` if `null`, which is rejected.
+    #[serde(borrow, default, deserialize_with = "deserialize_params")]
+    params: Option<&'a RawValue>,
+    /// The ID, `None` for notifications and `Some(Null)` for requests with a `null` ID.
+    #[serde(default, deserialize_with = "deserialize_id")]
+    id: Option<serde_json::Value>,
+    #[serde(borrow)]
+    jsonrpc: Option<Cow<'a, str>>,
+}
+
+/// Deserializes an `id` member which is present, even if it is `null`.
+fn deserialize_id<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<serde_json::Value>, D::Error> {
+    serde_json::Value::deserialize(deserializer).map(Some)
+}
+
+/// Deserializes a `params` member which is present, even if it is `null`.
+fn deserialize_params<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<&'de RawValue>, D::Error> {
+    <&RawValue>::deserialize(deserializer).map(Some)
+}
+
+impl<'a> IncomingRequest<'a> {
+    /// Returns the version of the protocol the request was made in.
+    fn version(&self) -> ProtocolVersion {
+        match self.jsonrpc.as_deref() {
+            Some("2.0") => ProtocolVersion::V2,
+            _ => ProtocolVersion::V1,
+        }
+    }
+
+    /// Returns whether the request is well formed beyond its JSON structure.
+    fn is_valid(&self) -> bool {
+        let valid_version = match self.jsonrpc.as_deref() {
+            None | Some("1.0") | Some("2.0") => true,
+            Some(_) => false,
+        };
+        let valid_id = match self.id {
+            None
+            | Some(serde_json::Value::Null)
+            | Some(serde_json::Value::Number(_))
+            | Some(serde_json::Value::String(_)) => true,
+            Some(_) => false,
+        };
+        let valid_params = match self.params.map(|p| p.get().as_bytes()[0]) {
+            None | Some(b'[') | Some(b'{') => true,
+            Some(_) => false,
+        };
+        valid_version && valid_id && valid_params
+    }
+}
+
+/// Dispatches requests to the handlers of their method.
+///
+/// Handlers are registered with [`Router::method`], and requests are served with
+/// [`Router::handle`]. The router can be shared between threads serving requests concurrently.
+#[derive(Default)]
+pub struct Router {
+    methods: HashMap<String, Method>,
+}
+
+/// What a method registered with a router does.
+enum Method {
+    Handler(Box<dyn Handler>),
+    Subscribe(Publisher),
+    Unsubscribe(Publisher),
+}
+
+impl Router {
+    /// Creates a router without any method.
+    pub fn new() -> Router {
+        Router {
+            methods: HashMap::new(),
+        }
+    }
+
+    /// Registers the handler of the given method, replacing any previous one.
+    pub fn method<S: Into<String>, H: Handler>(mut self, name: S, handler: H) -> Self {
+        self.methods.insert(name.into(), Method::Handler(Box::new(handler)));
+        self
+    }
+
+    /// Registers a function handling the given method, replacing any previous handler.
+    ///
+    /// The parameters of requests are passed by position, and deserialized into the arguments
+    /// of the function, see [`TypedHandler`]. Missing parameters are deserialized from `null`,
+    /// so that `Option` arguments are optional. The result of the function is serialized into
+    /// the response.
+    pub fn typed_method<S, Args, F>(self, name: S, f: F) -> Self
+    where
+        S: Into<String>,
+        Args: 'static,
+        F: TypedHandler<Args>,
+    {
+        let handler = Typed {
+            f,
+            names: vec![],
+            _args: PhantomData,
+        };
+        self.method(name, handler)
+    }
+
+    /// Registers a function handling the given method as [`Router::typed_method`] does, whose
+    /// parameters can also be passed by name.
+    ///
+    /// # Panics
+    ///
+    /// If the number of names doesn't match the number of arguments of the function.
+    pub fn named_method<S, Args, F>(self, name: S, params: &[&str], f: F) -> Self
+    where
+        S: Into<String>,
+        Args: 'static,
+        F: TypedHandler<Args>,
+    {
+        assert_eq!(params.len(), F::ARITY, "one name is needed for each argument");
+        let handler = Typed {
+            f,
+            names: params.iter().map(|&name| name.to_owned()).collect(),
+            _args: PhantomData,
+        };
+        self.method(name, handler)
+    }
+
+    /// Registers the methods subscribing to the notifications of `publisher` and unsubscribing
+    /// from them, replacing any previous handlers.
+    ///
+    /// The `subscribe` method returns the ID of a new subscription, whatever its parameters.
+    /// The `unsubscribe` method takes that ID as its only parameter, and returns whether the
+    /// subscription was closed. Subscriptions are tied to the connection they were made on, and
+    /// are closed along with it; subscribing fails with [`StandardError::MethodNotFound`] when
+    /// requests aren't served over a persistent connection, e.g. by [`Router::handle`].
+    pub fn subscription<S, U>(mut self, subscribe: S, unsubscribe: U, publisher: &Publisher) -> Self
+    where
+        S: Into<String>,
+        U: Into<String>,
+    {
+        self.methods.insert(subscribe.into(), Method::Subscribe(publisher.clone()));
+        self.methods.insert(unsubscribe.into(), Method::Unsubscribe(publisher.clone()));
+        self
+    }
+
+    /// Returns whether a handler is registered for the given method.
+    pub fn has_method(&self, name: &str) -> bool {
+        self.methods.contains_key(name)
+    }
+
+    /// Handles the body of a request or of a batch of requests, and returns the body of the
+    /// reply.
+    ///
+    /// Returns `None` if nothing should be sent back, i.e. if the body only consisted of
+    /// notifications.
+    pub fn handle(&self, body: &[u8]) -> Option<String> {
+        self.handle_in(body, None)
+    }
+
+    /// Handles a body received over the persistent connection of `session`, see
+    /// [`Router::handle`].
+    #[cfg(any(feature = "simple_tcp_server", all(feature = "simple_uds_server", not(windows))))]
+    pub(crate) fn handle_session(&self, body: &[u8], session: &Arc<Session>) -> Option<String> {
+        self.handle_in(body, Some(session))
+    }
+
+    fn handle_in(&self, body: &[u8], session: Option<&Arc<Session>>) -> Option<String> {
+        let body = match serde_json::from_slice::<&RawValue>(body) {
+            Ok(body) => body,
+            Err(_) => return Some(error_reply(StandardError::ParseError)),
+        };
+        if body.get().starts_with('[') {
+            let requests = match serde_json::from_str::<Vec<&RawValue>>(body.get()) {
+                Ok(requests) => requests,
+                Err(_) => return Some(error_reply(StandardError::ParseError)),
+            };
+            if requests.is_empty() {
+                return Some(error_reply(StandardError::InvalidRequest));
+            }
+            let responses: Vec<_> =
+                requests.iter().filter_map(|r| self.handle_one(r, session)).collect();
+            if responses.is_empty() {
+                None
+            } else {
+                let responses: Vec<_> = responses.iter().map(ResponseBody).collect();
+                Some(serde_json::to_string(&responses).expect("responses are serializable"))
+            }
+        } else {
+            let response = self.handle_one(body, session)?;
+            Some(
+                serde_json::to_string(&ResponseBody(&response))
+                    .expect("responses are serializable"),
+            )
+        }
+    }
+
+    /// Handles a single request, returning its response unless it was a notification.
+    fn handle_one(&self, request: &RawValue, session: Option<&Arc<Session>>) -> Option<Response> {
+        let request = match serde_json::from_str::<IncomingRequest>(request.get()) {
+            Ok(request) if request.is_valid() => request,
+            _ => return Some(error_response(StandardError::InvalidRequest)),
+        };
+        // JSON-RPC 1.0 notifications have a `null` ID.
+        let version = request.version();
+        let id = match request.id {
+            Some(serde_json::Value::Null) if version == ProtocolVersion::V1 => None,
+            id => id,
+        };
+        let result = match self.methods.get(&*request.method) {
+            Some(Method::Handler(handler)) => handler.handle(request.params),
+            // Nobody would know the ID of a subscription made by a notification.
+            Some(Method::Subscribe(_)) if id.is_none() => return None,
+            Some(Method::Subscribe(publisher)) => match session {
+                Some(session) => Ok(publisher.subscribe(session).into()),
+                None => {
+                    let data = "subscriptions require a persistent connection";
+                    let data = serde_json::value::to_raw_value(data).ok();
+                    Err(standard_error(StandardError::MethodNotFound, data))
+                }
+            },
+            Some(Method::Unsubscribe(publisher)) => {
+                parse_params(request.params).map(|(id,): (u64,)| {
+                    session.map_or(false, |s| publisher.unsubscribe(id, s)).into()
+                })
+            }
+            None => Err(standard_error(StandardError::MethodNotFound, None)),
+        };
+        id.map(|id| {
+            let mut response = result_to_response(result, id);
+            if version == ProtocolVersion::V1 {
+                response.jsonrpc = None;
+            }
+            response
+        })
+    }
+}
+
+impl fmt::Debug for Router {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut methods: Vec<_> = self.methods.keys().collect();
+        methods.sort();
+        f.debug_struct("Router").field("methods", &methods).finish()
+    }
+}
+
+/// The IDs of subscriptions, unique among all publishers.
+static NEXT_SUBSCRIPTION_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Pushes notifications to the clients subscribed to them, see [`Router::subscription`].
+///
+/// Notifications are requests without an ID, of the method the publisher was created with,
+/// whose parameters are the ID of the subscription and the published value, by name:
+/// `{"subscription": 1, "result": ...}`.
+///
+/// Clones of a publisher share the same subscribers.
+#[derive(Clone)]
+pub struct Publisher {
+    inner: Arc<PublisherInner>,
+}
+
+struct PublisherInner {
+    method: String,
+    subscribers: Mutex<HashMap<u64, Weak<Session>>>,
+}
+
+impl Publisher {
+    /// Creates a publisher sending its notifications as requests of the given method.
+    pub fn new<S: Into<String>>(method: S) -> Publisher {
+        let inner = PublisherInner {
+            method: method.into(),
+            subscribers: Mutex::new(HashMap::new()),
+        };
+        Publisher {
+            inner: Arc::new(inner),
+        }
+    }
+
+    /// Returns the method of the notifications.
+    pub fn method(&self) -> &str {
+        &self.inner.method
+    }
+
+    /// Returns the number of subscriptions whose connection is still open.
+    pub fn subscribers(&self) -> usize {
+        self.registry().values().filter(|s| s.strong_count() > 0).count()
+    }
+
+    /// Sends `result` to all subscribers, and returns the number of those it was sent to.
+    ///
+    /// Notifications are written to each connection in turn, a slow client holding back the
+    /// others until it times out. Subscriptions whose connection fails are closed.
+    pub fn publish<T: serde::Serialize>(&self, result: &T) -> Result<usize, serde_json::Error> {
+        let result = serde_json::value::to_raw_value(result)?;
+        let subscribers: Vec<_> = {
+            let mut subscribers = self.registry();
+            subscribers.retain(|_, s| s.strong_count() > 0);
+            subscribers.iter().filter_map(|(&id, s)| Some((id, s.upgrade()?))).collect()
+        };
+
+        let mut sent = 0;
+        for (id, session) in subscribers {
+            let params = [("subscription", crate::arg(id)), ("result", result.clone())];
+            let request = ProtocolVersion::V2.request(self.method(), Params::ByName(&params), None);
+            match session.notify(&serde_json::to_string(&request)?) {
+                Ok(()) => sent += 1,
+                Err(_) => {
+                    self.unsubscribe(id, &session);
+                    session.close();
+                }
+            }
+        }
+        Ok(sent)
+    }
+
+    fn registry(&self) -> MutexGuard<'_, HashMap<u64, Weak<Session>>> {
+        self.inner.subscribers.lock().expect("poisoned mutex")
+    }
+
+    /// Subscribes the client of `session`, and returns the ID of the subscription.
+    fn subscribe(&self, session: &Arc<Session>) -> u64 {
+        let id = NEXT_SUBSCRIPTION_ID.fetch_add(1, Ordering::Relaxed);
+        self.registry().insert(id, Arc::downgrade(session));
+        session.subscriptions.fetch_add(1, Ordering::AcqRel);
+        id
+    }
+
+    /// Closes a subscription of the client of `session`, returning whether there was one.
+    fn unsubscribe(&self, id: u64, session: &Arc<Session>) -> bool {
+        let mut subscribers = self.registry();
+        match subscribers.get(&id) {
+            Some(s) if Weak::ptr_eq(s, &Arc::downgrade(session)) => {
+                subscribers.remove(&id);
+                session.subscriptions.fetch_sub(1, Ordering::AcqRel);
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Debug for Publisher {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Publisher")
+            .field("method", &self.method())
+            .field("subscribers", &self.subscribers())
+            .finish()
+    }
+}
+
+/// A persistent connection, over which notifications are pushed to the client.
+pub(crate) struct Session {
+    writer: Mutex<Box<dyn Write + Send>>,
+    close: Box<dyn Fn() + Send + Sync>,
+    /// Notifications held back while a request is handled, so that they follow its reply, or
+    /// `None` if they can be sent right away.
+    held: Mutex<Option<Vec<String>>>,
+    /// The number of open subscriptions.
+    subscriptions: AtomicUsize,
+}
+
+impl Session {
+    /// Creates the session of a connection written to with `writer`, and closed with `close`.
+    #[cfg(any(feature = "simple_tcp_server", all(feature = "simple_uds_server", not(windows))))]
+    pub(crate) fn new<W, F>(writer: W, close: F) -> Session
+    where
+        W: Write + Send + 'static,
+        F: Fn() + Send + Sync + 'static,
+    {
+        Session {
+            writer: Mutex::new(Box::new(writer)),
+            close: Box::new(close),
+            held: Mutex::new(None),
+            subscriptions: AtomicUsize::new(0),
+        }
+    }
+
+    fn writer(&self) -> MutexGuard<'_, Box<dyn Write + Send>> {
+        self.writer.lock().expect("poisoned mutex")
+    }
+
+    fn held(&self) -> MutexGuard<'_, Option<Vec<String>>> {
+        self.held.lock().expect("poisoned mutex")
+    }
+
+    /// Writes the reply to a request.
+    #[cfg(any(feature = "simple_tcp_server", all(feature = "simple_uds_server", not(windows))))]
+    pub(crate) fn reply(&self, reply: &str) -> io::Result<()> {
+        self.writer().write_all(reply.as_bytes())
+    }
+
+    /// Holds back notifications until [`Session::release`] is called.
+    #[cfg(any(feature = "simple_tcp_server", all(feature = "simple_uds_server", not(windows))))]
+    pub(crate) fn hold(&self) {
+        *self.held() = Some(vec![]);
+    }
+
+    /// Sends the notifications held back since [`Session::hold`] was called, and the next ones
+    /// right away.
+    #[cfg(any(feature = "simple_tcp_server", all(feature = "simple_uds_server", not(windows))))]
+    pub(crate) fn release(&self) -> io::Result<()> {
+        let mut held = self.held();
+        let mut writer = self.writer();
+        for notification in held.take().unwrap_or_default() {
+            writer.write_all(notification.as_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// Returns whether the client has open subscriptions.
+    #[cfg(any(feature = "simple_tcp_server", all(feature = "simple_uds_server", not(windows))))]
+    pub(crate) fn is_subscribed(&self) -> bool {
+        self.subscriptions.load(Ordering::Acquire) > 0
+    }
+
+    fn notify(&self, notification: &str) -> io::Result<()> {
+        // The lock is kept while writing, lest the notification overtakes held back ones.
+        let mut held = self.held();
+        match *held {
+            Some(ref mut held) => {
+                held.push(notification.to_owned());
+                Ok(())
+            }
+            None => self.writer().write_all(notification.as_bytes()),
+        }
+    }
+
+    /// Closes the connection, e.g. after a notification was partially written to it.
+    fn close(&self) {
+        (self.close)();
+    }
+}
+
+/// Returns the response to a request whose ID couldn't be determined.
+fn error_response(code: StandardError) -> Response {
+    result_to_response(Err(standard_error(code, None)), serde_json::Value::Null)
+}
+
+/// Returns the body of a reply to a request whose ID couldn't be determined.
+pub(crate) fn error_reply(code: StandardError) -> String {
+    let response = error_response(code);
+    serde_json::to_string(&ResponseBody(&response)).expect("responses are serializable")
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+
+    use super::*;
+
+    fn router() -> Router {
+        Router::new()
+            .method("add", |params: Option<&RawValue>| {
+                let (a, b): (i64, i64) = parse_params(params)?;
+                Ok(json!(a + b))
+            })
+            .method("fail", |_: Option<&RawValue>| {
+                Err(RpcError {
+                    code: -1,
+                    message: "failed".to_owned(),
+                    data: None,
+                })
+            })
+    }
+
+    fn handle(router: &Router, body: &str) -> Option<Value> {
+        router.handle(body.as_bytes()).map(|reply| serde_json::from_str(&reply).unwrap())
+    }
+
+    /// Returns the error code of a response.
+    fn code(response: &Value) -> &Value {
+        &response["error"]["code"]
+    }
+
+    #[test]
+    fn single_requests() {
+        let router = router();
+        assert!(router.has_method("add"));
+        assert!(!router.has_method("sub"));
+
+        let reply = handle(&router, r#"{"jsonrpc":"2.0","method":"add","params":[1,2],"id":"a"}"#);
+        assert_eq!(reply.unwrap(), json!({"jsonrpc": "2.0", "result": 3, "id": "a"}));
+        let reply = handle(&router, r#"{"jsonrpc":"2.0","method":"add","params":[1],"id":1}"#);
+        assert_eq!(code(&reply.unwrap()), -32602);
+        let reply = handle(&router, r#"{"jsonrpc":"2.0","method":"fail","id":null}"#).unwrap();
+        assert_eq!((code(&reply), &reply["id"]), (&json!(-1), &Value::Null));
+        let reply = handle(&router, r#"{"jsonrpc":"2.0","method":"sub","id":1}"#).unwrap();
+        assert_eq!((code(&reply), &reply["id"]), (&json!(-32601), &json!(1)));
+
+        // Notifications aren't replied to, even when they fail.
+        assert_eq!(handle(&router, r#"{"jsonrpc":"2.0","method":"add","params":[1,2]}"#), None);
+        assert_eq!(handle(&router, r#"{"jsonrpc":"2.0","method":"sub"}"#), None);
+    }
+
+    #[test]
+    fn protocol_v1() {
+        let router = router();
+        for body in &[
+            r#"{"method":"add","params":[1,2],"id":1}"#,
+            r#"{"jsonrpc":"1.0","method":"add","params":[1,2],"id":1}"#,
+        ] {
+            let reply = router.handle(body.as_bytes()).unwrap();
+            assert_eq!(reply, r#"{"result":3,"error":null,"id":1}"#);
+        }
+        let reply = handle(&router, r#"{"method":"fail","params":[],"id":1}"#).unwrap();
+        assert_eq!((code(&reply), &reply["result"]), (&json!(-1), &Value::Null));
+
+        // Notifications have a null ID.
+        assert_eq!(handle(&router, r#"{"method":"add","params":[1,2],"id":null}"#), None);
+    }
+
+    #[test]
+    fn invalid_requests() {
+        let router = router();
+        for &(body, expected) in &[
+            (r#"{"jsonrpc":"2.0","method":"add","#, -32700),
+            (r#"[{"jsonrpc":"2.0","method":"add"}"#, -32700),
+            ("\u{ff}", -32700),
+            ("[]", -32600),
+            ("1", -32600),
+            (r#"{"jsonrpc":"2.0","method":1,"id":1}"#, -32600),
+            (r#"{"jsonrpc":"1.1","method":"add","id":1}"#, -32600),
+            (r#"{"jsonrpc":"2.0","method":"add","params":1,"id":1}"#, -32600),
+            (r#"{"jsonrpc":"2.0","method":"add","params":null,"id":1}"#, -32600),
+            (r#"{"jsonrpc":"2.0","method":"add","id":[1]}"#, -32600),
+        ] {
+            let reply = handle(&router, body).unwrap();
+            assert_eq!((code(&reply), &reply["id"]), (&json!(expected), &Value::Null), "{}", body);
+        }
+    }
+
+    #[test]
+    fn batches() {
+        let router = router();
+        let body = r#"[
+            {"jsonrpc":"2.0","method":"add","params":[1,2],"id":1},
+            {"jsonrpc":"2.0","method":"add","params":[3,4]},
+            1,
+            {"jsonrpc":"2.0","method":"sub","id":2}
+        ]"#;
+        let reply = handle(&router, body).unwrap();
+        let replies = reply.as_array().unwrap();
+        assert_eq!(replies.len(), 3);
+        assert_eq!(replies[0], json!({"jsonrpc": "2.0", "result": 3, "id": 1}));
+        assert_eq!((code(&replies[1]), &replies[1]["id"]), (&json!(-32600), &Value::Null));
+        assert_eq!((code(&replies[2]), &replies[2]["id"]), (&json!(-32601), &json!(2)));
+
+        let body =
+            r#"[{"jsonrpc":"2.0","method":"add","params":[1,2]},{"jsonrpc":"2.0","method":"x"}]"#;
+        assert_eq!(handle(&router, body), None);
+    }
+
+    #[test]
+    fn typed_methods() {
+        let router = Router::new()
+            .typed_method("add", |a: i64, b: Option<i64>| -> Result<i64, RpcError> {
+                Ok(a + b.unwrap_or(0))
+            })
+            .named_method("block", &["hash", "verbosity"], |hash: String, verbosity: u8| {
+                Ok::<_, RpcError>(json!({"hash": hash, "verbosity": verbosity}))
+            });
+
+        let reply = handle(&router, r#"{"jsonrpc":"2.0","method":"add","params":[1,2],"id":1}"#);
+        assert_eq!(reply.unwrap()["result"], 3);
+        let reply = handle(&router, r#"{"jsonrpc":"2.0","method":"add","params":[1],"id":1}"#);
+        assert_eq!(reply.unwrap()["result"], 1);
+        let body =
+            r#"{"jsonrpc":"2.0","method":"block","params":{"verbosity":2,"hash":"h"},"id":1}"#;
+        let reply = handle(&router, body).unwrap();
+        assert_eq!(reply["result"], json!({"hash": "h", "verbosity": 2}));
+        let body = r#"{"jsonrpc":"2.0","method":"block","params":["h",0],"id":1}"#;
+        let reply = handle(&router, body).unwrap();
+        assert_eq!(reply["result"], json!({"hash": "h", "verbosity": 0}));
+
+        for &(method, params, ref data) in &[
+            ("add", r#"["1"]"#, json!({"index": 0})),
+            ("add", "[]", json!({"index": 0, "error": "missing parameter"})),
+            ("add", "[1,2,3]", json!({"error": "expected at most 2 parameters, got 3"})),
+            ("add", r#"{"a":1}"#, json!({"error": "parameters can only be passed by position"})),
+            ("block", r#"{"hash":"h","verbosity":-1}"#, json!({"index": 1, "name": "verbosity"})),
+            ("block", r#"{"verbosity":1}"#, json!({"index": 0, "name": "hash"})),
+            ("block", r#"{"hash":"h","id":1}"#, json!({"error": "unknown parameter `id`"})),
+        ] {
+            let body =
+                format!(r#"{{"jsonrpc":"2.0","method":"{}","params":{},"id":1}}"#, method, params);
+            let reply = handle(&router, &body).unwrap();
+            assert_eq!(code(&reply), -32602, "{}", body);
+            // The data describes the failure, the message of deserialization errors aside.
+            for (key, value) in data.as_object().unwrap() {
+                assert_eq!(&reply["error"]["data"][key], value, "{}", body);
+            }
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn named_method_arity() {
+        let _ =
+            Router::new().named_method("add", &["a"], |a: i64, b: i64| -> Result<i64, RpcError> {
+                Ok(a + b)
+            });
+    }
+
+    #[test]
+    fn subscriptions_need_a_connection() {
+        let publisher = Publisher::new("block");
+        let router = Router::new().subscription("subscribe", "unsubscribe", &publisher);
+        assert!(router.has_method("subscribe") && router.has_method("unsubscribe"));
+
+        let reply = handle(&router, r#"{"jsonrpc":"2.0","method":"subscribe","id":1}"#).unwrap();
+        assert_eq!(code(&reply), -32601);
+        let body = r#"{"jsonrpc":"2.0","method":"unsubscribe","params":[1],"id":1}"#;
+        assert_eq!(handle(&router, body).unwrap()["result"], false);
+        let body = r#"{"jsonrpc":"2.0","method":"unsubscribe","params":["a"],"id":1}"#;
+        assert_eq!(code(&handle(&router, body).unwrap()), -32602);
+        assert_eq!(publisher.publish(&1).unwrap(), 0);
+    }
+}
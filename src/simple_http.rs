@@ -10,6 +10,8 @@ use std::io::{BufRead, BufReader, BufWriter, Read, Write};
 use std::net::TcpStream;
 use std::net::{SocketAddr, ToSocketAddrs};
 use std::sync::{Arc, Mutex, MutexGuard};
+#[cfg(feature = "log")]
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::time::Duration;
 use std::{error, fmt, io, net, num};
 
@@ -17,7 +19,14 @@ use base64;
 use serde;
 use serde_json;
 
-use crate::client::Transport;
+#[cfg(feature = "async")]
+use std::sync::{PoisonError, TryLockError};
+
+#[cfg(feature = "async")]
+use crate::async_client::{AsyncTransport, BoxFuture};
+#[cfg(feature = "async")]
+use crate::blocking::spawn_blocking;
+use crate::client::{ReadBody, Transport};
 use crate::{Request, Response};
 
 #[cfg(fuzzing)]
@@ -67,6 +76,10 @@ pub const DEFAULT_PROXY_PORT: u16 = 9050;
 /// Absolute maximum content length we will allow before cutting off the response
 const FINAL_RESP_ALLOC: u64 = 1024 * 1024 * 1024;
 
+/// The most spare connections kept open for concurrent asynchronous requests.
+#[cfg(feature = "async")]
+const MAX_SPARE_SOCKETS: usize = 8;
+
 /// Simple HTTP transport that implements the necessary subset of HTTP for
 /// running a bitcoind RPC client.
 #[derive(Clone, Debug)]
@@ -81,6 +94,12 @@ pub struct SimpleHttpTransport {
     #[cfg(feature = "proxy")]
     proxy_auth: Option<(String, String)>,
     sock: Arc<Mutex<Option<BufReader<TcpStream>>>>,
+    /// Connections used by asynchronous requests while `sock` is busy.
+    #[cfg(feature = "async")]
+    spare_socks: Arc<Mutex<Vec<BufReader<TcpStream>>>>,
+    /// Whether a connection was opened before, so that opening another one is a reconnection.
+    #[cfg(feature = "log")]
+    connected: Arc<AtomicBool>,
 }
 
 impl Default for SimpleHttpTransport {
@@ -104,6 +123,10 @@ impl Default for SimpleHttpTransport {
             #[cfg(feature = "proxy")]
             proxy_auth: None,
             sock: Arc::new(Mutex::new(None)),
+            #[cfg(feature = "async")]
+            spare_socks: Arc::new(Mutex::new(vec![])),
+            #[cfg(feature = "log")]
+            connected: Arc::new(AtomicBool::new(false)),
         }
     }
 }
@@ -123,54 +146,115 @@ impl SimpleHttpTransport {
     where
         R: for<'a> serde::de::Deserialize<'a>,
     {
-        match self.try_request(req) {
-            Ok(response) => Ok(response),
-            Err(err) => {
-                // No part of this codebase should panic, so unwrapping a mutex lock is fine
-                *self.sock.lock().expect("poisoned mutex") = None;
+        // No part of this codebase should panic, so unwrapping a mutex lock is fine
+        let mut sock_lock: MutexGuard<Option<_>> = self.sock.lock().expect("poisoned mutex");
+        self.request_on(&mut sock_lock, req)
+    }
+
+    /// Makes a request, handing the body of the response to `read_body` as it is received.
+    fn request_streaming(
+        &self,
+        req: impl serde::Serialize,
+        read_body: &mut ReadBody,
+    ) -> Result<(), crate::Error> {
+        let mut sock_lock: MutexGuard<Option<_>> = self.sock.lock().expect("poisoned mutex");
+        match self.try_request_streaming(&mut sock_lock, req, read_body) {
+            Ok(()) => Ok(()),
+            // Other errors leave the connection usable, see `try_request_streaming`.
+            Err(err @ crate::Error::Transport(_)) | Err(err @ crate::Error::Json(_)) => {
+                *sock_lock = None;
                 Err(err)
             }
+            Err(err) => Err(err),
         }
     }
 
-    fn try_request<R>(
+    fn notify(&self, req: impl serde::Serialize) -> Result<(), Error> {
+        let mut sock_lock: MutexGuard<Option<_>> = self.sock.lock().expect("poisoned mutex");
+        self.notify_on(&mut sock_lock, req)
+    }
+
+    /// Makes a request over the socket in `sock_slot`, dropping the socket on failure.
+    fn request_on<R>(
         &self,
+        sock_slot: &mut Option<BufReader<TcpStream>>,
         req: impl serde::Serialize,
     ) -> Result<R, Error>
     where
         R: for<'a> serde::de::Deserialize<'a>,
     {
-        // No part of this codebase should panic, so unwrapping a mutex lock is fine
-        let mut sock_lock: MutexGuard<Option<_>> = self.sock.lock().expect("poisoned mutex");
-        if sock_lock.is_none() {
-            *sock_lock = Some(BufReader::new({
-                #[cfg(feature = "proxy")]
-                {
-                    if let Some((username, password)) = &self.proxy_auth {
-                        Socks5Stream::connect_with_password(
-                            self.proxy_addr,
-                            self.addr,
-                            username.as_str(),
-                            password.as_str(),
-                        )?
-                        .into_inner()
-                    } else {
-                        Socks5Stream::connect(self.proxy_addr, self.addr)?.into_inner()
-                    }
-                }
+        match self.try_request(sock_slot, req) {
+            Ok(response) => Ok(response),
+            Err(err) => {
+                *sock_slot = None;
+                Err(err)
+            }
+        }
+    }
 
-                #[cfg(not(feature = "proxy"))]
-                {
-                    let stream = TcpStream::connect_timeout(&self.addr, self.timeout)?;
-                    stream.set_read_timeout(Some(self.timeout))?;
-                    stream.set_write_timeout(Some(self.timeout))?;
-                    stream
-                }
-            }));
+    /// Sends a notification over the socket in `sock_slot`, dropping the socket on failure.
+    fn notify_on(
+        &self,
+        sock_slot: &mut Option<BufReader<TcpStream>>,
+        req: impl serde::Serialize,
+    ) -> Result<(), Error> {
+        match self.try_notify(sock_slot, req) {
+            Ok(()) => Ok(()),
+            Err(err) => {
+                *sock_slot = None;
+                Err(err)
+            }
+        }
+    }
+
+    /// Opens a new connection to the server, possibly through the SOCKS5 proxy.
+    fn connect(&self) -> Result<TcpStream, Error> {
+        #[cfg(feature = "proxy")]
+        {
+            if let Some((username, password)) = &self.proxy_auth {
+                Ok(Socks5Stream::connect_with_password(
+                    self.proxy_addr,
+                    self.addr,
+                    username.as_str(),
+                    password.as_str(),
+                )?
+                .into_inner())
+            } else {
+                Ok(Socks5Stream::connect(self.proxy_addr, self.addr)?.into_inner())
+            }
+        }
+
+        #[cfg(not(feature = "proxy"))]
+        {
+            let stream = TcpStream::connect_timeout(&self.addr, self.timeout)?;
+            stream.set_read_timeout(Some(self.timeout))?;
+            stream.set_write_timeout(Some(self.timeout))?;
+            Ok(stream)
+        }
+    }
+
+    /// Writes the HTTP request for `req`, connecting first if there is no open socket, and
+    /// returns the socket to read the response from.
+    fn send_http_request<'s>(
+        &self,
+        sock_slot: &'s mut Option<BufReader<TcpStream>>,
+        req: impl serde::Serialize,
+    ) -> Result<&'s mut BufReader<TcpStream>, Error> {
+        if sock_slot.is_none() {
+            trace_event!(
+                "{} to {}",
+                if self.connected.swap(true, Ordering::Relaxed) {
+                    "reconnect"
+                } else {
+                    "connect"
+                },
+                self.addr
+            );
+            *sock_slot = Some(BufReader::new(self.connect()?));
         };
         // In the immediately preceding block, we made sure that `sock` is non-`None`,
         // so unwrapping here is fine.
-        let sock: &mut BufReader<_> = sock_lock.as_mut().unwrap();
+        let sock: &mut BufReader<_> = sock_slot.as_mut().unwrap();
 
         // Serialize the body first so we can set the Content-Length header.
         let body = serde_json::to_vec(&req)?;
@@ -196,50 +280,21 @@ impl SimpleHttpTransport {
             sock.write_all(&body)?;
             sock.flush()?;
         }
+        trace_event!("wrote request of {} bytes", body.len());
 
-        // Parse first HTTP response header line
-        let mut header_buf = String::new();
-        sock.read_line(&mut header_buf)?;
-        if header_buf.len() < 12 {
-            return Err(Error::HttpResponseTooShort { actual: header_buf.len(), needed: 12 });
-        }
-        if !header_buf.as_bytes()[..12].is_ascii() {
-            return Err(Error::HttpResponseNonAsciiHello(header_buf.as_bytes()[..12].to_vec()));
-        }
-        if !header_buf.starts_with("HTTP/1.1 ") {
-            return Err(Error::HttpResponseBadHello {
-                actual: header_buf[0..9].into(),
-                expected: "HTTP/1.1 ".into(),
-            });
-        }
-        let response_code = match header_buf[9..12].parse::<u16>() {
-            Ok(n) => n,
-            Err(e) => return Err(Error::HttpResponseBadStatus(
-                header_buf[9..12].into(),
-                e,
-            )),
-        };
+        Ok(sock)
+    }
 
-        // Parse response header fields
-        let mut content_length = None;
-        loop {
-            header_buf.clear();
-            sock.read_line(&mut header_buf)?;
-            if header_buf == "\r\n" {
-                break;
-            }
-            header_buf.make_ascii_lowercase();
-
-            const CONTENT_LENGTH: &str = "content-length: ";
-            if header_buf.starts_with(CONTENT_LENGTH) {
-                content_length = Some(
-                    header_buf[CONTENT_LENGTH.len()..]
-                        .trim()
-                        .parse::<u64>()
-                        .map_err(|e| Error::HttpResponseBadContentLength(header_buf[CONTENT_LENGTH.len()..].into(), e))?
-                );
-            }
-        }
+    fn try_request<R>(
+        &self,
+        sock_slot: &mut Option<BufReader<TcpStream>>,
+        req: impl serde::Serialize,
+    ) -> Result<R, Error>
+    where
+        R: for<'a> serde::de::Deserialize<'a>,
+    {
+        let sock = self.send_http_request(sock_slot, req)?;
+        let (response_code, content_length) = read_response_head(sock)?;
 
         if response_code == 401 {
             // There is no body in a 401 response, so don't try to read it
@@ -263,7 +318,9 @@ impl SimpleHttpTransport {
         // Attempt to parse the response. Don't check the HTTP error code until
         // after parsing, since Bitcoin Core will often return a descriptive JSON
         // error structure which is more useful than the error code.
-        match serde_json::from_reader(&mut reader) {
+        let result = serde_json::from_reader(&mut reader);
+        trace_event!("{}", crate::trace::BodyParse(&result));
+        match result {
             Ok(s) => {
                 if content_length.is_some() {
                     reader.bytes().count(); // consume any trailing bytes
@@ -281,6 +338,135 @@ impl SimpleHttpTransport {
             }
         }
     }
+
+    fn try_request_streaming(
+        &self,
+        sock_slot: &mut Option<BufReader<TcpStream>>,
+        req: impl serde::Serialize,
+        read_body: &mut ReadBody,
+    ) -> Result<(), crate::Error> {
+        let sock = self.send_http_request(sock_slot, req)?;
+        let (response_code, content_length) = read_response_head(sock)?;
+
+        if response_code == 401 {
+            // There is no body in a 401 response, so don't try to read it
+            return Err(Error::HttpErrorCode(response_code).into());
+        }
+
+        let mut reader = match content_length {
+            None => sock.take(FINAL_RESP_ALLOC),
+            Some(n) if n > FINAL_RESP_ALLOC => {
+                return Err(Error::HttpResponseContentLengthTooLarge {
+                    length: n,
+                    max: FINAL_RESP_ALLOC,
+                }.into());
+            },
+            Some(n) => sock.take(n),
+        };
+
+        // As in `try_request`, a JSON error on a non-200 response is blamed on the status.
+        let result = read_body(&mut reader);
+        trace_event!("{}", crate::trace::BodyParse(&result));
+        match result {
+            Err(crate::Error::Json(_)) if response_code != 200 => {
+                Err(Error::HttpErrorCode(response_code).into())
+            }
+            Err(e @ crate::Error::Transport(_)) | Err(e @ crate::Error::Json(_)) => Err(e),
+            // Either the body was read, or the response it holds failed the checks of the client,
+            // e.g. on its RPC error: the connection can be reused in both cases.
+            result => {
+                if content_length.is_some() {
+                    reader.bytes().count(); // consume any trailing bytes
+                }
+                result
+            }
+        }
+    }
+
+    fn try_notify(
+        &self,
+        sock_slot: &mut Option<BufReader<TcpStream>>,
+        req: impl serde::Serialize,
+    ) -> Result<(), Error> {
+        let sock = self.send_http_request(sock_slot, req)?;
+        let (response_code, content_length) = read_response_head(sock)?;
+
+        if response_code == 401 {
+            // There is no body in a 401 response, so don't try to read it
+            return Err(Error::HttpErrorCode(response_code));
+        }
+
+        // The server has nothing to tell us about a notification, so any body is discarded.
+        match content_length {
+            Some(n) => {
+                io::copy(&mut sock.take(n), &mut io::sink())?;
+            }
+            None if response_code == 204 => {}
+            None => {
+                // Without a content-length header the body only ends when the server closes
+                // the connection, so the socket can't be reused afterwards.
+                io::copy(&mut sock.take(FINAL_RESP_ALLOC), &mut io::sink())?;
+                *sock_slot = None;
+            }
+        }
+
+        if response_code / 100 != 2 {
+            return Err(Error::HttpErrorCode(response_code));
+        }
+        Ok(())
+    }
+}
+
+/// Reads the status line and header fields of an HTTP response, returning the status code and
+/// the value of the content-length header, if any.
+fn read_response_head<R: BufRead>(sock: &mut R) -> Result<(u16, Option<u64>), Error> {
+    // Parse first HTTP response header line
+    let mut header_buf = String::new();
+    sock.read_line(&mut header_buf)?;
+    if header_buf.len() < 12 {
+        return Err(Error::HttpResponseTooShort { actual: header_buf.len(), needed: 12 });
+    }
+    if !header_buf.as_bytes()[..12].is_ascii() {
+        return Err(Error::HttpResponseNonAsciiHello(header_buf.as_bytes()[..12].to_vec()));
+    }
+    if !header_buf.starts_with("HTTP/1.1 ") {
+        return Err(Error::HttpResponseBadHello {
+            actual: header_buf[0..9].into(),
+            expected: "HTTP/1.1 ".into(),
+        });
+    }
+    let response_code = match header_buf[9..12].parse::<u16>() {
+        Ok(n) => n,
+        Err(e) => return Err(Error::HttpResponseBadStatus(
+            header_buf[9..12].into(),
+            e,
+        )),
+    };
+    trace_event!("status {}", response_code);
+
+    // Parse response header fields
+    let mut content_length = None;
+    loop {
+        header_buf.clear();
+        sock.read_line(&mut header_buf)?;
+        if header_buf == "\r\n" {
+            break;
+        }
+        trace_event!("{}", crate::trace::Header(&header_buf));
+        header_buf.make_ascii_lowercase();
+
+        const CONTENT_LENGTH: &str = "content-length: ";
+        if header_buf.starts_with(CONTENT_LENGTH) {
+            content_length = Some(
+                header_buf[CONTENT_LENGTH.len()..]
+                    .trim()
+                    .parse::<u64>()
+                    .map_err(|e| Error::HttpResponseBadContentLength(header_buf[CONTENT_LENGTH.len()..].into(), e))?
+            );
+        }
+    }
+
+    Ok((response_code, content_length))
 }
 
 /// Error that can happen when sending requests.
@@ -485,18 +671,134 @@ fn check_url(url: &str) -> Result<(SocketAddr, String), Error> {
 
 impl Transport for SimpleHttpTransport {
     fn send_request(&self, req: Request) -> Result<Response, crate::Error> {
+        trace_span!(std::slice::from_ref(&req));
         Ok(self.request(req)?)
     }
 
+    fn send_request_streaming(
+        &self,
+        req: Request,
+        read_body: &mut ReadBody,
+    ) -> Result<(), crate::Error> {
+        trace_span!(std::slice::from_ref(&req));
+        self.request_streaming(req, read_body)
+    }
+
     fn send_batch(&self, reqs: &[Request]) -> Result<Vec<Response>, crate::Error> {
+        trace_span!(reqs);
+        if reqs.iter().all(Request::is_notification) {
+            self.notify(reqs)?;
+            return Ok(vec![]);
+        }
         Ok(self.request(reqs)?)
     }
 
+    fn send_notification(&self, req: Request) -> Result<(), crate::Error> {
+        trace_span!(std::slice::from_ref(&req));
+        Ok(self.notify(req)?)
+    }
+
     fn fmt_target(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "http://{}:{}{}", self.addr.ip(), self.addr.port(), self.path)
     }
 }
 
+#[cfg(feature = "async")]
+impl SimpleHttpTransport {
+    /// Runs `f` with the shared socket if it is free, or else with a spare connection, so that
+    /// concurrent asynchronous requests don't have to wait for each other.
+    ///
+    /// Up to [`MAX_SPARE_SOCKETS`] spare connections are kept open for later requests.
+    fn with_free_socket<T>(&self, f: impl FnOnce(&mut Option<BufReader<TcpStream>>) -> T) -> T {
+        match self.sock.try_lock() {
+            Ok(mut sock_lock) => f(&mut sock_lock),
+            // A request panicked midway, leaving the connection in an unknown state.
+            Err(TryLockError::Poisoned(poisoned)) => {
+                let mut sock_lock = poisoned.into_inner();
+                *sock_lock = None;
+                f(&mut sock_lock)
+            }
+            Err(TryLockError::WouldBlock) => {
+                let mut sock = self.spare_socks().pop();
+                let output = f(&mut sock);
+                if let Some(sock) = sock {
+                    let mut spare_socks = self.spare_socks();
+                    if spare_socks.len() < MAX_SPARE_SOCKETS {
+                        spare_socks.push(sock);
+                    }
+                }
+                output
+            }
+        }
+    }
+
+    /// Locks the spare connections.
+    fn spare_socks(&self) -> MutexGuard<'_, Vec<BufReader<TcpStream>>> {
+        // Nothing panics while holding the lock, the list is consistent even if poisoned.
+        self.spare_socks.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+#[cfg(feature = "async")]
+impl AsyncTransport for SimpleHttpTransport {
+    fn send_request<'a>(
+        &'a self,
+        req: Request<'a>,
+    ) -> BoxFuture<'a, Result<Response, crate::Error>> {
+        let tp = self.clone();
+        let body = serde_json::value::to_raw_value(&req);
+        Box::pin(async move {
+            let body = body?;
+            Ok(spawn_blocking(trace_in_span!(std::slice::from_ref(&req), move || {
+                tp.with_free_socket(|sock| tp.request_on(sock, body))
+            }))
+            .await?)
+        })
+    }
+
+    fn send_batch<'a>(
+        &'a self,
+        reqs: &'a [Request<'a>],
+    ) -> BoxFuture<'a, Result<Vec<Response>, crate::Error>> {
+        let tp = self.clone();
+        let all_notifications = reqs.iter().all(Request::is_notification);
+        let body = serde_json::value::to_raw_value(reqs);
+        Box::pin(async move {
+            let body = body?;
+            if all_notifications {
+                spawn_blocking(trace_in_span!(reqs, move || {
+                    tp.with_free_socket(|sock| tp.notify_on(sock, body))
+                }))
+                .await?;
+                return Ok(vec![]);
+            }
+            Ok(spawn_blocking(trace_in_span!(reqs, move || {
+                tp.with_free_socket(|sock| tp.request_on(sock, body))
+            }))
+            .await?)
+        })
+    }
+
+    fn send_notification<'a>(
+        &'a self,
+        req: Request<'a>,
+    ) -> BoxFuture<'a, Result<(), crate::Error>> {
+        let tp = self.clone();
+        let body = serde_json::value::to_raw_value(&req);
+        Box::pin(async move {
+            let body = body?;
+            Ok(spawn_blocking(trace_in_span!(std::slice::from_ref(&req), move || {
+                tp.with_free_socket(|sock| tp.notify_on(sock, body))
+            }))
+            .await?)
+        })
+    }
+
+    fn fmt_target(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        Transport::fmt_target(self, f)
+    }
+}
+
 /// Builder for simple bitcoind [`SimpleHttpTransport`].
 #[derive(Clone, Debug)]
 pub struct Builder {
@@ -606,6 +908,22 @@ impl crate::Client {
     }
 }
 
+#[cfg(feature = "async")]
+impl crate::AsyncClient {
+    /// Creates a new asynchronous JSON-RPC client using a bare-minimum HTTP transport.
+    pub fn simple_http(
+        url: &str,
+        user: Option<String>,
+        pass: Option<String>,
+    ) -> Result<crate::AsyncClient, Error> {
+        let mut builder = Builder::new().url(url)?;
+        if let Some(user) = user {
+            builder = builder.auth(user, pass);
+        }
+        Ok(crate::AsyncClient::with_transport(builder.build()))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::net;
@@ -689,6 +1007,167 @@ mod tests {
         let _ = Client::simple_http("localhost:22", None, None).unwrap();
     }
 
+    /// Reads a single HTTP request from `sock` and returns its body.
+    #[cfg(not(feature = "proxy"))]
+    fn read_http_request<R: BufRead>(sock: &mut R) -> String {
+        let mut content_length = 0;
+        let mut line = String::new();
+        loop {
+            line.clear();
+            sock.read_line(&mut line).unwrap();
+            if line == "\r\n" {
+                break;
+            }
+            const CONTENT_LENGTH: &str = "Content-Length: ";
+            if line.starts_with(CONTENT_LENGTH) {
+                content_length = line[CONTENT_LENGTH.len()..].trim().parse().unwrap();
+            }
+        }
+        let mut body = vec![0; content_length];
+        sock.read_exact(&mut body).unwrap();
+        String::from_utf8(body).unwrap()
+    }
+
+    #[test]
+    #[cfg(not(feature = "proxy"))]
+    fn notification_then_request() {
+        let server = net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", server.local_addr().unwrap());
+
+        let server_thread = std::thread::spawn(move || {
+            let (stream, _) = server.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut writer = stream;
+
+            let body = read_http_request(&mut reader);
+            assert_eq!(body, r#"{"method":"ping","params":[],"jsonrpc":"2.0"}"#);
+            writer.write_all(b"HTTP/1.1 204 No Content\r\n\r\n").unwrap();
+
+            // The connection is reused for the following request.
+            let body = read_http_request(&mut reader);
+            assert_eq!(body, r#"{"method":"uptime","params":[],"id":1,"jsonrpc":"2.0"}"#);
+            let resp = r#"{"result":42,"error":null,"id":1}"#;
+            write!(writer, "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{}", resp.len(), resp)
+                .unwrap();
+        });
+
+        let client = Client::simple_http(&url, None, None).unwrap();
+        client.notify("ping", &[]).unwrap();
+        assert_eq!(client.call::<u64>("uptime", &[]).unwrap(), 42);
+        server_thread.join().unwrap();
+    }
+
+    #[cfg(all(feature = "log", not(feature = "proxy")))]
+    #[test]
+    fn traced_requests() {
+        let server = net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+
+        let server_thread = std::thread::spawn(move || {
+            for resp in &["not json", r#"{"result":42,"error":null,"id":2}"#] {
+                let (stream, _) = server.accept().unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut writer = stream;
+                read_http_request(&mut reader);
+                write!(
+                    writer,
+                    "HTTP/1.1 200 OK\r\nSet-Cookie: session=s3cr3t\r\nContent-Length: {}\r\n\r\n{}",
+                    resp.len(),
+                    resp
+                )
+                .unwrap();
+            }
+        });
+
+        let tp =
+            Builder::new().url(&addr.to_string()).unwrap().auth("user", Some("hunter2")).build();
+        let client = Client::with_transport(tp);
+        crate::trace::tests::take_records();
+        assert!(client.call::<u64>("uptime", &[]).is_err());
+        assert_eq!(client.call::<u64>("uptime", &[]).unwrap(), 42);
+        server_thread.join().unwrap();
+
+        let records = crate::trace::tests::take_records();
+        let records: Vec<_> =
+            records.iter().map(|r| r.trim_start_matches("jsonrpc::simple_http: ")).collect();
+        assert_eq!(records[0], format!("[uptime#1] connect to {}", addr));
+        assert_eq!(records[1], "[uptime#1] wrote request of 54 bytes");
+        assert_eq!(records[2], "[uptime#1] status 200");
+        assert_eq!(records[3], "[uptime#1] header Set-Cookie: [redacted]");
+        assert_eq!(records[4], "[uptime#1] header Content-Length: 8");
+        assert!(records[5].starts_with("[uptime#1] failed to parse body: "));
+        assert_eq!(records[6], format!("[uptime#2] reconnect to {}", addr));
+        assert_eq!(records[11], "[uptime#2] parsed body");
+        assert!(records.iter().all(|r| !r.contains("s3cr3t") && !r.contains("Authorization")));
+    }
+
+    #[test]
+    #[cfg(not(feature = "proxy"))]
+    fn streaming_requests() {
+        let server = net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", server.local_addr().unwrap());
+
+        let server_thread = std::thread::spawn(move || {
+            let (stream, _) = server.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut writer = stream;
+
+            let responses = [
+                (200, r#"{"result":[1,2,3],"error":null,"id":1}"#),
+                (500, r#"{"result":null,"error":{"code":-5,"message":"not found"},"id":2}"#),
+                (200, r#"{"result":"done","error":null,"id":3}"#),
+            ];
+            for &(status, resp) in &responses {
+                read_http_request(&mut reader);
+                write!(
+                    writer,
+                    "HTTP/1.1 {} X\r\nContent-Length: {}\r\n\r\n{}",
+                    status,
+                    resp.len(),
+                    resp
+                )
+                .unwrap();
+            }
+        });
+
+        // All calls share the same connection.
+        let client = Client::simple_http(&url, None, None).unwrap();
+        let mut elements = vec![];
+        client.for_each("listsomething", &[], |n: u32| elements.push(n)).unwrap();
+        assert_eq!(elements, [1, 2, 3]);
+        match client.call_streaming::<String>("getsomething", &[]) {
+            Err(crate::Error::Rpc(ref e)) if e.code == -5 => {}
+            r => panic!("expected RPC error, got {:?}", r),
+        }
+        assert_eq!(client.call_streaming::<String>("finish", &[]).unwrap(), "done");
+        server_thread.join().unwrap();
+    }
+
+    #[cfg(all(feature = "async", not(feature = "proxy")))]
+    #[test]
+    fn async_request() {
+        use crate::async_client::tests::block_on;
+
+        let server = net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", server.local_addr().unwrap());
+
+        let server_thread = std::thread::spawn(move || {
+            let (stream, _) = server.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut writer = stream;
+
+            let body = read_http_request(&mut reader);
+            assert_eq!(body, r#"{"method":"uptime","params":[],"id":1,"jsonrpc":"2.0"}"#);
+            let resp = r#"{"result":42,"error":null,"id":1}"#;
+            write!(writer, "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{}", resp.len(), resp)
+                .unwrap();
+        });
+
+        let client = crate::AsyncClient::simple_http(&url, None, None).unwrap();
+        assert_eq!(block_on(client.call::<u64>("uptime", &[])).unwrap(), 42);
+        server_thread.join().unwrap();
+    }
+
     #[cfg(feature = "proxy")]
     #[test]
     fn construct_with_proxy() {
@@ -0,0 +1,489 @@
+//! This module implements a minimal, blocking HTTP/1.1 server speaking the same dialect of
+//! JSON-RPC as bitcoind, i.e. the server counterpart of [`crate::simple_http`]. Bodies of `POST`
+//! requests are dispatched with a [`Router`], whatever the path.
+//!
+//! Each connection is served on its own thread, or on one of a pool, and kept open between
+//! requests unless the client asks otherwise. Requests can be required to carry Basic
+//! authentication credentials.
+//!
+//! ```no_run
+//! use jsonrpc::server::{self, Router};
+//! use jsonrpc::simple_http_server::SimpleHttpServer;
+//!
+//! let router = Router::new().method("uptime", |_: Option<&_>| Ok(42.into()));
+//! let server = SimpleHttpServer::builder(router)
+//!     .auth("user", Some("hunter2"))
+//!     .bind("127.0.0.1:8332")
+//!     .unwrap();
+//! let shutdown = server.shutdown_handle();
+//! std::thread::spawn(move || server.run());
+//! // ...
+//! shutdown.shutdown();
+//! ```
+//!
+
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::Arc;
+use std::time::Duration;
+
+use base64;
+
+use crate::serve::{Acceptor, Connection};
+pub use crate::serve::{ShutdownHandle, Workers};
+use crate::server::Router;
+
+/// Default maximum size of the body of a request.
+pub const DEFAULT_MAX_BODY_SIZE: u64 = 32 * 1024 * 1024;
+
+/// Maximum size of the request line and header fields of a request.
+const MAX_HEAD_SIZE: u64 = 16 * 1024;
+
+/// Configuration of a [`SimpleHttpServer`].
+#[derive(Clone, Debug)]
+struct Config {
+    /// The expected `user:pass` credentials.
+    credentials: Option<String>,
+    workers: Workers,
+    timeout: Duration,
+    max_body_size: u64,
+}
+
+/// Simple HTTP server dispatching JSON-RPC requests to a [`Router`].
+pub struct SimpleHttpServer {
+    acceptor: Acceptor<TcpListener>,
+    addr: SocketAddr,
+    router: Arc<Router>,
+    config: Arc<Config>,
+}
+
+impl SimpleHttpServer {
+    /// Returns a builder for a server dispatching requests with `router`.
+    pub fn builder(router: Router) -> Builder {
+        Builder::new(router)
+    }
+
+    /// Returns the address the server is listening on.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Returns a handle to stop the server.
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.acceptor.shutdown_handle()
+    }
+
+    /// Serves connections until the server is shut down with a [`ShutdownHandle`].
+    ///
+    /// Once shut down, requests being handled are completed, their connection is closed, and
+    /// this returns once all connections are closed.
+    pub fn run(self) {
+        let router = self.router;
+        let config = self.config;
+        self.acceptor.run(config.workers, move |stream, handle| {
+            // Errors, e.g. timeouts, close the connection.
+            let _ = serve_connection(stream, &router, &config, handle);
+        });
+    }
+}
+
+impl fmt::Debug for SimpleHttpServer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "jsonrpc::simple_http_server::SimpleHttpServer({})", self.addr)
+    }
+}
+
+/// The parts of a request the server cares about.
+struct RequestHead {
+    method: String,
+    content_length: Option<u64>,
+    /// Whether the connection should be closed after the response.
+    close: bool,
+    authorization: Option<String>,
+    expect_continue: bool,
+    chunked: bool,
+}
+
+/// Reads the request line and header fields of a request, or returns `None` on a clean EOF.
+fn read_request_head<R: BufRead>(sock: &mut R) -> io::Result<Option<RequestHead>> {
+    let mut sock = sock.take(MAX_HEAD_SIZE);
+    let mut line = String::new();
+    if sock.read_line(&mut line)? == 0 {
+        return Ok(None);
+    }
+    let mut parts = line.split_whitespace();
+    let (method, version) = match (parts.next(), parts.next(), parts.next(), parts.next()) {
+        (Some(method), Some(_path), Some(version), None) if version.starts_with("HTTP/1.") => {
+            (method.to_owned(), version.to_owned())
+        }
+        _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "bad request line")),
+    };
+    let mut head = RequestHead {
+        method,
+        content_length: None,
+        close: version == "HTTP/1.0",
+        authorization: None,
+        expect_continue: false,
+        chunked: false,
+    };
+
+    loop {
+        line.clear();
+        if sock.read_line(&mut line)? == 0 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "truncated request head"));
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        let colon = match line.find(':') {
+            Some(colon) => colon,
+            None => return Err(io::Error::new(io::ErrorKind::InvalidData, "bad header field")),
+        };
+        let (name, value) = (line[..colon].to_ascii_lowercase(), line[colon + 1..].trim());
+        match name.as_str() {
+            "content-length" => {
+                let length = value.parse().map_err(|_| {
+                    io::Error::new(io::ErrorKind::InvalidData, "bad content-length")
+                })?;
+                head.content_length = Some(length);
+            }
+            "connection" => {
+                if value.eq_ignore_ascii_case("close") {
+                    head.close = true;
+                } else if value.eq_ignore_ascii_case("keep-alive") {
+                    head.close = false;
+                }
+            }
+            "authorization" => head.authorization = Some(value.to_owned()),
+            "expect" => head.expect_continue = value.eq_ignore_ascii_case("100-continue"),
+            "transfer-encoding" => head.chunked = !value.eq_ignore_ascii_case("identity"),
+            _ => {}
+        }
+    }
+    Ok(Some(head))
+}
+
+/// Returns whether the value of an `Authorization` header holds the expected credentials.
+fn check_auth(authorization: Option<&str>, credentials: &str) -> bool {
+    let authorization = match authorization {
+        Some(authorization) => authorization,
+        None => return false,
+    };
+    let mut parts = authorization.splitn(2, ' ');
+    let scheme = parts.next().unwrap_or("");
+    let decoded = match parts.next().map(|token| base64::decode(token.trim())) {
+        Some(Ok(decoded)) if scheme.eq_ignore_ascii_case("basic") => decoded,
+        _ => return false,
+    };
+    // Compare in constant time, so as not to leak how much of the credentials was guessed.
+    let expected = credentials.as_bytes();
+    decoded.len() == expected.len()
+        && decoded.iter().zip(expected).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+/// Writes a response, with `body` if any.
+fn write_response<W: Write>(
+    sock: &mut W,
+    status: &str,
+    headers: &str,
+    body: Option<&str>,
+    close: bool,
+) -> io::Result<()> {
+    let mut response = format!("HTTP/1.1 {}\r\n{}", status, headers);
+    if let Some(body) = body {
+        response.push_str("Content-Type: application/json\r\n");
+        response.push_str(&format!("Content-Length: {}\r\n", body.len()));
+    } else if !status.starts_with("204") {
+        response.push_str("Content-Length: 0\r\n");
+    }
+    if close {
+        response.push_str("Connection: close\r\n");
+    }
+    response.push_str("\r\n");
+    response.push_str(body.unwrap_or(""));
+    sock.write_all(response.as_bytes())?;
+    sock.flush()
+}
+
+/// Rejects a request with the given status, and closes the connection.
+///
+/// The body of the request is read first if possible, so that the client reads the response
+/// rather than seeing the connection reset.
+fn reject<R: Read, W: Write>(
+    reader: &mut R,
+    writer: &mut W,
+    head: &RequestHead,
+    config: &Config,
+    status: &str,
+    headers: &str,
+) -> io::Result<()> {
+    match head.content_length {
+        Some(n) if n <= config.max_body_size && !head.chunked && !head.expect_continue => {
+            io::copy(&mut reader.take(n), &mut io::sink())?;
+        }
+        _ => {}
+    }
+    write_response(writer, status, headers, None, true)
+}
+
+/// Serves the requests of a connection until it is closed.
+fn serve_connection(
+    stream: TcpStream,
+    router: &Router,
+    config: &Config,
+    handle: &ShutdownHandle,
+) -> io::Result<()> {
+    stream.set_timeout(Some(config.timeout))?;
+    let mut writer = stream.try_clone()?;
+    let mut reader = BufReader::new(stream);
+
+    loop {
+        let head = match read_request_head(&mut reader) {
+            Ok(Some(head)) => head,
+            Ok(None) => return Ok(()),
+            Err(ref e) if e.kind() == io::ErrorKind::InvalidData => {
+                return write_response(&mut writer, "400 Bad Request", "", None, true);
+            }
+            Err(e) => return Err(e),
+        };
+        // Stop serving the connection after this request if the server is shutting down.
+        let close = head.close || handle.is_shutdown();
+
+        let (status, headers) = match (&config.credentials, head.content_length) {
+            (Some(credentials), _) if !check_auth(head.authorization.as_deref(), credentials) => {
+                ("401 Unauthorized", "WWW-Authenticate: Basic realm=\"jsonrpc\"\r\n")
+            }
+            _ if head.method != "POST" => ("405 Method Not Allowed", "Allow: POST\r\n"),
+            _ if head.chunked => ("501 Not Implemented", ""),
+            (_, None) => ("411 Length Required", ""),
+            (_, Some(n)) if n > config.max_body_size => ("413 Payload Too Large", ""),
+            (_, Some(_)) => ("", ""),
+        };
+        if !status.is_empty() {
+            return reject(&mut reader, &mut writer, &head, config, status, headers);
+        }
+        let length = head.content_length.unwrap_or(0);
+
+        if head.expect_continue {
+            writer.write_all(b"HTTP/1.1 100 Continue\r\n\r\n")?;
+        }
+        let mut body = Vec::with_capacity(length as usize);
+        (&mut reader).take(length).read_to_end(&mut body)?;
+        if body.len() as u64 != length {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "truncated request body"));
+        }
+
+        match router.handle(&body) {
+            Some(reply) => write_response(&mut writer, "200 OK", "", Some(&reply), close)?,
+            None => write_response(&mut writer, "204 No Content", "", None, close)?,
+        }
+        if close {
+            return Ok(());
+        }
+    }
+}
+
+/// Builder for a [`SimpleHttpServer`].
+#[derive(Debug)]
+pub struct Builder {
+    router: Router,
+    config: Config,
+}
+
+impl Builder {
+    /// Constructs a new [`Builder`] for a server dispatching requests with `router`.
+    pub fn new(router: Router) -> Builder {
+        Builder {
+            router,
+            config: Config {
+                credentials: None,
+                workers: Workers::default(),
+                timeout: Duration::from_secs(30),
+                max_body_size: DEFAULT_MAX_BODY_SIZE,
+            },
+        }
+    }
+
+    /// Sets how connections are distributed among threads, each on its own by default.
+    ///
+    /// Note that with a pool, idle connections kept alive keep their thread busy until they
+    /// time out.
+    pub fn workers(mut self, workers: Workers) -> Self {
+        self.config.workers = workers;
+        self
+    }
+
+    /// Sets the timeout after which a connection is closed if the client is idle.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.config.timeout = timeout;
+        self
+    }
+
+    /// Requires requests to carry the given Basic authentication credentials.
+    pub fn auth<S: AsRef<str>>(mut self, user: S, pass: Option<S>) -> Self {
+        let mut credentials = user.as_ref().to_owned();
+        credentials.push(':');
+        if let Some(ref pass) = pass {
+            credentials.push_str(pass.as_ref());
+        }
+        self.config.credentials = Some(credentials);
+        self
+    }
+
+    /// Requires requests to carry the credentials of the given cookie string ('user:pass').
+    pub fn cookie_auth<S: AsRef<str>>(mut self, cookie: S) -> Self {
+        self.config.credentials = Some(cookie.as_ref().to_owned());
+        self
+    }
+
+    /// Sets the maximum size of the body of a request, larger ones are rejected.
+    ///
+    /// The default is [`DEFAULT_MAX_BODY_SIZE`].
+    pub fn max_body_size(mut self, size: u64) -> Self {
+        self.config.max_body_size = size;
+        self
+    }
+
+    /// Binds the server to the given address, ready to be run.
+    pub fn bind<A: ToSocketAddrs>(self, addr: A) -> io::Result<SimpleHttpServer> {
+        let listener = TcpListener::bind(addr)?;
+        Ok(SimpleHttpServer {
+            addr: listener.local_addr()?,
+            acceptor: Acceptor::new(listener)?,
+            router: Arc::new(self.router),
+            config: Arc::new(self.config),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use serde_json::value::RawValue;
+    use serde_json::Value;
+
+    use super::*;
+    use crate::server::parse_params;
+
+    fn start(builder: Builder) -> (SocketAddr, ShutdownHandle, thread::JoinHandle<()>) {
+        let server = builder.bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr();
+        let handle = server.shutdown_handle();
+        (addr, handle, thread::spawn(move || server.run()))
+    }
+
+    fn router() -> Router {
+        Router::new().method("echo", |params: Option<&RawValue>| parse_params::<Value>(params))
+    }
+
+    /// Writes a raw request and reads the response up to the end of the body.
+    fn exchange(sock: &mut BufReader<TcpStream>, request: &str) -> String {
+        sock.get_mut().write_all(request.as_bytes()).unwrap();
+        let mut response = String::new();
+        let mut content_length = 0;
+        loop {
+            let n = sock.read_line(&mut response).unwrap();
+            let line = &response[response.len() - n..];
+            if line == "\r\n" || n == 0 {
+                break;
+            }
+            if line.to_ascii_lowercase().starts_with("content-length: ") {
+                content_length = line[16..].trim().parse().unwrap();
+            }
+        }
+        let mut body = vec![0; content_length];
+        sock.read_exact(&mut body).unwrap();
+        response + &String::from_utf8(body).unwrap()
+    }
+
+    fn post(body: &str, headers: &str) -> String {
+        format!("POST / HTTP/1.1\r\n{}Content-Length: {}\r\n\r\n{}", headers, body.len(), body)
+    }
+
+    #[test]
+    fn keep_alive_and_shutdown() {
+        let (addr, handle, server) = start(Builder::new(router()));
+        let mut sock = BufReader::new(TcpStream::connect(addr).unwrap());
+
+        let request = r#"{"jsonrpc":"2.0","method":"echo","params":[1],"id":1}"#;
+        let response = exchange(&mut sock, &post(request, ""));
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+        assert!(response.ends_with(r#"{"result":[1],"id":1,"jsonrpc":"2.0"}"#), "{}", response);
+
+        // The connection stays open for further requests.
+        let notification = r#"{"jsonrpc":"2.0","method":"echo"}"#;
+        let response = exchange(&mut sock, &post(notification, ""));
+        assert_eq!(response, "HTTP/1.1 204 No Content\r\n\r\n");
+        let response = exchange(&mut sock, "GET / HTTP/1.1\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 405 "), "{}", response);
+
+        // Shutting down closes idle connections.
+        let idle = TcpStream::connect(addr).unwrap();
+        handle.shutdown();
+        assert!(handle.is_shutdown());
+        server.join().unwrap();
+        assert_eq!((&idle).read_to_end(&mut vec![]).unwrap(), 0);
+    }
+
+    #[test]
+    fn rejected_requests() {
+        let builder = Builder::new(router()).auth("user", Some("hunter2")).max_body_size(64);
+        let (addr, handle, server) = start(builder);
+        let connect = || BufReader::new(TcpStream::connect(addr).unwrap());
+
+        let request = r#"{"jsonrpc":"2.0","method":"echo","params":[1],"id":1}"#;
+        let response = exchange(&mut connect(), &post(request, ""));
+        assert!(response.starts_with("HTTP/1.1 401 "), "{}", response);
+        let wrong = "Authorization: Basic dXNlcjpodW50ZXIx\r\n";
+        let response = exchange(&mut connect(), &post(request, wrong));
+        assert!(response.starts_with("HTTP/1.1 401 "), "{}", response);
+
+        let auth = "Authorization: Basic dXNlcjpodW50ZXIy\r\n";
+        let response = exchange(&mut connect(), &post(request, auth));
+        assert!(response.starts_with("HTTP/1.1 200 "), "{}", response);
+        let response = exchange(&mut connect(), &post(&"1".repeat(65), auth));
+        assert!(response.starts_with("HTTP/1.1 413 "), "{}", response);
+        let response = exchange(&mut connect(), &format!("POST / HTTP/1.1\r\n{}\r\n", auth));
+        assert!(response.starts_with("HTTP/1.1 411 "), "{}", response);
+        let response = exchange(&mut connect(), "nonsense\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 400 "), "{}", response);
+
+        handle.shutdown();
+        server.join().unwrap();
+    }
+
+    #[cfg(all(feature = "simple_http", not(feature = "proxy")))]
+    #[test]
+    fn simple_http_client() {
+        use crate::simple_http::{self, SimpleHttpTransport};
+        use crate::{arg, Client};
+
+        let builder = Builder::new(router()).cookie_auth("user:hunter2");
+        let (addr, handle, server) = start(builder);
+
+        let url = format!("http://{}", addr);
+        let client =
+            Client::simple_http(&url, Some("user".to_owned()), Some("hunter2".to_owned())).unwrap();
+        assert_eq!(client.call::<Vec<u32>>("echo", &[arg(1), arg(2)]).unwrap(), [1, 2]);
+        client.notify("echo", &[]).unwrap();
+        match client.call::<Value>("missing", &[]) {
+            Err(crate::Error::Rpc(ref e)) if e.code == -32601 => {}
+            r => panic!("expected method not found, got {:?}", r),
+        }
+
+        let tp = SimpleHttpTransport::builder().url(&url).unwrap().auth("user", None).build();
+        match Client::with_transport(tp).call::<Value>("echo", &[]) {
+            Err(crate::Error::Transport(e)) => match e.downcast_ref() {
+                Some(simple_http::Error::HttpErrorCode(401)) => {}
+                _ => panic!("expected 401, got {:?}", e),
+            },
+            r => panic!("expected 401, got {:?}", r),
+        }
+
+        handle.shutdown();
+        server.join().unwrap();
+    }
+}
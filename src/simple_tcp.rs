@@ -2,12 +2,17 @@
 //! it does not handle TCP over Unix Domain Sockets, see `simple_uds` for this.
 //!
 
+use std::io::Write;
 use std::{error, fmt, io, net, time};
 
 use serde;
 use serde_json;
 
-use crate::client::Transport;
+#[cfg(feature = "async")]
+use crate::async_client::{AsyncTransport, BoxFuture};
+#[cfg(feature = "async")]
+use crate::blocking::spawn_blocking;
+use crate::client::{ReadBody, Transport};
 use crate::{Request, Response};
 
 /// Error that can occur while using the TCP transport.
@@ -86,35 +91,146 @@ impl TcpTransport {
     where
         R: for<'a> serde::de::Deserialize<'a>,
     {
-        let mut sock = net::TcpStream::connect(self.addr)?;
+        let mut sock = self.connect()?;
         sock.set_read_timeout(self.timeout)?;
         sock.set_write_timeout(self.timeout)?;
 
-        serde_json::to_writer(&mut sock, &req)?;
+        self.write_request(&mut sock, req)?;
 
         // NOTE: we don't check the id there, so it *must* be synchronous
-        let resp: R = serde_json::Deserializer::from_reader(&mut sock)
+        let resp = serde_json::Deserializer::from_reader(&mut sock)
             .into_iter()
             .next()
-            .ok_or(Error::Timeout)??;
-        Ok(resp)
+            .ok_or(Error::Timeout)?;
+        trace_event!("{}", crate::trace::BodyParse(&resp));
+        Ok(resp?)
+    }
+
+    fn request_streaming(
+        &self,
+        req: impl serde::Serialize,
+        read_body: &mut ReadBody,
+    ) -> Result<(), crate::Error> {
+        let mut sock = self.connect()?;
+        sock.set_read_timeout(self.timeout).map_err(Error::SocketError)?;
+        sock.set_write_timeout(self.timeout).map_err(Error::SocketError)?;
+
+        self.write_request(&mut sock, req)?;
+
+        let result = read_body(&mut io::BufReader::new(sock));
+        trace_event!("{}", crate::trace::BodyParse(&result));
+        result
+    }
+
+    fn notify(&self, req: impl serde::Serialize) -> Result<(), Error> {
+        let mut sock = self.connect()?;
+        sock.set_write_timeout(self.timeout)?;
+
+        // No response is sent for notifications, so we are done once it is written.
+        self.write_request(&mut sock, req)
+    }
+
+    fn connect(&self) -> Result<net::TcpStream, Error> {
+        trace_event!("connect to {}", self.addr);
+        Ok(net::TcpStream::connect(self.addr)?)
+    }
+
+    fn write_request(
+        &self,
+        sock: &mut net::TcpStream,
+        req: impl serde::Serialize,
+    ) -> Result<(), Error> {
+        let body = serde_json::to_vec(&req)?;
+        sock.write_all(&body)?;
+        trace_event!("wrote request of {} bytes", body.len());
+        Ok(())
     }
 }
 
 impl Transport for TcpTransport {
     fn send_request(&self, req: Request) -> Result<Response, crate::Error> {
+        trace_span!(std::slice::from_ref(&req));
         Ok(self.request(req)?)
     }
 
+    fn send_request_streaming(
+        &self,
+        req: Request,
+        read_body: &mut ReadBody,
+    ) -> Result<(), crate::Error> {
+        trace_span!(std::slice::from_ref(&req));
+        self.request_streaming(req, read_body)
+    }
+
     fn send_batch(&self, reqs: &[Request]) -> Result<Vec<Response>, crate::Error> {
+        trace_span!(reqs);
+        if reqs.iter().all(Request::is_notification) {
+            self.notify(reqs)?;
+            return Ok(vec![]);
+        }
         Ok(self.request(reqs)?)
     }
 
+    fn send_notification(&self, req: Request) -> Result<(), crate::Error> {
+        trace_span!(std::slice::from_ref(&req));
+        Ok(self.notify(req)?)
+    }
+
     fn fmt_target(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "{}", self.addr)
     }
 }
 
+#[cfg(feature = "async")]
+impl AsyncTransport for TcpTransport {
+    fn send_request<'a>(
+        &'a self,
+        req: Request<'a>,
+    ) -> BoxFuture<'a, Result<Response, crate::Error>> {
+        let tp = self.clone();
+        let body = serde_json::value::to_raw_value(&req);
+        Box::pin(async move {
+            let body = body?;
+            Ok(spawn_blocking(trace_in_span!(std::slice::from_ref(&req), move || tp.request(body)))
+                .await?)
+        })
+    }
+
+    fn send_batch<'a>(
+        &'a self,
+        reqs: &'a [Request<'a>],
+    ) -> BoxFuture<'a, Result<Vec<Response>, crate::Error>> {
+        let tp = self.clone();
+        let all_notifications = reqs.iter().all(Request::is_notification);
+        let body = serde_json::value::to_raw_value(reqs);
+        Box::pin(async move {
+            let body = body?;
+            if all_notifications {
+                spawn_blocking(trace_in_span!(reqs, move || tp.notify(body))).await?;
+                return Ok(vec![]);
+            }
+            Ok(spawn_blocking(trace_in_span!(reqs, move || tp.request(body))).await?)
+        })
+    }
+
+    fn send_notification<'a>(
+        &'a self,
+        req: Request<'a>,
+    ) -> BoxFuture<'a, Result<(), crate::Error>> {
+        let tp = self.clone();
+        let body = serde_json::value::to_raw_value(&req);
+        Box::pin(async move {
+            let body = body?;
+            Ok(spawn_blocking(trace_in_span!(std::slice::from_ref(&req), move || tp.notify(body)))
+                .await?)
+        })
+    }
+
+    fn fmt_target(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        Transport::fmt_target(self, f)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::{
@@ -123,7 +239,7 @@ mod tests {
     };
 
     use super::*;
-    use crate::Client;
+    use crate::{Client, Params};
 
     // Test a dummy request / response over a raw TCP transport
     #[test]
@@ -134,8 +250,8 @@ mod tests {
         let addr = server.local_addr().unwrap();
         let dummy_req = Request {
             method: "arandommethod",
-            params: &[],
-            id: serde_json::Value::Number(4242242.into()),
+            params: Params::ByPosition(&[]),
+            id: Some(serde_json::Value::Number(4242242.into())),
             jsonrpc: Some("2.0"),
         };
         let dummy_req_ser = serde_json::to_vec(&dummy_req).unwrap();
@@ -171,4 +287,57 @@ mod tests {
         let recv_resp = client_thread.join().unwrap();
         assert_eq!(serde_json::to_vec(&recv_resp).unwrap(), dummy_resp_ser);
     }
+
+    // Test a dummy request / response over the asynchronous TCP transport
+    #[cfg(feature = "async")]
+    #[test]
+    fn async_tcp_transport() {
+        use crate::async_client::tests::block_on;
+        use crate::AsyncClient;
+
+        let addr: net::SocketAddr =
+            net::SocketAddrV4::new(net::Ipv4Addr::new(127, 0, 0, 1), 0).into();
+        let server = net::TcpListener::bind(addr).unwrap();
+        let addr = server.local_addr().unwrap();
+
+        let server_thread = thread::spawn(move || {
+            let (mut stream, _) = server.accept().unwrap();
+            let req: serde_json::Value = serde_json::Deserializer::from_reader(&mut stream)
+                .into_iter()
+                .next()
+                .unwrap()
+                .unwrap();
+            assert_eq!(req["method"], "arandommethod");
+            let resp = crate::error::result_to_response(Ok(true.into()), req["id"].clone());
+            serde_json::to_writer(&mut stream, &resp).unwrap();
+        });
+
+        let client = AsyncClient::with_transport(TcpTransport {
+            addr,
+            timeout: Some(time::Duration::from_secs(5)),
+        });
+        assert!(block_on(client.call::<bool>("arandommethod", &[])).unwrap());
+        server_thread.join().unwrap();
+    }
+
+    // Test that a notification is sent without waiting for a response
+    #[test]
+    fn tcp_transport_notification() {
+        let addr: net::SocketAddr =
+            net::SocketAddrV4::new(net::Ipv4Addr::new(127, 0, 0, 1), 0).into();
+        let server = net::TcpListener::bind(addr).unwrap();
+        let addr = server.local_addr().unwrap();
+
+        let client = Client::with_transport(TcpTransport {
+            addr,
+            timeout: Some(time::Duration::from_secs(5)),
+        });
+        client.notify("arandommethod", &[]).unwrap();
+
+        let (mut stream, _) = server.accept().unwrap();
+        stream.set_read_timeout(Some(time::Duration::from_secs(5))).unwrap();
+        let mut recv_req = vec![];
+        stream.read_to_end(&mut recv_req).unwrap();
+        assert_eq!(recv_req, br#"{"method":"arandommethod","params":[],"jsonrpc":"2.0"}"#);
+    }
 }
@@ -0,0 +1,219 @@
+//! This module implements a synchronous server over a raw TcpListener, the counterpart of
+//! [`crate::simple_tcp`]. Requests and responses are sent over the connection as concatenated
+//! JSON values, and dispatched with a [`Router`].
+//!
+//! ```no_run
+//! use jsonrpc::server::Router;
+//! use jsonrpc::simple_tcp_server::{TcpServer, Workers};
+//!
+//! let router = Router::new().method("uptime", |_: Option<&_>| Ok(42.into()));
+//! let server = TcpServer::builder(router).workers(Workers::Pool(4)).bind("127.0.0.1:0");
+//! let server = server.unwrap();
+//! let shutdown = server.shutdown_handle();
+//! std::thread::spawn(move || server.run());
+//! // ...
+//! shutdown.shutdown();
+//! ```
+//!
+
+use std::net::{SocketAddr, TcpListener, ToSocketAddrs};
+use std::sync::Arc;
+use std::time::Duration;
+use std::{fmt, io};
+
+use crate::serve::{self, Acceptor};
+pub use crate::serve::{ShutdownHandle, Workers};
+use crate::server::Router;
+
+/// Simple synchronous TCP server dispatching JSON-RPC requests to a [`Router`].
+pub struct TcpServer {
+    acceptor: Acceptor<TcpListener>,
+    addr: SocketAddr,
+    router: Arc<Router>,
+    workers: Workers,
+    timeout: Option<Duration>,
+}
+
+impl TcpServer {
+    /// Returns a builder for a server dispatching requests with `router`.
+    pub fn builder(router: Router) -> Builder {
+        Builder::new(router)
+    }
+
+    /// Returns the address the server is listening on.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Returns a handle to stop the server.
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.acceptor.shutdown_handle()
+    }
+
+    /// Serves connections until the server is shut down with a [`ShutdownHandle`].
+    ///
+    /// Once shut down, requests being handled are completed, their connection is closed, and
+    /// this returns once all connections are closed.
+    pub fn run(self) {
+        let router = self.router;
+        let timeout = self.timeout;
+        self.acceptor.run(self.workers, move |stream, handle| {
+            // Errors, e.g. timeouts, close the connection.
+            let _ = serve::serve_json_stream(stream, &router, timeout, handle);
+        });
+    }
+}
+
+impl fmt::Debug for TcpServer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "jsonrpc::simple_tcp_server::TcpServer({})", self.addr)
+    }
+}
+
+/// Builder for a [`TcpServer`].
+#[derive(Debug)]
+pub struct Builder {
+    router: Router,
+    workers: Workers,
+    timeout: Option<Duration>,
+}
+
+impl Builder {
+    /// Constructs a new [`Builder`] for a server dispatching requests with `router`.
+    pub fn new(router: Router) -> Builder {
+        Builder {
+            router,
+            workers: Workers::default(),
+            timeout: Some(Duration::from_secs(30)),
+        }
+    }
+
+    /// Sets how connections are distributed among threads, each on its own by default.
+    pub fn workers(mut self, workers: Workers) -> Self {
+        self.workers = workers;
+        self
+    }
+
+    /// Sets the timeout after which a connection is closed if the client is idle, or `None` to
+    /// wait forever.
+    pub fn timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Binds the server to the given address, ready to be run.
+    pub fn bind<A: ToSocketAddrs>(self, addr: A) -> io::Result<TcpServer> {
+        let listener = TcpListener::bind(addr)?;
+        Ok(TcpServer {
+            addr: listener.local_addr()?,
+            acceptor: Acceptor::new(listener)?,
+            router: Arc::new(self.router),
+            workers: self.workers,
+            timeout: self.timeout,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+    use std::thread;
+
+    use serde_json::value::RawValue;
+
+    use super::*;
+    use crate::server::parse_params;
+
+    fn router() -> Router {
+        Router::new()
+            .method("echo", |params: Option<&RawValue>| parse_params::<serde_json::Value>(params))
+    }
+
+    #[test]
+    fn concatenated_values() {
+        let server = TcpServer::builder(router()).bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr();
+        let handle = server.shutdown_handle();
+        let server = thread::spawn(move || server.run());
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        let requests = concat!(
+            r#"{"jsonrpc":"2.0","method":"echo","params":[1],"id":1}"#,
+            r#"{"jsonrpc":"2.0","method":"echo","params":[2]}"#,
+            "\n",
+            r#"[{"jsonrpc":"2.0","method":"echo","params":[3],"id":3}]"#,
+            "{nonsense",
+        );
+        stream.write_all(requests.as_bytes()).unwrap();
+        let mut replies = String::new();
+        stream.read_to_string(&mut replies).unwrap();
+        assert_eq!(
+            replies,
+            concat!(
+                r#"{"result":[1],"id":1,"jsonrpc":"2.0"}"#,
+                r#"[{"result":[3],"id":3,"jsonrpc":"2.0"}]"#,
+                r#"{"error":{"code":-32700,"message":"Parse error","data":null},"#,
+                r#""id":null,"jsonrpc":"2.0"}"#,
+            )
+        );
+
+        handle.shutdown();
+        server.join().unwrap();
+    }
+
+    #[cfg(feature = "simple_tcp")]
+    #[test]
+    fn worker_pool() {
+        use crate::simple_tcp::TcpTransport;
+        use crate::{arg, Client};
+
+        let server =
+            TcpServer::builder(router()).workers(Workers::Pool(2)).bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr();
+        let handle = server.shutdown_handle();
+        let server = thread::spawn(move || server.run());
+
+        // Idle connections keep both workers busy, the client is served once one of them closes.
+        let idle = TcpStream::connect(addr).unwrap();
+        let _idle = TcpStream::connect(addr).unwrap();
+        let client = thread::spawn(move || {
+            let client = Client::with_transport(TcpTransport::new(addr));
+            client.call::<Vec<u32>>("echo", &[arg(1)]).unwrap()
+        });
+        thread::sleep(Duration::from_millis(50));
+        drop(idle);
+        assert_eq!(client.join().unwrap(), [1]);
+
+        handle.shutdown();
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn panicking_handler() {
+        let router = router().method("panic", |_: Option<&RawValue>| panic!("handler panicked"));
+        let server =
+            TcpServer::builder(router).workers(Workers::Pool(1)).bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr();
+        let handle = server.shutdown_handle();
+        let server = thread::spawn(move || server.run());
+
+        let send = |request: &str| {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            stream.write_all(request.as_bytes()).unwrap();
+            stream.shutdown(std::net::Shutdown::Write).unwrap();
+            let mut replies = String::new();
+            stream.read_to_string(&mut replies).unwrap();
+            replies
+        };
+        // The connection is closed, and the only worker serves the next one.
+        assert_eq!(send(r#"{"jsonrpc":"2.0","method":"panic","id":1}"#), "");
+        assert_eq!(
+            send(r#"{"jsonrpc":"2.0","method":"echo","params":[1],"id":2}"#),
+            r#"{"result":[1],"id":2,"jsonrpc":"2.0"}"#
+        );
+
+        handle.shutdown();
+        server.join().unwrap();
+    }
+}
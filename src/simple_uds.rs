@@ -1,13 +1,18 @@
 //! This module implements a synchronous transport over a raw TcpListener.
 //!
 
+use std::io::Write;
 use std::os::unix::net::UnixStream;
 use std::{error, fmt, io, path, time};
 
 use serde;
 use serde_json;
 
-use crate::client::Transport;
+#[cfg(feature = "async")]
+use crate::async_client::{AsyncTransport, BoxFuture};
+#[cfg(feature = "async")]
+use crate::blocking::spawn_blocking;
+use crate::client::{ReadBody, Transport};
 use crate::{Request, Response};
 
 /// Error that can occur while using the UDS transport.
@@ -86,35 +91,146 @@ impl UdsTransport {
     where
         R: for<'a> serde::de::Deserialize<'a>,
     {
-        let mut sock = UnixStream::connect(&self.sockpath)?;
+        let mut sock = self.connect()?;
         sock.set_read_timeout(self.timeout)?;
         sock.set_write_timeout(self.timeout)?;
 
-        serde_json::to_writer(&mut sock, &req)?;
+        self.write_request(&mut sock, req)?;
 
         // NOTE: we don't check the id there, so it *must* be synchronous
-        let resp: R = serde_json::Deserializer::from_reader(&mut sock)
+        let resp = serde_json::Deserializer::from_reader(&mut sock)
             .into_iter()
             .next()
-            .ok_or(Error::Timeout)??;
-        Ok(resp)
+            .ok_or(Error::Timeout)?;
+        trace_event!("{}", crate::trace::BodyParse(&resp));
+        Ok(resp?)
+    }
+
+    fn request_streaming(
+        &self,
+        req: impl serde::Serialize,
+        read_body: &mut ReadBody,
+    ) -> Result<(), crate::error::Error> {
+        let mut sock = self.connect()?;
+        sock.set_read_timeout(self.timeout).map_err(Error::SocketError)?;
+        sock.set_write_timeout(self.timeout).map_err(Error::SocketError)?;
+
+        self.write_request(&mut sock, req)?;
+
+        let result = read_body(&mut io::BufReader::new(sock));
+        trace_event!("{}", crate::trace::BodyParse(&result));
+        result
+    }
+
+    fn notify(&self, req: impl serde::Serialize) -> Result<(), Error> {
+        let mut sock = self.connect()?;
+        sock.set_write_timeout(self.timeout)?;
+
+        // No response is sent for notifications, so we are done once it is written.
+        self.write_request(&mut sock, req)
+    }
+
+    fn connect(&self) -> Result<UnixStream, Error> {
+        trace_event!("connect to {}", self.sockpath.display());
+        Ok(UnixStream::connect(&self.sockpath)?)
+    }
+
+    fn write_request(
+        &self,
+        sock: &mut UnixStream,
+        req: impl serde::Serialize,
+    ) -> Result<(), Error> {
+        let body = serde_json::to_vec(&req)?;
+        sock.write_all(&body)?;
+        trace_event!("wrote request of {} bytes", body.len());
+        Ok(())
     }
 }
 
 impl Transport for UdsTransport {
     fn send_request(&self, req: Request) -> Result<Response, crate::error::Error> {
+        trace_span!(std::slice::from_ref(&req));
         Ok(self.request(req)?)
     }
 
+    fn send_request_streaming(
+        &self,
+        req: Request,
+        read_body: &mut ReadBody,
+    ) -> Result<(), crate::error::Error> {
+        trace_span!(std::slice::from_ref(&req));
+        self.request_streaming(req, read_body)
+    }
+
     fn send_batch(&self, reqs: &[Request]) -> Result<Vec<Response>, crate::error::Error> {
+        trace_span!(reqs);
+        if reqs.iter().all(Request::is_notification) {
+            self.notify(reqs)?;
+            return Ok(vec![]);
+        }
         Ok(self.request(reqs)?)
     }
 
+    fn send_notification(&self, req: Request) -> Result<(), crate::error::Error> {
+        trace_span!(std::slice::from_ref(&req));
+        Ok(self.notify(req)?)
+    }
+
     fn fmt_target(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "{}", self.sockpath.to_string_lossy())
     }
 }
 
+#[cfg(feature = "async")]
+impl AsyncTransport for UdsTransport {
+    fn send_request<'a>(
+        &'a self,
+        req: Request<'a>,
+    ) -> BoxFuture<'a, Result<Response, crate::error::Error>> {
+        let tp = self.clone();
+        let body = serde_json::value::to_raw_value(&req);
+        Box::pin(async move {
+            let body = body?;
+            Ok(spawn_blocking(trace_in_span!(std::slice::from_ref(&req), move || tp.request(body)))
+                .await?)
+        })
+    }
+
+    fn send_batch<'a>(
+        &'a self,
+        reqs: &'a [Request<'a>],
+    ) -> BoxFuture<'a, Result<Vec<Response>, crate::error::Error>> {
+        let tp = self.clone();
+        let all_notifications = reqs.iter().all(Request::is_notification);
+        let body = serde_json::value::to_raw_value(reqs);
+        Box::pin(async move {
+            let body = body?;
+            if all_notifications {
+                spawn_blocking(trace_in_span!(reqs, move || tp.notify(body))).await?;
+                return Ok(vec![]);
+            }
+            Ok(spawn_blocking(trace_in_span!(reqs, move || tp.request(body))).await?)
+        })
+    }
+
+    fn send_notification<'a>(
+        &'a self,
+        req: Request<'a>,
+    ) -> BoxFuture<'a, Result<(), crate::error::Error>> {
+        let tp = self.clone();
+        let body = serde_json::value::to_raw_value(&req);
+        Box::pin(async move {
+            let body = body?;
+            Ok(spawn_blocking(trace_in_span!(std::slice::from_ref(&req), move || tp.notify(body)))
+                .await?)
+        })
+    }
+
+    fn fmt_target(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        Transport::fmt_target(self, f)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::{
@@ -125,7 +241,7 @@ mod tests {
     };
 
     use super::*;
-    use crate::Client;
+    use crate::{Client, Params};
 
     // Test a dummy request / response over an UDS
     #[test]
@@ -137,8 +253,8 @@ mod tests {
         let server = UnixListener::bind(&socket_path).unwrap();
         let dummy_req = Request {
             method: "getinfo",
-            params: &[],
-            id: serde_json::Value::Number(111.into()),
+            params: Params::ByPosition(&[]),
+            id: Some(serde_json::Value::Number(111.into())),
             jsonrpc: Some("2.0"),
         };
         let dummy_req_ser = serde_json::to_vec(&dummy_req).unwrap();
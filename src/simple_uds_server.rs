@@ -0,0 +1,137 @@
+//! This module implements a synchronous server over a Unix Domain Socket, the counterpart of
+//! [`crate::simple_uds`]. Requests and responses are sent over the connection as concatenated
+//! JSON values, and dispatched with a [`Router`].
+//!
+
+use std::os::unix::net::UnixListener;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use std::{fmt, fs, io};
+
+use crate::serve::{self, Acceptor};
+pub use crate::serve::{ShutdownHandle, Workers};
+use crate::server::Router;
+
+/// Simple synchronous UDS server dispatching JSON-RPC requests to a [`Router`].
+pub struct UdsServer {
+    acceptor: Acceptor<UnixListener>,
+    sockpath: PathBuf,
+    router: Arc<Router>,
+    workers: Workers,
+    timeout: Option<Duration>,
+}
+
+impl UdsServer {
+    /// Returns a builder for a server dispatching requests with `router`.
+    pub fn builder(router: Router) -> Builder {
+        Builder::new(router)
+    }
+
+    /// Returns the path of the socket the server is listening on.
+    pub fn sockpath(&self) -> &Path {
+        &self.sockpath
+    }
+
+    /// Returns a handle to stop the server.
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.acceptor.shutdown_handle()
+    }
+
+    /// Serves connections until the server is shut down with a [`ShutdownHandle`].
+    ///
+    /// Once shut down, requests being handled are completed, their connection is closed, and
+    /// this returns once all connections are closed and the socket is removed.
+    pub fn run(self) {
+        let router = self.router;
+        let timeout = self.timeout;
+        self.acceptor.run(self.workers, move |stream, handle| {
+            // Errors, e.g. timeouts, close the connection.
+            let _ = serve::serve_json_stream(stream, &router, timeout, handle);
+        });
+        let _ = fs::remove_file(&self.sockpath);
+    }
+}
+
+impl fmt::Debug for UdsServer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "jsonrpc::simple_uds_server::UdsServer({})", self.sockpath.display())
+    }
+}
+
+/// Builder for a [`UdsServer`].
+#[derive(Debug)]
+pub struct Builder {
+    router: Router,
+    workers: Workers,
+    timeout: Option<Duration>,
+}
+
+impl Builder {
+    /// Constructs a new [`Builder`] for a server dispatching requests with `router`.
+    pub fn new(router: Router) -> Builder {
+        Builder {
+            router,
+            workers: Workers::default(),
+            timeout: Some(Duration::from_secs(30)),
+        }
+    }
+
+    /// Sets how connections are distributed among threads, each on its own by default.
+    pub fn workers(mut self, workers: Workers) -> Self {
+        self.workers = workers;
+        self
+    }
+
+    /// Sets the timeout after which a connection is closed if the client is idle, or `None` to
+    /// wait forever.
+    pub fn timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Binds the server to a new socket at the given path, ready to be run.
+    pub fn bind<P: AsRef<Path>>(self, sockpath: P) -> io::Result<UdsServer> {
+        let listener = UnixListener::bind(&sockpath)?;
+        Ok(UdsServer {
+            acceptor: Acceptor::new(listener)?,
+            sockpath: sockpath.as_ref().to_path_buf(),
+            router: Arc::new(self.router),
+            workers: self.workers,
+            timeout: self.timeout,
+        })
+    }
+}
+
+#[cfg(all(test, feature = "simple_uds"))]
+mod tests {
+    use std::thread;
+
+    use serde_json::value::RawValue;
+
+    use super::*;
+    use crate::server::parse_params;
+    use crate::simple_uds::UdsTransport;
+    use crate::{arg, Client};
+
+    #[test]
+    fn uds_client() {
+        let router = Router::new()
+            .method("echo", |params: Option<&RawValue>| parse_params::<serde_json::Value>(params));
+        let sockpath =
+            std::env::temp_dir().join(format!("jsonrpc-uds-server-{}", std::process::id()));
+        let _ = fs::remove_file(&sockpath);
+        let server = UdsServer::builder(router).workers(Workers::Pool(1)).bind(&sockpath).unwrap();
+        let handle = server.shutdown_handle();
+        let server = thread::spawn(move || server.run());
+
+        let client = Client::with_transport(UdsTransport::new(&sockpath));
+        assert_eq!(client.call::<Vec<u32>>("echo", &[arg(1)]).unwrap(), [1]);
+        client.notify("echo", &[]).unwrap();
+        assert_eq!(client.call::<Vec<u32>>("echo", &[arg(2)]).unwrap(), [2]);
+
+        handle.shutdown();
+        server.join().unwrap();
+        assert!(!sockpath.exists());
+    }
+}
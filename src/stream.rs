@@ -0,0 +1,344 @@
+//! # Streaming responses
+//!
+//! Support for deserializing the result of a call straight from the connection, without holding
+//! the whole response in memory first. See
+//! [`Client::call_streaming`](crate::Client::call_streaming),
+//! [`Client::call_seed`](crate::Client::call_seed) and
+//! [`Client::for_each`](crate::Client::for_each).
+//!
+//! Transports hand the body of the response over with
+//! [`Transport::send_request_streaming`](crate::Transport::send_request_streaming). The bundled
+//! transports stream it from their socket, others fall back to serializing the response they
+//! received in full.
+//!
+
+use std::marker::PhantomData;
+use std::{error, fmt, io};
+
+use serde::de::{self, DeserializeSeed, IgnoredAny, MapAccess, SeqAccess, Visitor};
+use serde_json;
+
+use crate::client::{check_envelope, Members, ResponseChecks};
+use crate::error::RpcError;
+
+/// Error that can occur while streaming a response.
+#[derive(Debug)]
+pub enum Error {
+    /// The transport didn't hand over a response body.
+    NoBody,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match *self {
+            Error::NoBody => f.write_str("the transport didn't hand over a response body"),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            Error::NoBody => None,
+        }
+    }
+}
+
+impl From<Error> for crate::Error {
+    fn from(e: Error) -> crate::Error {
+        crate::Error::Transport(Box::new(e))
+    }
+}
+
+/// A reader keeping a copy of the bytes read through it, for transport wrappers which need the
+/// response held by a body they hand over.
+pub(crate) struct Tee<'a> {
+    inner: &'a mut dyn io::BufRead,
+    copy: Vec<u8>,
+}
+
+impl<'a> Tee<'a> {
+    /// Wraps `inner`, starting with an empty copy.
+    pub(crate) fn new(inner: &'a mut dyn io::BufRead) -> Tee<'a> {
+        Tee {
+            inner,
+            copy: vec![],
+        }
+    }
+
+    /// Returns the bytes read so far.
+    pub(crate) fn into_copy(self) -> Vec<u8> {
+        self.copy
+    }
+}
+
+impl<'a> io::Read for Tee<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.copy.extend_from_slice(&buf[..n]);
+        Ok(n)
+    }
+}
+
+impl<'a> io::BufRead for Tee<'a> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.inner.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        // The consumed bytes are still buffered, so this doesn't read from the inner reader.
+        if let Ok(buf) = self.inner.fill_buf() {
+            self.copy.extend_from_slice(&buf[..amt.min(buf.len())]);
+        }
+        self.inner.consume(amt)
+    }
+}
+
+/// A seed calling a closure on each element of an array, see
+/// [`Client::for_each`](crate::Client::for_each).
+pub struct ForEach<T, F> {
+    f: F,
+    _element: PhantomData<fn(T)>,
+}
+
+impl<T, F: FnMut(T)> ForEach<T, F> {
+    /// Creates a seed calling `f` on each element of an array.
+    pub fn new(f: F) -> ForEach<T, F> {
+        ForEach {
+            f,
+            _element: PhantomData,
+        }
+    }
+}
+
+impl<'de, T: de::Deserialize<'de>, F: FnMut(T)> DeserializeSeed<'de> for ForEach<T, F> {
+    type Value = ();
+
+    fn deserialize<D: de::Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        deserializer.deserialize_seq(self)
+    }
+}
+
+impl<'de, T: de::Deserialize<'de>, F: FnMut(T)> Visitor<'de> for ForEach<T, F> {
+    type Value = ();
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("an array")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(mut self, mut seq: A) -> Result<(), A::Error> {
+        while let Some(element) = seq.next_element()? {
+            (self.f)(element);
+        }
+        Ok(())
+    }
+}
+
+/// The `result` member of a response, still holding the seed if the result was `null` or absent.
+enum Slot<S, V> {
+    Null(S),
+    Value(V),
+}
+
+/// Deserializes a `result` member with the inner seed unless it is `null`.
+struct ResultSeed<S>(S);
+
+impl<'de, S: DeserializeSeed<'de>> DeserializeSeed<'de> for ResultSeed<S> {
+    type Value = Slot<S, S::Value>;
+
+    fn deserialize<D: de::Deserializer<'de>>(
+        self,
+        deserializer: D,
+    ) -> Result<Self::Value, D::Error> {
+        deserializer.deserialize_option(self)
+    }
+}
+
+impl<'de, S: DeserializeSeed<'de>> Visitor<'de> for ResultSeed<S> {
+    type Value = Slot<S, S::Value>;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a result")
+    }
+
+    fn visit_none<E: de::Error>(self) -> Result<Self::Value, E> {
+        Ok(Slot::Null(self.0))
+    }
+
+    fn visit_unit<E: de::Error>(self) -> Result<Self::Value, E> {
+        Ok(Slot::Null(self.0))
+    }
+
+    fn visit_some<D: de::Deserializer<'de>>(
+        self,
+        deserializer: D,
+    ) -> Result<Self::Value, D::Error> {
+        self.0.deserialize(deserializer).map(Slot::Value)
+    }
+}
+
+/// A response whose result was deserialized with a seed.
+pub(crate) struct Envelope<S, V> {
+    result: Slot<S, V>,
+    /// Which of the `result` and `error` members were present, even if `null`.
+    members: Members,
+    error: Option<RpcError>,
+    id: serde_json::Value,
+    jsonrpc: Option<String>,
+}
+
+impl<S, V> Envelope<S, V> {
+    /// Checks the response against the ID of the request, and returns its result.
+    pub(crate) fn into_result<'de>(
+        self,
+        checks: ResponseChecks,
+        id: Option<&serde_json::Value>,
+    ) -> Result<V, crate::Error>
+    where
+        S: DeserializeSeed<'de, Value = V>,
+    {
+        let has_result = match self.result {
+            Slot::Null(_) => false,
+            Slot::Value(_) => true,
+        };
+        check_envelope(
+            checks,
+            id,
+            self.jsonrpc.as_deref(),
+            &self.id,
+            has_result,
+            self.error.as_ref(),
+            Some(self.members),
+        )?;
+        if let Some(e) = self.error {
+            return Err(crate::Error::Rpc(e));
+        }
+        match self.result {
+            Slot::Value(value) => Ok(value),
+            Slot::Null(seed) => Ok(seed.deserialize(serde_json::Value::Null)?),
+        }
+    }
+}
+
+/// Deserializes a response, passing its `result` member to the inner seed.
+pub(crate) struct ResponseSeed<S>(pub(crate) S);
+
+impl<'de, S: DeserializeSeed<'de>> DeserializeSeed<'de> for ResponseSeed<S> {
+    type Value = Envelope<S, S::Value>;
+
+    fn deserialize<D: de::Deserializer<'de>>(
+        self,
+        deserializer: D,
+    ) -> Result<Self::Value, D::Error> {
+        deserializer.deserialize_map(self)
+    }
+}
+
+impl<'de, S: DeserializeSeed<'de>> Visitor<'de> for ResponseSeed<S> {
+    type Value = Envelope<S, S::Value>;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a JSON-RPC response object")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+        let mut seed = Some(self.0);
+        let mut result = None;
+        let mut error = None;
+        let mut has_error = false;
+        let mut id = None;
+        let mut jsonrpc = None;
+        let mut has_jsonrpc = false;
+        while let Some(key) = map.next_key::<String>()? {
+            match key.as_str() {
+                "result" => match seed.take() {
+                    Some(seed) => result = Some(map.next_value_seed(ResultSeed(seed))?),
+                    None => return Err(de::Error::duplicate_field("result")),
+                },
+                "error" if has_error => return Err(de::Error::duplicate_field("error")),
+                "error" => {
+                    has_error = true;
+                    error = map.next_value()?;
+                }
+                "id" if id.is_some() => return Err(de::Error::duplicate_field("id")),
+                "id" => id = Some(map.next_value()?),
+                "jsonrpc" if has_jsonrpc => return Err(de::Error::duplicate_field("jsonrpc")),
+                "jsonrpc" => {
+                    has_jsonrpc = true;
+                    jsonrpc = map.next_value()?;
+                }
+                _ => {
+                    map.next_value::<IgnoredAny>()?;
+                }
+            }
+        }
+
+        let members = Members {
+            result: result.is_some(),
+            error: has_error,
+        };
+        let result = match (result, seed) {
+            (Some(result), _) => result,
+            (None, Some(seed)) => Slot::Null(seed),
+            (None, None) => unreachable!("the seed is only taken when reading the result"),
+        };
+        Ok(Envelope {
+            result,
+            members,
+            error,
+            id: id.unwrap_or_default(),
+            jsonrpc,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse<'de, S: DeserializeSeed<'de>>(
+        body: &'de str,
+        seed: S,
+    ) -> Result<S::Value, crate::Error> {
+        let mut de = serde_json::Deserializer::from_str(body);
+        let envelope = ResponseSeed(seed).deserialize(&mut de)?;
+        envelope.into_result(ResponseChecks::default(), Some(&serde_json::Value::from(1)))
+    }
+
+    #[test]
+    fn envelope() {
+        let seed = PhantomData::<Vec<u32>>;
+        assert_eq!(parse(r#"{"id":1,"result":[1,2],"error":null}"#, seed).unwrap(), [1, 2]);
+        let seed = PhantomData::<Option<u32>>;
+        assert_eq!(parse(r#"{"result":null,"error":null,"id":1}"#, seed).unwrap(), None);
+        assert_eq!(parse(r#"{"error":null,"id":1,"extra":{}}"#, seed).unwrap(), None);
+
+        let body = r#"{"result":null,"error":{"code":-8,"message":"nope"},"id":1}"#;
+        match parse(body, PhantomData::<u32>) {
+            Err(crate::Error::Rpc(ref e)) if e.code == -8 => {}
+            r => panic!("expected RPC error, got {:?}", r),
+        }
+        match parse(r#"{"result":1,"error":null,"id":2}"#, PhantomData::<u32>) {
+            Err(crate::Error::NonceMismatch) => {}
+            r => panic!("expected nonce mismatch, got {:?}", r),
+        }
+        for body in &[
+            r#"{"result":1,"result":2,"id":1}"#,
+            r#"{"result":1,"error":null,"error":null,"id":1}"#,
+            r#"{"result":1,"id":2,"id":1}"#,
+            r#"{"jsonrpc":"2.0","jsonrpc":"2.0","result":1,"id":1}"#,
+        ] {
+            match parse(body, PhantomData::<u32>) {
+                Err(crate::Error::Json(ref e)) if e.to_string().starts_with("duplicate field") => {}
+                r => panic!("expected duplicate field error, got {:?}", r),
+            }
+        }
+    }
+
+    #[test]
+    fn for_each() {
+        let mut sum = 0;
+        parse(r#"{"result":[1,2,3],"id":1}"#, ForEach::new(|n: u32| sum += n)).unwrap();
+        assert_eq!(sum, 6);
+    }
+}
@@ -0,0 +1,227 @@
+//! # Tracing
+//!
+//! Diagnostic events emitted by the bundled transports while they carry out a call: connecting,
+//! writing the request, and reading back the status line, headers and body of the response.
+//!
+//! The events are logged at debug level through the `log` crate, with the module of the
+//! transport as target, e.g. `jsonrpc::simple_http`. Every event is logged along with the method
+//! name and ID of the calls being made.
+//!
+//! Credentials are never logged: neither the `Authorization` header nor the parameters of the
+//! calls are, and the value of sensitive response headers is redacted.
+//!
+
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::Rc;
+
+use crate::Request;
+
+/// Response headers whose value is never logged.
+#[cfg(feature = "simple_http")]
+const REDACTED_HEADERS: &[&str] = &["authorization", "proxy-authorization", "cookie", "set-cookie"];
+
+/// A call being made, as logged along with the events of the transport.
+struct Call {
+    method: String,
+    id: Option<serde_json::Value>,
+}
+
+impl fmt::Display for Call {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.id {
+            Some(ref id) => write!(f, "{}#{}", self.method, id),
+            None => f.write_str(&self.method),
+        }
+    }
+}
+
+thread_local! {
+    /// The calls being made by the current thread.
+    static CURRENT: RefCell<Rc<[Call]>> = RefCell::new(Rc::new([]));
+}
+
+/// The calls being made by the current thread, displayed as a comma-separated list.
+pub(crate) struct Calls(Rc<[Call]>);
+
+impl fmt::Display for Calls {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, call) in self.0.iter().enumerate() {
+            let sep = if i == 0 {
+                ""
+            } else {
+                ", "
+            };
+            write!(f, "{}{}", sep, call)?;
+        }
+        Ok(())
+    }
+}
+
+/// Returns the calls being made by the current thread.
+pub(crate) fn current() -> Calls {
+    Calls(CURRENT.with(|c| c.borrow().clone()))
+}
+
+/// The calls a transport is making, to be entered on the thread doing the work.
+pub(crate) struct Span {
+    calls: Vec<Call>,
+}
+
+impl Span {
+    /// Creates a span for the given requests.
+    pub(crate) fn new(reqs: &[Request]) -> Span {
+        let calls = reqs
+            .iter()
+            .map(|req| Call {
+                method: req.method.to_owned(),
+                id: req.id.clone(),
+            })
+            .collect();
+        Span {
+            calls,
+        }
+    }
+
+    /// Makes the calls of the span those of the current thread, until the guard is dropped.
+    pub(crate) fn enter(self) -> Entered {
+        let calls = Rc::from(self.calls);
+        let previous = CURRENT.with(|c| c.replace(calls));
+        Entered {
+            previous: Some(previous),
+        }
+    }
+}
+
+/// Guard restoring the calls of the current thread when dropped, see [`Span::enter`].
+pub(crate) struct Entered {
+    previous: Option<Rc<[Call]>>,
+}
+
+impl Drop for Entered {
+    fn drop(&mut self) {
+        if let Some(previous) = self.previous.take() {
+            CURRENT.with(|c| *c.borrow_mut() = previous);
+        }
+    }
+}
+
+/// The outcome of parsing the body of a response, displayed as the logged event.
+pub(crate) struct BodyParse<'a, T, E>(pub(crate) &'a Result<T, E>);
+
+impl<'a, T, E: fmt::Display> fmt::Display for BodyParse<'a, T, E> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self.0 {
+            Ok(_) => f.write_str("parsed body"),
+            Err(ref e) => write!(f, "failed to parse body: {}", e),
+        }
+    }
+}
+
+/// A raw header line of an HTTP response, displayed with its value redacted if it may carry
+/// credentials.
+#[cfg(feature = "simple_http")]
+pub(crate) struct Header<'a>(pub(crate) &'a str);
+
+#[cfg(feature = "simple_http")]
+impl<'a> fmt::Display for Header<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let line = self.0.trim_end();
+        let (name, value) = match line.find(':') {
+            Some(colon) => (&line[..colon], line[colon + 1..].trim()),
+            None => (line, ""),
+        };
+        if REDACTED_HEADERS.iter().any(|h| h.eq_ignore_ascii_case(name)) {
+            write!(f, "header {}: [redacted]", name)
+        } else {
+            write!(f, "header {}: {}", name, value)
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::sync::Once;
+
+    use super::*;
+    use crate::arg;
+
+    thread_local! {
+        static RECORDS: RefCell<Vec<String>> = RefCell::new(vec![]);
+    }
+
+    /// Collects the records of this crate logged by each thread.
+    struct Recorder;
+
+    impl log::Log for Recorder {
+        fn enabled(&self, metadata: &log::Metadata) -> bool {
+            metadata.target().starts_with("jsonrpc::")
+        }
+
+        fn log(&self, record: &log::Record) {
+            if self.enabled(record.metadata()) {
+                let record = format!("{}: {}", record.target(), record.args());
+                RECORDS.with(|r| r.borrow_mut().push(record));
+            }
+        }
+
+        fn flush(&self) {}
+    }
+
+    static RECORDER: Recorder = Recorder;
+
+    /// Installs a logger collecting the records of each thread, and returns those logged by the
+    /// current thread since the last call.
+    pub(crate) fn take_records() -> Vec<String> {
+        static INIT: Once = Once::new();
+        INIT.call_once(|| {
+            log::set_logger(&RECORDER).unwrap();
+            log::set_max_level(log::LevelFilter::Debug);
+        });
+        RECORDS.with(|r| r.replace(vec![]))
+    }
+
+    #[cfg(feature = "simple_http")]
+    #[test]
+    fn header_redaction() {
+        assert_eq!(Header("Content-Length: 12\r\n").to_string(), "header Content-Length: 12");
+        assert_eq!(
+            Header("Set-Cookie: session=secret\r\n").to_string(),
+            "header Set-Cookie: [redacted]"
+        );
+    }
+
+    #[test]
+    fn span_calls() {
+        take_records();
+        let params = [arg(1)];
+        let reqs = [
+            Request {
+                method: "getblockhash",
+                params: (&params).into(),
+                id: Some(serde_json::Value::from(7)),
+                jsonrpc: Some("2.0"),
+            },
+            Request {
+                method: "ping",
+                params: Default::default(),
+                id: None,
+                jsonrpc: Some("2.0"),
+            },
+        ];
+
+        {
+            let _span = Span::new(&reqs).enter();
+            trace_event!("wrote request of {} bytes", 10);
+        }
+        trace_event!("status {}", 200);
+
+        assert_eq!(
+            take_records(),
+            [
+                "jsonrpc::trace::tests: [getblockhash#7, ping] wrote request of 10 bytes",
+                "jsonrpc::trace::tests: [] status 200",
+            ]
+        );
+    }
+}
@@ -13,7 +13,10 @@
 //
 
 use std::borrow::Cow;
-use std::hash::{Hash, Hasher};
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hash, Hasher};
+use std::sync::atomic;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use serde_json::Value;
 
@@ -69,6 +72,21 @@ impl<'a> Hash for HashableValue<'a> {
     }
 }
 
+/// Returns a random number, without pulling in a dependency for it.
+///
+/// The randomness comes from the random keys of the standard library's [`RandomState`], so
+/// this is not suitable for anything security related.
+pub fn random_u64() -> u64 {
+    static COUNTER: atomic::AtomicUsize = atomic::AtomicUsize::new(0);
+
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_usize(COUNTER.fetch_add(1, atomic::Ordering::Relaxed));
+    if let Ok(now) = SystemTime::now().duration_since(UNIX_EPOCH) {
+        hasher.write_u128(now.as_nanos());
+    }
+    hasher.finish()
+}
+
 #[cfg(test)]
 mod tests {
     use std::borrow::Cow;